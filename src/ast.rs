@@ -5,12 +5,16 @@
 //! efficient incremental output (pull parsing), and consequently doesn't provide it's own
 //! AST types.
 
+mod abbreviations;
+mod autolinks;
+mod math;
 mod unflatten;
+mod wikilinks;
 
 
-use std::{collections::HashSet, mem};
+use std::{collections::HashSet, mem, ops::Range};
 
-use pulldown_cmark::{self as md, Event, HeadingLevel, LinkType, Tag};
+use pulldown_cmark::{self as md, Alignment, Event, HeadingLevel, LinkType, Tag};
 
 use self::unflatten::UnflattenedEvent;
 
@@ -25,8 +29,27 @@ use self::unflatten::UnflattenedEvent;
 #[derive(Debug, Clone, PartialEq)]
 pub enum Block {
     Paragraph(Text),
-    List(Vec<ListItem>),
-    Heading(HeadingLevel, Text),
+    /// *CommonMark Spec:* [lists](https://spec.commonmark.org/0.30/#lists)
+    List {
+        items: Vec<ListItem>,
+        /// Whether this is an ordered (`1.`) list, instead of a bullet (`-`) list.
+        ordered: bool,
+        /// The number of the first item of an ordered list. This is `1` for bullet
+        /// lists.
+        start: u64,
+        /// Whether this is a tight list, whose items are not separated by blank lines.
+        ///
+        /// *CommonMark Spec:* [loose and tight lists](https://spec.commonmark.org/0.30/#loose)
+        tight: bool,
+    },
+    Heading {
+        level: HeadingLevel,
+        text: Text,
+        /// The `#id` from a `{#id .class}` heading attribute block.
+        id: Option<String>,
+        /// The `.class` names from a `{#id .class}` heading attribute block.
+        classes: Vec<String>,
+    },
     /// An indented or fenced code block.
     ///
     /// *CommonMark Spec:* [indented code blocks](https://spec.commonmark.org/0.30/#indented-code-blocks),
@@ -37,15 +60,41 @@ pub enum Block {
         /// *CommonMark Spec:* [info string](https://spec.commonmark.org/0.30/#info-string)
         info_string: Option<String>,
         code: String,
+        /// The lines of the Markdown source that this code block was parsed from,
+        /// including any fence lines.
+        lines: Option<SourceLines>,
     },
     /// *CommonMark Spec:* [block quotes](https://spec.commonmark.org/0.30/#block-quotes)
     BlockQuote(Vec<Block>),
     Table {
+        /// The alignment of each column, from the delimiter row, e.g. `|:--|--:|`.
+        alignments: Vec<Alignment>,
         headers: Vec<Text>,
         rows: Vec<Vec<Text>>,
     },
     /// *CommonMark Spec: [thematic breaks](https://spec.commonmark.org/0.30/#thematic-breaks)
     Rule,
+    /// A `$$...$$` TeX math paragraph.
+    DisplayMath(String),
+    /// The raw markup of an HTML block, like a standalone `<div>...</div>`.
+    ///
+    /// *CommonMark Spec:* [HTML blocks](https://spec.commonmark.org/0.30/#html-blocks)
+    Html(String),
+    /// A `[^label]: ...` footnote definition.
+    ///
+    /// Footnote definitions are moved to the end of the document.
+    FootnoteDefinition {
+        label: String,
+        blocks: Vec<Block>,
+    },
+}
+
+/// A range of lines in the Markdown source. Lines are numbered starting from 1.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SourceLines {
+    pub first: usize,
+    /// The last line in this range (inclusive).
+    pub last: usize,
 }
 
 /// A sequence of [`TextSpan`]s that make up a block of text.
@@ -60,7 +109,31 @@ pub struct ListItem(pub Vec<Block>);
 pub enum TextSpan {
     Text(String, HashSet<TextStyle>),
     Code(String),
-    Link { label: Text, destination: String },
+    Link {
+        label: Text,
+        destination: String,
+        /// The title of a link like `[text](url "title")`.
+        title: Option<String>,
+    },
+    /// An occurrence of an abbreviation defined with `*[TERM]: expansion`.
+    Abbreviation {
+        label: Text,
+        expansion: String,
+    },
+    /// A named location that links can target, from inline HTML `<a name="...">`.
+    Anchor(String),
+    /// The `[ ]` or `[x]` checkbox at the start of a task list item, and whether it
+    /// is checked.
+    TaskListMarker(bool),
+    /// A `[^label]` reference to a [`Block::FootnoteDefinition`].
+    FootnoteReference(String),
+    /// An `![alt](destination)` image. `destination` is a URL or a local file path.
+    Image {
+        alt: String,
+        destination: String,
+    },
+    /// `$...$` TeX math within text.
+    InlineMath(String),
     SoftBreak,
     HardBreak,
 }
@@ -70,60 +143,157 @@ pub enum TextStyle {
     Emphasis,
     Strong,
     Strikethrough,
+    /// Text color, e.g. from inline HTML `<span style="color: red">`.
+    Color(Color),
+    /// Highlighted text, from inline HTML `<mark>`.
+    Highlight,
+    /// Subscript text, from inline HTML `<sub>`.
+    Subscript,
+    /// Superscript text, from inline HTML `<sup>`.
+    Superscript,
+    /// Keyboard input, from inline HTML `<kbd>`.
+    Keyboard,
+}
+
+/// An RGB color, with 8 bits per channel.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Color {
+    pub red: u8,
+    pub green: u8,
+    pub blue: u8,
 }
 
 //======================================
 // AST Builder
 //======================================
 
-pub(crate) fn parse_markdown_to_ast(input: &str) -> Vec<Block> {
-    /* For Markdown parsing debugging.
-    {
-        let mut options = md::Options::empty();
-        options.insert(md::Options::ENABLE_STRIKETHROUGH);
-        let parser = md::Parser::new_ext(input, options);
+/// Options controlling how Markdown input is parsed into [`Block`]s.
+#[derive(Debug, Clone, Default)]
+pub struct Options {
+    /// If set, inline HTML tags whose meaning depends on their attributes, like
+    /// `<span style="color: red">`, are interpreted instead of being skipped.
+    pub html_attributes: bool,
+    /// If set, `[[Target]]` and `[[Target|display text]]` wikilinks are converted into
+    /// links to the slugified target name.
+    pub wikilinks: bool,
+    /// If set, `$...$` and `$$...$$` are converted into TeX math.
+    pub math: bool,
+    /// If set, the unflattened Markdown events are printed to stderr, for debugging.
+    pub verbose: bool,
+}
+
+/// A warning about Markdown input that could not be fully converted.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Diagnostic {
+    /// The 1-based line number of the input the warning applies to.
+    pub line: usize,
+    /// The 1-based column, in characters, of the input the warning applies to.
+    pub column: usize,
+    pub kind: DiagnosticKind,
+    pub message: String,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum DiagnosticKind {
+    /// HTML that was skipped or only partially interpreted.
+    InlineHtml,
+    /// A link whose label or destination could not be converted.
+    Link,
+    /// A code block that is likely to be malformed.
+    CodeBlock,
+}
 
-        let events: Vec<_> = parser.into_iter().collect();
+/// State used while converting [`UnflattenedEvent`]s into [`Block`]s.
+struct Context<'a> {
+    opts: &'a Options,
+    input: &'a str,
+    diagnostics: Vec<Diagnostic>,
+}
+
+/// Parse `input` into a sequence of [`Block`]s, using the default [`Options`].
+pub fn parse_markdown_to_ast(input: &str) -> Vec<Block> {
+    parse_markdown_to_ast_with_options(input, &Options::default())
+}
+
+/// Parse `input` into a sequence of [`Block`]s, printing any warnings to stderr.
+pub fn parse_markdown_to_ast_with_options(input: &str, opts: &Options) -> Vec<Block> {
+    let (blocks, diagnostics) = parse_markdown_with_diagnostics(input, opts);
+
+    for diagnostic in diagnostics {
+        eprintln!("warning: {}", diagnostic.message);
+    }
+
+    blocks
+}
 
-        println!("==== All events =====\n");
+/// Parse `input` into a sequence of [`Block`]s, returning any warnings instead of
+/// printing them.
+pub fn parse_markdown_with_diagnostics(
+    input: &str,
+    opts: &Options,
+) -> (Vec<Block>, Vec<Diagnostic>) {
+    let (input, abbreviations) = abbreviations::extract_definitions(input);
+
+    let events = unflatten::parse_markdown_to_unflattened_events(&input);
+
+    if opts.verbose {
+        eprintln!("===== Unflattened events =====\n");
         for event in &events {
-            println!("{event:?}");
+            eprintln!("{event:#?}");
         }
-        println!("\n=====================\n");
+        eprintln!("\n===== End unflattened events =====\n");
+    }
 
-        println!("==== Unflattened events =====\n");
-        for event in unflatten::parse_markdown_to_unflattened_events(input) {
-            println!("{event:#?}")
-        }
-        println!("=============================\n");
+    let mut cx = Context {
+        opts,
+        input: &input,
+        diagnostics: Vec::new(),
+    };
+
+    let mut blocks = events_to_blocks(&mut cx, events);
+
+    // Collect the footnotes at the end of the document.
+    let mut footnotes = Vec::new();
+    take_footnote_definitions(&mut blocks, &mut footnotes);
+    blocks.extend(footnotes);
+
+    // Recognize math first, so that text inside math isn't converted into
+    // abbreviations or links.
+    if opts.math {
+        math::apply(&mut blocks);
     }
-    */
 
-    let events = unflatten::parse_markdown_to_unflattened_events(input);
+    abbreviations::apply(&mut blocks, &abbreviations);
+
+    autolinks::apply(&mut blocks);
 
-    events_to_blocks(events)
+    if opts.wikilinks {
+        wikilinks::apply(&mut blocks);
+    }
+
+    (blocks, cx.diagnostics)
 }
 
 /// Returns `true` if `event` contains content that can be added "inline" with text
 /// content.
 ///
 /// `event`'s that cannot be added inline will start a new [`Block`].
-fn is_inline(event: &UnflattenedEvent) -> bool {
+fn is_inline(input: &str, event: &UnflattenedEvent) -> bool {
     match event {
-        UnflattenedEvent::Event(event) => match event {
+        UnflattenedEvent::Event(event, range) => match event {
             Event::Start(_) | Event::End(_) => unreachable!(),
             Event::Text(_) => true,
             Event::Code(_) => true,
             Event::SoftBreak => true,
             Event::HardBreak => true,
-            // TODO: HTML could cause break to next block?
-            Event::Html(_) => false,
+            Event::Html(html) => !is_html_block(input, html, range),
             Event::Rule => false,
-            Event::TaskListMarker(_) => false,
+            Event::TaskListMarker(_) => true,
             Event::FootnoteReference(_) => true,
         },
-        UnflattenedEvent::Nested { tag, events: _ } => match tag {
+        UnflattenedEvent::Nested { tag, .. } => match tag {
             Tag::Emphasis | Tag::Strong | Tag::Strikethrough => true,
+            Tag::Link(_, _, _) | Tag::Image(_, _, _) => true,
             Tag::Heading(_, _, _) => false,
             Tag::Paragraph => false,
             Tag::List(_) => false,
@@ -131,114 +301,144 @@ fn is_inline(event: &UnflattenedEvent) -> bool {
             Tag::CodeBlock(_) => false,
             Tag::BlockQuote => false,
             Tag::Table(_) => false,
+            Tag::FootnoteDefinition(_) => false,
             Tag::TableHead | Tag::TableRow => unreachable!(),
             _ => todo!("handle tag: {tag:?}"),
         },
     }
 }
 
-fn events_to_blocks(events: Vec<UnflattenedEvent>) -> Vec<Block> {
+fn events_to_blocks(cx: &mut Context, events: Vec<UnflattenedEvent>) -> Vec<Block> {
     let mut complete: Vec<Block> = vec![];
 
     let mut text_spans: Vec<TextSpan> = vec![];
 
+    // Consecutive inline events are converted together, so that e.g. inline HTML
+    // start and end tags in a tight list item apply to the text between them.
+    let mut inline_events: Vec<UnflattenedEvent> = vec![];
+
+    // The offset of the end of the last line of HTML block content, so that
+    // consecutive lines can be combined into a single `Block::Html`.
+    let mut html_block_end: Option<usize> = None;
+
     for event in events {
         // println!("event: {:?}", event);
 
-        if !is_inline(&event) {
-            if !text_spans.is_empty() {
-                complete.push(Block::Paragraph(Text(mem::replace(
-                    &mut text_spans,
-                    vec![],
-                ))));
-            }
+        if is_inline(cx.input, &event) {
+            inline_events.push(event);
+            continue;
+        }
+
+        if !inline_events.is_empty() {
+            text_spans.extend(unwrap_text(
+                cx,
+                mem::take(&mut inline_events),
+                HashSet::new(),
+            ));
+        }
+
+        // In a loose task list, the task list marker comes before the paragraph
+        // containing the item text. Keep them together.
+        let is_marker_before_paragraph =
+            matches!(text_spans.as_slice(), [TextSpan::TaskListMarker(_)])
+                && matches!(
+                    event,
+                    UnflattenedEvent::Nested {
+                        tag: Tag::Paragraph,
+                        ..
+                    }
+                );
+
+        if !is_marker_before_paragraph && !text_spans.is_empty() {
+            complete.push(Block::Paragraph(Text(mem::replace(
+                &mut text_spans,
+                vec![],
+            ))));
         }
 
         match event {
-            UnflattenedEvent::Event(event) => match event {
+            UnflattenedEvent::Event(event, range) => match event {
                 Event::Start(_) | Event::End(_) => {
                     panic!("illegal Event::{{Start, End}} in UnflattenedEvent::Event")
                 },
-                Event::Text(text) => {
-                    text_spans.push(TextSpan::Text(text.to_string(), HashSet::new()))
+                Event::Html(html) => {
+                    // Each line of an HTML block is a separate event.
+                    match complete.last_mut() {
+                        Some(Block::Html(block))
+                            if html_block_end.is_some_and(|end| {
+                                is_line_prefix(&cx.input[end..range.start])
+                            }) =>
+                        {
+                            block.push_str(&html)
+                        },
+                        _ => complete.push(Block::Html(html.to_string())),
+                    }
+                    html_block_end = Some(range.end);
                 },
-                Event::Code(code) => text_spans.push(TextSpan::Code(code.to_string())),
-                Event::SoftBreak => text_spans.push(TextSpan::SoftBreak),
-                Event::HardBreak => text_spans.push(TextSpan::HardBreak),
-                Event::Html(_) => eprintln!("warning: skipping inline HTML"),
                 Event::Rule => complete.push(Block::Rule),
-                Event::TaskListMarker(_) | Event::FootnoteReference(_) => {
-                    todo!("handle: {event:?}")
-                },
+                _ => unreachable!("inline event: {event:?}"),
             },
-            UnflattenedEvent::Nested { tag, events } => {
+            UnflattenedEvent::Nested { tag, events, range } => {
                 match tag {
-                    //
-                    // Inline content
-                    //
-                    Tag::Emphasis => {
-                        text_spans.extend(unwrap_text(
-                            events,
-                            HashSet::from_iter([TextStyle::Emphasis]),
-                        ));
-                    },
-                    Tag::Strong => {
-                        text_spans.extend(unwrap_text(
-                            events,
-                            HashSet::from_iter([TextStyle::Strong]),
-                        ));
-                    },
-                    Tag::Strikethrough => {
-                        text_spans.extend(unwrap_text(
-                            events,
-                            HashSet::from_iter([TextStyle::Strikethrough]),
-                        ));
-                    },
-
-                    Tag::Link(link_type, destination, label) => {
-                        let text = unwrap_text(events, HashSet::new());
-                        text_spans.push(TextSpan::from_link(
-                            link_type,
-                            text,
-                            destination.to_string(),
-                            label.to_string(),
-                        ))
-                    },
-
                     //
                     // Block content
                     //
-
-                    // TODO: Use the two Heading fields that are ignored here?
-                    Tag::Heading(level, _, _) => {
-                        complete.push(Block::Heading(
+                    Tag::Heading(level, id, classes) => {
+                        complete.push(Block::Heading {
                             level,
-                            unwrap_text(events, Default::default()),
-                        ));
+                            text: unwrap_text(cx, events, Default::default()),
+                            id: id.map(str::to_owned),
+                            classes: classes.into_iter().map(str::to_owned).collect(),
+                        });
                     },
                     Tag::Paragraph => {
-                        text_spans.extend(unwrap_text(events, Default::default()))
+                        text_spans.extend(unwrap_text(cx, events, Default::default()))
                     },
-                    Tag::List(_) => {
+                    Tag::List(start) => {
                         let mut items: Vec<ListItem> = Vec::new();
+                        let mut tight = true;
 
                         for event in events {
                             if let UnflattenedEvent::Nested {
                                 tag: Tag::Item,
                                 events: item_events,
+                                ..
                             } = event
                             {
-                                let item_blocks = events_to_blocks(item_events);
+                                // pulldown-cmark only wraps the text of the items of a
+                                // loose list in paragraphs.
+                                tight &= !item_events.iter().any(|event| {
+                                    matches!(
+                                        event,
+                                        UnflattenedEvent::Nested {
+                                            tag: Tag::Paragraph,
+                                            ..
+                                        }
+                                    )
+                                });
+
+                                let item_blocks = events_to_blocks(cx, item_events);
                                 items.push(ListItem(item_blocks));
                             } else {
                                 todo!("handle list element: {event:?}");
                             }
                         }
 
-                        complete.push(Block::List(items));
+                        complete.push(Block::List {
+                            items,
+                            ordered: start.is_some(),
+                            start: start.unwrap_or(1),
+                            tight,
+                        });
                     },
                     Tag::Item => {
-                        complete.extend(events_to_blocks(events));
+                        complete.extend(events_to_blocks(cx, events));
+                    },
+                    Tag::FootnoteDefinition(label) => {
+                        complete.push(Block::FootnoteDefinition {
+                            label: label.to_string(),
+                            blocks: events_to_blocks(cx, events),
+                        });
                     },
                     Tag::CodeBlock(kind) => {
                         let fence_label = match kind {
@@ -246,24 +446,35 @@ fn events_to_blocks(events: Vec<UnflattenedEvent>) -> Vec<Block> {
                             md::CodeBlockKind::Fenced(label) => Some(label.to_string()),
                         };
 
-                        let text_spans = unwrap_text(events, Default::default());
+                        if fence_label.is_some()
+                            && !is_closed_code_fence(&cx.input[range.clone()])
+                        {
+                            cx.warn(
+                                range.clone(),
+                                DiagnosticKind::CodeBlock,
+                                "code fence is never closed, so the rest of its \
+                                 container is treated as code",
+                            );
+                        }
+
+                        let text_spans = unwrap_text(cx, events, Default::default());
                         let code_text = text_to_string(&text_spans);
 
                         complete.push(Block::CodeBlock {
                             info_string: fence_label,
                             code: code_text,
+                            lines: Some(SourceLines::of_range(cx.input, range)),
                         })
                     },
                     Tag::BlockQuote => {
-                        let blocks = events_to_blocks(events);
+                        let blocks = events_to_blocks(cx, events);
                         complete.push(Block::BlockQuote(blocks))
                     },
-                    // TODO: Support table column alignments.
-                    Tag::Table(_alignments) => {
+                    Tag::Table(alignments) => {
                         let mut events = events.into_iter();
                         let header_events = match events.next().unwrap() {
-                            UnflattenedEvent::Event(_) => panic!(),
-                            UnflattenedEvent::Nested { tag, events } => {
+                            UnflattenedEvent::Event(..) => panic!(),
+                            UnflattenedEvent::Nested { tag, events, .. } => {
                                 assert!(tag == Tag::TableHead);
                                 events
                             },
@@ -273,6 +484,7 @@ fn events_to_blocks(events: Vec<UnflattenedEvent>) -> Vec<Block> {
 
                         for table_cell in header_events {
                             let table_cell_text = unwrap_text(
+                                cx,
                                 unwrap_table_cell(table_cell),
                                 HashSet::new(),
                             );
@@ -284,8 +496,8 @@ fn events_to_blocks(events: Vec<UnflattenedEvent>) -> Vec<Block> {
 
                         for row_events in events {
                             let row_events = match row_events {
-                                UnflattenedEvent::Event(_) => panic!(),
-                                UnflattenedEvent::Nested { tag, events } => {
+                                UnflattenedEvent::Event(..) => panic!(),
+                                UnflattenedEvent::Nested { tag, events, .. } => {
                                     assert!(tag == Tag::TableRow);
                                     events
                                 },
@@ -295,6 +507,7 @@ fn events_to_blocks(events: Vec<UnflattenedEvent>) -> Vec<Block> {
 
                             for table_cell in row_events {
                                 let table_cell_text = unwrap_text(
+                                    cx,
                                     unwrap_table_cell(table_cell),
                                     HashSet::new(),
                                 );
@@ -305,7 +518,11 @@ fn events_to_blocks(events: Vec<UnflattenedEvent>) -> Vec<Block> {
                             rows.push(row);
                         }
 
-                        complete.push(Block::Table { headers, rows })
+                        complete.push(Block::Table {
+                            alignments,
+                            headers,
+                            rows,
+                        })
                     },
                     _ => todo!("handle: {tag:?}"),
                 }
@@ -313,6 +530,10 @@ fn events_to_blocks(events: Vec<UnflattenedEvent>) -> Vec<Block> {
         }
     }
 
+    if !inline_events.is_empty() {
+        text_spans.extend(unwrap_text(cx, inline_events, HashSet::new()));
+    }
+
     if !text_spans.is_empty() {
         complete.push(Block::paragraph(text_spans));
     }
@@ -320,38 +541,119 @@ fn events_to_blocks(events: Vec<UnflattenedEvent>) -> Vec<Block> {
     complete
 }
 
-fn unwrap_text(events: Vec<UnflattenedEvent>, mut styles: HashSet<TextStyle>) -> Text {
+fn unwrap_text(
+    cx: &mut Context,
+    events: Vec<UnflattenedEvent>,
+    mut styles: HashSet<TextStyle>,
+) -> Text {
     let mut text_spans: Vec<TextSpan> = vec![];
 
+    // The styles applied by each currently open inline HTML `<span>` tag, or `None` if
+    // the tag did not add a new style.
+    let mut open_spans: Vec<Option<TextStyle>> = vec![];
+
+    // The number of currently open `<a name="...">` tags.
+    let mut open_anchors: usize = 0;
+
+    // The style of each currently open style tag like `<mark>` or `<sub>`, and
+    // whether the tag added that style.
+    let mut open_style_tags: Vec<(TextStyle, bool)> = vec![];
+
     for event in events {
         match event {
-            UnflattenedEvent::Event(event) => match event {
+            UnflattenedEvent::Event(event, range) => match event {
                 Event::Start(_) | Event::End(_) => unreachable!(),
+                // pulldown-cmark can emit empty text, e.g. for trailing whitespace in a
+                // table cell. Skip it, so it doesn't become an empty string box.
+                Event::Text(text) if text.is_empty() => (),
                 Event::Text(text) => {
                     text_spans.push(TextSpan::Text(text.to_string(), styles.clone()))
                 },
                 Event::Code(code) => text_spans.push(TextSpan::Code(code.to_string())),
                 Event::SoftBreak => text_spans.push(TextSpan::SoftBreak),
                 Event::HardBreak => text_spans.push(TextSpan::HardBreak),
-                Event::Html(_) => eprintln!("warning: skipping inline HTML"),
-                Event::TaskListMarker(_) | Event::Rule | Event::FootnoteReference(_) => {
-                    todo!("handle: {event:?}")
+                Event::Html(html) if cx.opts.html_attributes && is_span_start(&html) => {
+                    let style = span_style(cx, range, &html)
+                        .filter(|style| styles.insert(style.clone()));
+                    open_spans.push(style);
+                },
+                Event::Html(html) if cx.opts.html_attributes && is_span_end(&html) => {
+                    match open_spans.pop() {
+                        Some(Some(style)) => {
+                            styles.remove(&style);
+                        },
+                        Some(None) => (),
+                        None => cx.warn(
+                            range,
+                            DiagnosticKind::InlineHtml,
+                            format!("skipping unmatched inline HTML {html:?}"),
+                        ),
+                    }
                 },
+                Event::Html(html)
+                    if cx.opts.html_attributes && anchor_name(&html).is_some() =>
+                {
+                    let name = anchor_name(&html).unwrap();
+                    text_spans.push(TextSpan::Anchor(name.to_owned()));
+                    open_anchors += 1;
+                },
+                Event::Html(html)
+                    if open_anchors > 0 && html.trim().eq_ignore_ascii_case("</a>") =>
+                {
+                    open_anchors -= 1;
+                },
+                Event::Html(html) if is_line_break(&html) => {
+                    text_spans.push(TextSpan::HardBreak)
+                },
+                Event::Html(html) if style_tag(&html).is_some() => {
+                    let (style, is_end) = style_tag(&html).unwrap();
+
+                    if is_end {
+                        let open = open_style_tags
+                            .iter()
+                            .rposition(|(open_style, _)| *open_style == style);
+
+                        match open.map(|index| open_style_tags.remove(index)) {
+                            Some((style, true)) => {
+                                styles.remove(&style);
+                            },
+                            Some((_, false)) => (),
+                            None => cx.warn(
+                                range,
+                                DiagnosticKind::InlineHtml,
+                                format!("skipping unmatched inline HTML {html:?}"),
+                            ),
+                        }
+                    } else {
+                        let added = styles.insert(style.clone());
+                        open_style_tags.push((style, added));
+                    }
+                },
+                Event::Html(_) => {
+                    cx.warn(range, DiagnosticKind::InlineHtml, "skipping inline HTML")
+                },
+                Event::TaskListMarker(checked) => {
+                    text_spans.push(TextSpan::TaskListMarker(checked))
+                },
+                Event::FootnoteReference(label) => {
+                    text_spans.push(TextSpan::FootnoteReference(label.to_string()))
+                },
+                Event::Rule => todo!("handle: {event:?}"),
             },
-            UnflattenedEvent::Nested { tag, events } => match tag {
+            UnflattenedEvent::Nested { tag, events, range } => match tag {
                 Tag::Emphasis => {
                     styles.insert(TextStyle::Emphasis);
-                    text_spans.extend(unwrap_text(events, styles.clone()));
+                    text_spans.extend(unwrap_text(cx, events, styles.clone()));
                     styles.remove(&TextStyle::Emphasis);
                 },
                 Tag::Strong => {
                     styles.insert(TextStyle::Strong);
-                    text_spans.extend(unwrap_text(events, styles.clone()));
+                    text_spans.extend(unwrap_text(cx, events, styles.clone()));
                     styles.remove(&TextStyle::Strong);
                 },
                 Tag::Strikethrough => {
                     styles.insert(TextStyle::Strikethrough);
-                    text_spans.extend(unwrap_text(events, styles.clone()));
+                    text_spans.extend(unwrap_text(cx, events, styles.clone()));
                     styles.remove(&TextStyle::Strikethrough);
                 },
                 Tag::Paragraph => {
@@ -364,17 +666,26 @@ fn unwrap_text(events: Vec<UnflattenedEvent>, mut styles: HashSet<TextStyle>) ->
                         text_spans.push(TextSpan::HardBreak);
                         text_spans.push(TextSpan::HardBreak);
                     }
-                    text_spans.extend(unwrap_text(events, styles.clone()))
+                    text_spans.extend(unwrap_text(cx, events, styles.clone()))
                 },
-                Tag::Link(link_type, destination, label) => {
-                    let text = unwrap_text(events, HashSet::new());
-                    text_spans.push(TextSpan::from_link(
+                Tag::Link(link_type, destination, title) => {
+                    let text = unwrap_text(cx, events, HashSet::new());
+                    text_spans.extend(TextSpan::from_link(
+                        cx,
+                        range,
                         link_type,
                         text,
                         destination.to_string(),
-                        label.to_string(),
+                        title.to_string(),
                     ))
                 },
+                Tag::Image(_, destination, _) => {
+                    let alt = unwrap_text(cx, events, HashSet::new());
+                    text_spans.push(TextSpan::Image {
+                        alt: alt_text(&alt),
+                        destination: destination.to_string(),
+                    })
+                },
                 _ => todo!("handle {tag:?}"),
             },
         }
@@ -383,27 +694,374 @@ fn unwrap_text(events: Vec<UnflattenedEvent>, mut styles: HashSet<TextStyle>) ->
     Text(text_spans)
 }
 
+//--------------------------------------
+// Inline HTML
+//--------------------------------------
+
+/// Returns `true` if `html`, at the byte offsets `range` of `input`, is a line of an
+/// HTML block, instead of inline HTML in a tight list item.
+///
+/// Each line of an HTML block, including its newline, is a separate event.
+fn is_html_block(input: &str, html: &str, range: &Range<usize>) -> bool {
+    if html.ends_with('\n') {
+        return true;
+    }
+
+    // The last line of an HTML block at the end of the input has no newline, but
+    // starts its line, after any container markers like `>` or `*`.
+    let line_start = input[..range.start]
+        .rfind('\n')
+        .map_or(0, |offset| offset + 1);
+
+    range.end == input.len()
+        && input[line_start..range.start]
+            .chars()
+            .all(|c| c.is_whitespace() || ">*+-.)".contains(c) || c.is_ascii_digit())
+}
+
+/// Returns `true` if `text` is only indentation and block quote markers, which
+/// separate the lines of a block nested in a list item or block quote.
+fn is_line_prefix(text: &str) -> bool {
+    text.chars().all(|c| matches!(c, ' ' | '\t' | '>'))
+}
+
+/// Returns `true` if `html` is a `<br>`, `<br/>`, or `<br />` tag.
+fn is_line_break(html: &str) -> bool {
+    let html = html.trim().to_ascii_lowercase();
+
+    matches!(html.as_str(), "<br>" | "<br/>" | "<br />")
+}
+
+/// Returns the style applied by an attribute-free inline HTML tag like `<mark>` or
+/// `</sub>`, and whether `html` is the end tag.
+fn style_tag(html: &str) -> Option<(TextStyle, bool)> {
+    let html = html.trim().to_ascii_lowercase();
+
+    let name = html.strip_prefix('<')?.strip_suffix('>')?;
+    let (name, is_end) = match name.strip_prefix('/') {
+        Some(name) => (name, true),
+        None => (name, false),
+    };
+
+    let style = match name {
+        "b" | "strong" => TextStyle::Strong,
+        "i" | "em" => TextStyle::Emphasis,
+        "mark" => TextStyle::Highlight,
+        "sub" => TextStyle::Subscript,
+        "sup" => TextStyle::Superscript,
+        "kbd" => TextStyle::Keyboard,
+        _ => return None,
+    };
+
+    Some((style, is_end))
+}
+
+fn is_span_start(html: &str) -> bool {
+    let html = html.trim().to_ascii_lowercase();
+
+    html.strip_prefix("<span")
+        .map(|rest| rest.starts_with(|c: char| c == '>' || c.is_whitespace()))
+        .unwrap_or(false)
+}
+
+fn is_span_end(html: &str) -> bool {
+    html.trim().eq_ignore_ascii_case("</span>")
+}
+
+/// Returns the anchor name of an HTML `<a name="...">` start tag.
+fn anchor_name(html: &str) -> Option<&str> {
+    let html = html.trim();
+
+    let is_anchor = html
+        .get(..3)
+        .map(|start| {
+            start[..2].eq_ignore_ascii_case("<a")
+                && start[2..].starts_with(char::is_whitespace)
+        })
+        .unwrap_or(false);
+
+    if !is_anchor {
+        return None;
+    }
+
+    html_attribute(html, "name").filter(|name| !name.is_empty())
+}
+
+/// Returns the text style specified by the `style` attribute of an HTML `<span ...>`
+/// start tag.
+///
+/// Only the CSS `color` property is supported. Other properties are skipped with a
+/// warning.
+fn span_style(cx: &mut Context, range: Range<usize>, html: &str) -> Option<TextStyle> {
+    let style = match html_attribute(html, "style") {
+        Some(style) => style,
+        None => {
+            cx.warn(
+                range,
+                DiagnosticKind::InlineHtml,
+                format!("skipping inline HTML: {html:?}"),
+            );
+            return None;
+        },
+    };
+
+    let mut text_style = None;
+
+    for declaration in style.split(';').filter(|decl| !decl.trim().is_empty()) {
+        let (property, value) = declaration.split_once(':').unwrap_or((declaration, ""));
+
+        let color = if property.trim().eq_ignore_ascii_case("color") {
+            Color::from_css(value.trim())
+        } else {
+            None
+        };
+
+        match color {
+            Some(color) => text_style = Some(TextStyle::Color(color)),
+            None => cx.warn(
+                range.clone(),
+                DiagnosticKind::InlineHtml,
+                format!(
+                    "skipping unsupported inline HTML style: {:?}",
+                    declaration.trim()
+                ),
+            ),
+        }
+    }
+
+    text_style
+}
+
+/// Returns the value of the attribute `name` in the HTML start tag `html`.
+fn html_attribute<'a>(html: &'a str, name: &str) -> Option<&'a str> {
+    let lowercase = html.to_ascii_lowercase();
+
+    let mut search_from = 0;
+
+    while let Some(offset) = lowercase[search_from..].find(name) {
+        let start = search_from + offset;
+        search_from = start + name.len();
+
+        // Require that this is a complete attribute name, and not a suffix of a
+        // longer name.
+        let preceded_by_space = lowercase[..start]
+            .chars()
+            .last()
+            .map(char::is_whitespace)
+            .unwrap_or(false);
+        if !preceded_by_space {
+            continue;
+        }
+
+        let rest = html[search_from..].trim_start();
+        let rest = match rest.strip_prefix('=') {
+            Some(rest) => rest.trim_start(),
+            None => continue,
+        };
+
+        let quote = match rest.chars().next() {
+            Some(quote @ ('"' | '\'')) => quote,
+            _ => continue,
+        };
+
+        let value = &rest[1..];
+        return value.find(quote).map(|end| &value[..end]);
+    }
+
+    None
+}
+
 fn unwrap_table_cell(event: UnflattenedEvent) -> Vec<UnflattenedEvent> {
     match event {
-        UnflattenedEvent::Event(_) => panic!(),
-        UnflattenedEvent::Nested { tag, events } => {
+        UnflattenedEvent::Event(..) => panic!(),
+        UnflattenedEvent::Nested { tag, events, .. } => {
             assert_eq!(tag, Tag::TableCell, "expected to get Tag::TableCell");
             events
         },
     }
 }
 
+/// Call `f` on each [`Text`] in `blocks` that contains Markdown prose, including
+/// the text of nested blocks.
+fn visit_text_mut(blocks: &mut [Block], f: &mut dyn FnMut(&mut Text)) {
+    for block in blocks {
+        match block {
+            Block::Paragraph(text) | Block::Heading { text, .. } => f(text),
+            Block::List { items, .. } => {
+                for ListItem(blocks) in items {
+                    visit_text_mut(blocks, f);
+                }
+            },
+            Block::BlockQuote(blocks) => visit_text_mut(blocks, f),
+            Block::Table { headers, rows, .. } => {
+                for text in headers.iter_mut().chain(rows.iter_mut().flatten()) {
+                    f(text);
+                }
+            },
+            Block::FootnoteDefinition { blocks, .. } => visit_text_mut(blocks, f),
+            Block::CodeBlock { .. }
+            | Block::DisplayMath(_)
+            | Block::Html(_)
+            | Block::Rule => (),
+        }
+    }
+}
+
+/// Move the [`Block::FootnoteDefinition`]s in `blocks` into `footnotes`, including
+/// definitions nested inside list items and block quotes.
+fn take_footnote_definitions(blocks: &mut Vec<Block>, footnotes: &mut Vec<Block>) {
+    for mut block in mem::take(blocks) {
+        match &mut block {
+            Block::FootnoteDefinition { blocks: inner, .. } => {
+                let mut nested = Vec::new();
+                take_footnote_definitions(inner, &mut nested);
+                footnotes.push(block);
+                footnotes.extend(nested);
+                continue;
+            },
+            Block::List { items, .. } => {
+                for ListItem(blocks) in items {
+                    take_footnote_definitions(blocks, footnotes);
+                }
+            },
+            Block::BlockQuote(blocks) => take_footnote_definitions(blocks, footnotes),
+            _ => (),
+        }
+
+        blocks.push(block);
+    }
+}
+
+/// Returns `true` if the source text of a fenced code block ends with a closing fence.
+///
+/// In block quotes and lists, `source` may include container markers like `>`.
+fn is_closed_code_fence(source: &str) -> bool {
+    let mut lines = source.lines().map(|line| {
+        line.trim_start_matches(|c: char| c == '>' || c.is_whitespace())
+            .trim_end()
+    });
+
+    let opening = lines.next().unwrap_or("");
+
+    let fence_char = match opening.chars().next() {
+        Some(c @ ('`' | '~')) => c,
+        _ => return true,
+    };
+    let fence_len = opening.chars().take_while(|c| *c == fence_char).count();
+
+    match lines.rfind(|line| !line.is_empty()) {
+        Some(closing) => {
+            closing.chars().all(|c| c == fence_char)
+                && closing.chars().count() >= fence_len
+        },
+        None => false,
+    }
+}
+
+/// Convert `name` into a lowercase, `-` separated identifier.
+///
+/// E.g. `Getting Started!` becomes `getting-started`.
+pub(crate) fn slugify(name: &str) -> String {
+    name.split(|c: char| !c.is_alphanumeric())
+        .filter(|word| !word.is_empty())
+        .map(str::to_lowercase)
+        .collect::<Vec<_>>()
+        .join("-")
+}
+
+/// Give each heading in `blocks` that doesn't have a `{#id}` attribute an id derived
+/// from its text, e.g. `getting-started`, that is unique among the headings.
+pub(crate) fn assign_heading_ids(blocks: &mut [Block]) {
+    let mut ids: HashSet<String> = blocks
+        .iter()
+        .filter_map(|block| match block {
+            Block::Heading { id, .. } => id.clone(),
+            _ => None,
+        })
+        .collect();
+
+    for block in blocks {
+        if let Block::Heading {
+            text,
+            id: id @ None,
+            ..
+        } = block
+        {
+            let slug = slugify(&text_to_string(text));
+            let unique = (0..)
+                .map(|n| match n {
+                    0 => slug.clone(),
+                    n => format!("{slug}-{n}"),
+                })
+                .find(|id| !ids.contains(id))
+                .unwrap();
+
+            ids.insert(unique.clone());
+            *id = Some(unique);
+        }
+    }
+}
+
+/// Combine adjacent [`TextSpan::Text`]s that have the same styles.
+fn merge_adjacent_text(spans: Vec<TextSpan>) -> Vec<TextSpan> {
+    let mut merged: Vec<TextSpan> = Vec::new();
+
+    for span in spans {
+        match (merged.last_mut(), span) {
+            (Some(TextSpan::Text(text, styles)), TextSpan::Text(next, next_styles))
+                if *styles == next_styles =>
+            {
+                text.push_str(&next)
+            },
+            (_, span) => merged.push(span),
+        }
+    }
+
+    merged
+}
+
+/// Returns the plain text of the description of an image.
+///
+/// *CommonMark Spec:* [images](https://spec.commonmark.org/0.30/#images)
+fn alt_text(Text(text_spans): &Text) -> String {
+    text_spans
+        .iter()
+        .map(|span| match span {
+            TextSpan::Text(text, _)
+            | TextSpan::Code(text)
+            | TextSpan::InlineMath(text) => text.clone(),
+            TextSpan::Link { label, .. } | TextSpan::Abbreviation { label, .. } => {
+                alt_text(label)
+            },
+            TextSpan::Image { alt, .. } => alt.clone(),
+            TextSpan::SoftBreak | TextSpan::HardBreak => " ".to_owned(),
+            TextSpan::Anchor(_)
+            | TextSpan::TaskListMarker(_)
+            | TextSpan::FootnoteReference(_) => String::new(),
+        })
+        .collect()
+}
+
+/// Returns the text of `text`, without any formatting.
+///
+/// Text styles are ignored, code spans are included verbatim, and links and
+/// abbreviations are replaced by their label.
 fn text_to_string(Text(text_spans): &Text) -> String {
     let mut string = String::new();
 
     for span in text_spans {
         match span {
-            TextSpan::Text(text, styles) => {
-                if !styles.is_empty() {
-                    todo!("support text style(s) `{styles:?}` in string {text:?}");
-                }
-
-                string.push_str(&text);
+            TextSpan::Text(text, _) => {
+                string.push_str(text);
+            },
+            TextSpan::Code(code) | TextSpan::InlineMath(code) => {
+                string.push_str(code);
+            },
+            TextSpan::Link { label, .. } | TextSpan::Abbreviation { label, .. } => {
+                string.push_str(&text_to_string(label));
+            },
+            TextSpan::Image { alt, .. } => {
+                string.push_str(alt);
             },
             TextSpan::SoftBreak => {
                 string.push_str(" ");
@@ -411,7 +1069,9 @@ fn text_to_string(Text(text_spans): &Text) -> String {
             TextSpan::HardBreak => {
                 string.push_str("\n");
             },
-            _ => todo!("handle span: {span:?}"),
+            TextSpan::Anchor(_)
+            | TextSpan::TaskListMarker(_)
+            | TextSpan::FootnoteReference(_) => (),
         }
     }
 
@@ -423,37 +1083,154 @@ fn text_to_string(Text(text_spans): &Text) -> String {
 //======================================
 
 impl TextSpan {
+    /// Returns the spans for a link with the given `text`.
+    ///
+    /// A link with an empty destination, like `[text]()`, is converted to just its
+    /// text.
     fn from_link(
+        cx: &mut Context,
+        range: Range<usize>,
         link_type: LinkType,
         text: Text,
         destination: String,
-        label: String,
-    ) -> TextSpan {
-        if !label.is_empty() {
-            eprintln!("warning: link label is ignored: {label:?}");
-        }
-
-        match link_type {
-            LinkType::Inline => (),
-            LinkType::Reference => (),
-            LinkType::Shortcut => (),
-            LinkType::Collapsed => (),
-            LinkType::Autolink => (),
-            LinkType::Email => (),
+        title: String,
+    ) -> Vec<TextSpan> {
+        let destination = match link_type {
+            LinkType::Inline => destination,
+            LinkType::Reference => destination,
+            LinkType::Shortcut => destination,
+            LinkType::Collapsed => destination,
+            LinkType::Autolink => destination,
+            // E.g. `<name@example.com>`. The label is the bare address, and the
+            // destination needs a `mailto:` scheme to be openable.
+            LinkType::Email => {
+                if destination.starts_with("mailto:") {
+                    destination
+                } else {
+                    format!("mailto:{destination}")
+                }
+            },
             // Unknown
             LinkType::ReferenceUnknown
             | LinkType::CollapsedUnknown
             | LinkType::ShortcutUnknown => {
-                eprintln!(
-                    "warning: unable to resolve location of link with text '{}'",
-                    text_to_string(&text)
-                )
+                cx.warn(
+                    range,
+                    DiagnosticKind::Link,
+                    format!(
+                        "unable to resolve location of link with text '{}'",
+                        text_to_string(&text)
+                    ),
+                );
+                destination
             },
+        };
+
+        if destination.is_empty() {
+            let Text(spans) = text;
+            return spans;
         }
 
-        TextSpan::Link {
+        vec![TextSpan::Link {
             label: text,
             destination,
+            title: Some(title).filter(|title| !title.is_empty()),
+        }]
+    }
+}
+
+impl Context<'_> {
+    /// Record a warning about the input source text at the byte offsets `range`.
+    fn warn(
+        &mut self,
+        range: Range<usize>,
+        kind: DiagnosticKind,
+        message: impl Into<String>,
+    ) {
+        let before = &self.input[..range.start];
+
+        let line = before.matches('\n').count() + 1;
+        let column = match before.rfind('\n') {
+            Some(newline) => before[newline + 1..].chars().count() + 1,
+            None => before.chars().count() + 1,
+        };
+
+        self.diagnostics.push(Diagnostic {
+            line,
+            column,
+            kind,
+            message: message.into(),
+        })
+    }
+}
+
+impl DiagnosticKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            DiagnosticKind::InlineHtml => "inline-html",
+            DiagnosticKind::Link => "link",
+            DiagnosticKind::CodeBlock => "code-block",
+        }
+    }
+}
+
+impl Color {
+    /// Parse a CSS color: a `#rgb` or `#rrggbb` hex color, or one of the common CSS
+    /// named colors.
+    pub(crate) fn from_css(value: &str) -> Option<Color> {
+        let value = value.to_ascii_lowercase();
+
+        if let Some(hex) = value.strip_prefix('#') {
+            let channel = |digits: &str| u8::from_str_radix(digits, 16).ok();
+
+            return match hex.len() {
+                3 => {
+                    let mut digits = hex.chars().map(|c| c.to_string().repeat(2));
+                    Some(Color {
+                        red: channel(&digits.next()?)?,
+                        green: channel(&digits.next()?)?,
+                        blue: channel(&digits.next()?)?,
+                    })
+                },
+                6 => Some(Color {
+                    red: channel(hex.get(0..2)?)?,
+                    green: channel(hex.get(2..4)?)?,
+                    blue: channel(hex.get(4..6)?)?,
+                }),
+                _ => None,
+            };
+        }
+
+        let (red, green, blue) = match value.as_str() {
+            "black" => (0, 0, 0),
+            "white" => (255, 255, 255),
+            "gray" | "grey" => (128, 128, 128),
+            "red" => (255, 0, 0),
+            "green" => (0, 128, 0),
+            "blue" => (0, 0, 255),
+            "yellow" => (255, 255, 0),
+            "orange" => (255, 165, 0),
+            "purple" => (128, 0, 128),
+            "pink" => (255, 192, 203),
+            "brown" => (165, 42, 42),
+            "cyan" => (0, 255, 255),
+            "magenta" => (255, 0, 255),
+            _ => return None,
+        };
+
+        Some(Color { red, green, blue })
+    }
+}
+
+impl SourceLines {
+    /// Returns the lines spanned by the byte offsets `range` of `input`.
+    fn of_range(input: &str, range: Range<usize>) -> SourceLines {
+        let line_of = |offset: usize| input[..offset].matches('\n').count() + 1;
+
+        SourceLines {
+            first: line_of(range.start),
+            // `range` is exclusive, and typically includes the trailing newline.
+            last: line_of(range.end.max(range.start + 1) - 1),
         }
     }
 }
@@ -462,6 +1239,26 @@ impl Block {
     fn paragraph(text: Vec<TextSpan>) -> Block {
         Block::Paragraph(Text(text))
     }
+
+    #[cfg(test)]
+    fn bullet_list(items: Vec<ListItem>) -> Block {
+        Block::List {
+            items,
+            ordered: false,
+            start: 1,
+            tight: true,
+        }
+    }
+
+    #[cfg(test)]
+    fn loose_bullet_list(items: Vec<ListItem>) -> Block {
+        Block::List {
+            items,
+            ordered: false,
+            start: 1,
+            tight: false,
+        }
+    }
 }
 
 impl IntoIterator for Text {
@@ -524,38 +1321,748 @@ fn tests() {
 
     assert_eq!(
         parse_markdown_to_ast("* hello"),
-        vec![Block::List(vec![ListItem(vec![Block::paragraph(vec![
-            TextSpan::Text("hello".into(), HashSet::new())
-        ])])])]
+        vec![Block::bullet_list(vec![ListItem(vec![Block::paragraph(
+            vec![TextSpan::Text("hello".into(), HashSet::new())]
+        )])])]
     );
 
     // List items with styled text
 
     assert_eq!(
         parse_markdown_to_ast("* *hello*"),
-        vec![Block::List(vec![ListItem(vec![Block::paragraph(vec![
-            TextSpan::Text(
+        vec![Block::bullet_list(vec![ListItem(vec![Block::paragraph(
+            vec![TextSpan::Text(
                 "hello".into(),
                 HashSet::from_iter(vec![TextStyle::Emphasis])
-            )
-        ])])])]
+            )]
+        )])])]
     );
 
     assert_eq!(
         parse_markdown_to_ast("* **hello**"),
-        vec![Block::List(vec![ListItem(vec![Block::paragraph(vec![
-            TextSpan::Text("hello".into(), HashSet::from_iter(vec![TextStyle::Strong]))
-        ])])])]
+        vec![Block::bullet_list(vec![ListItem(vec![Block::paragraph(
+            vec![TextSpan::Text(
+                "hello".into(),
+                HashSet::from_iter(vec![TextStyle::Strong])
+            )]
+        )])])]
     );
 
     assert_eq!(
         parse_markdown_to_ast("* ~~hello~~"),
-        vec![Block::List(vec![ListItem(vec![Block::paragraph(vec![
-            TextSpan::Text(
+        vec![Block::bullet_list(vec![ListItem(vec![Block::paragraph(
+            vec![TextSpan::Text(
                 "hello".into(),
                 HashSet::from_iter(vec![TextStyle::Strikethrough])
-            )
-        ])])])]
+            )]
+        )])])]
+    );
+}
+
+#[test]
+fn test_styled_text_across_soft_break() {
+    use pretty_assertions::assert_eq;
+
+    let emphasis = |text: &str| {
+        TextSpan::Text(text.into(), HashSet::from_iter([TextStyle::Emphasis]))
+    };
+
+    assert_eq!(
+        parse_markdown_to_ast("*emphasis\nthat wraps*"),
+        vec![Block::paragraph(vec![
+            emphasis("emphasis"),
+            TextSpan::SoftBreak,
+            emphasis("that wraps"),
+        ])]
+    );
+
+    assert_eq!(
+        parse_markdown_to_ast("* **strong\n  text**"),
+        vec![Block::bullet_list(vec![ListItem(vec![Block::paragraph(
+            vec![
+                TextSpan::Text("strong".into(), HashSet::from_iter([TextStyle::Strong])),
+                TextSpan::SoftBreak,
+                TextSpan::Text("text".into(), HashSet::from_iter([TextStyle::Strong])),
+            ]
+        )])])]
+    );
+}
+
+#[test]
+fn test_inline_html_span_color() {
+    use pretty_assertions::assert_eq;
+
+    let opts = Options {
+        html_attributes: true,
+        ..Options::default()
+    };
+
+    assert_eq!(
+        parse_markdown_to_ast_with_options(
+            r#"a <span style="color:red">red</span> <span style="color: #00f">blue</span>"#,
+            &opts
+        ),
+        vec![Block::paragraph(vec![
+            TextSpan::Text("a ".into(), HashSet::new()),
+            TextSpan::Text(
+                "red".into(),
+                HashSet::from_iter([TextStyle::Color(Color {
+                    red: 255,
+                    green: 0,
+                    blue: 0
+                })])
+            ),
+            TextSpan::Text(" ".into(), HashSet::new()),
+            TextSpan::Text(
+                "blue".into(),
+                HashSet::from_iter([TextStyle::Color(Color {
+                    red: 0,
+                    green: 0,
+                    blue: 255
+                })])
+            ),
+        ])]
+    );
+
+    // Unsupported styles are skipped.
+    assert_eq!(
+        parse_markdown_to_ast_with_options(
+            r#"<span style="font-size: 2em">big</span>"#,
+            &opts
+        ),
+        vec![Block::paragraph(vec![TextSpan::Text(
+            "big".into(),
+            HashSet::new()
+        )])]
+    );
+}
+
+#[test]
+fn test_setext_and_atx_headings() {
+    use indoc::indoc;
+    use pretty_assertions::assert_eq;
+
+    // Setext headings have the same levels as the equivalent ATX headings.
+    assert_eq!(
+        parse_markdown_to_ast(indoc!(
+            "
+            Title
+            =====
+
+            Section
+            -------
+            "
+        )),
+        parse_markdown_to_ast(indoc!(
+            "
+            # Title
+
+            ## Section
+            "
+        ))
+    );
+}
+
+#[test]
+fn test_setext_headings() {
+    use pretty_assertions::assert_eq;
+
+    let text = |text: &str| TextSpan::Text(text.into(), HashSet::new());
+    let heading = |level, spans| Block::Heading {
+        level,
+        text: Text(spans),
+        id: None,
+        classes: vec![],
+    };
+
+    assert_eq!(
+        parse_markdown_to_ast("Title\n=====\n\nSubtitle\n-----\n"),
+        vec![
+            heading(HeadingLevel::H1, vec![text("Title")]),
+            heading(HeadingLevel::H2, vec![text("Subtitle")]),
+        ]
+    );
+
+    // A setext heading can span several lines.
+    assert_eq!(
+        parse_markdown_to_ast("A long\ntitle\n---\n"),
+        vec![heading(
+            HeadingLevel::H2,
+            vec![text("A long"), TextSpan::SoftBreak, text("title")]
+        )]
+    );
+
+    // Dashes after a blank line are a thematic break, not a heading underline.
+    assert_eq!(
+        parse_markdown_to_ast("Text\n\n-----\n"),
+        vec![Block::paragraph(vec![text("Text")]), Block::Rule]
+    );
+
+    // A table delimiter row is not a heading underline.
+    assert!(matches!(
+        parse_markdown_to_ast("a | b\n--- | ---\n").as_slice(),
+        [Block::Table { .. }]
+    ));
+}
+
+#[test]
+fn test_html_blocks() {
+    use pretty_assertions::assert_eq;
+
+    let text = |text: &str| TextSpan::Text(text.into(), HashSet::new());
+
+    assert_eq!(
+        parse_markdown_to_ast("<div>\n  <b>hello</b>\n</div>\n\nText"),
+        vec![
+            Block::Html("<div>\n  <b>hello</b>\n</div>\n".into()),
+            Block::paragraph(vec![text("Text")]),
+        ]
+    );
+
+    // HTML blocks separated by a blank line are separate blocks.
+    assert_eq!(
+        parse_markdown_to_ast("<table></table>\n\n<!-- comment -->"),
+        vec![
+            Block::Html("<table></table>\n".into()),
+            Block::Html("<!-- comment -->".into()),
+        ]
+    );
+
+    assert_eq!(
+        parse_markdown_to_ast("* <div>\n  item\n  </div>"),
+        vec![Block::bullet_list(vec![ListItem(vec![Block::Html(
+            "<div>\nitem\n</div>".into()
+        )])])]
+    );
+
+    // Inline HTML in a tight list item is not an HTML block.
+    let (blocks, diagnostics) =
+        parse_markdown_with_diagnostics("* a <b>x</b>", &Options::default());
+    assert_eq!(
+        blocks,
+        vec![Block::bullet_list(vec![ListItem(vec![Block::paragraph(
+            vec![
+                text("a "),
+                TextSpan::Text("x".into(), HashSet::from_iter([TextStyle::Strong])),
+            ]
+        )])])]
+    );
+    assert_eq!(diagnostics, vec![]);
+}
+
+#[test]
+fn test_heading_attributes() {
+    use pretty_assertions::assert_eq;
+
+    assert_eq!(
+        parse_markdown_to_ast("## Setup {#setup .optional .linux}"),
+        vec![Block::Heading {
+            level: HeadingLevel::H2,
+            text: Text(vec![TextSpan::Text("Setup".into(), HashSet::new())]),
+            id: Some("setup".into()),
+            classes: vec!["optional".into(), "linux".into()],
+        }]
+    );
+}
+
+#[test]
+fn test_wikilinks() {
+    use pretty_assertions::assert_eq;
+
+    let opts = Options {
+        wikilinks: true,
+        ..Options::default()
+    };
+
+    let link = |label: &str, destination: &str| TextSpan::Link {
+        label: Text(vec![TextSpan::Text(label.into(), HashSet::new())]),
+        destination: destination.into(),
+        title: None,
+    };
+
+    assert_eq!(
+        parse_markdown_to_ast_with_options("See [[Foo Bar]] or [[Baz|the baz]].", &opts),
+        vec![Block::paragraph(vec![
+            TextSpan::Text("See ".into(), HashSet::new()),
+            link("Foo Bar", "#foo-bar"),
+            TextSpan::Text(" or ".into(), HashSet::new()),
+            link("the baz", "#baz"),
+            TextSpan::Text(".".into(), HashSet::new()),
+        ])]
+    );
+
+    // Headings are given ids that wikilinks can link to.
+    assert_eq!(
+        parse_markdown_to_ast_with_options("# Foo Bar\n\n# Foo Bar\n\n# Baz {#b}", &opts)
+            .into_iter()
+            .map(|block| match block {
+                Block::Heading { id, .. } => id,
+                block => panic!("expected heading, got {block:?}"),
+            })
+            .collect::<Vec<_>>(),
+        vec![
+            Some("foo-bar".to_owned()),
+            Some("foo-bar-1".to_owned()),
+            Some("b".to_owned())
+        ]
+    );
+
+    // Wikilinks are only converted if enabled.
+    assert_eq!(
+        parse_markdown_to_ast("[[Foo Bar]]"),
+        vec![Block::paragraph(vec![TextSpan::Text(
+            "[[Foo Bar]]".into(),
+            HashSet::new()
+        )])]
+    );
+}
+
+#[test]
+fn test_math() {
+    use pretty_assertions::assert_eq;
+
+    let opts = Options {
+        math: true,
+        ..Options::default()
+    };
+
+    let text = |text: &str| TextSpan::Text(text.into(), HashSet::new());
+
+    assert_eq!(
+        parse_markdown_to_ast_with_options("Sum: $a+b$, or $$x_1 y_2$$.", &opts),
+        vec![Block::paragraph(vec![
+            text("Sum: "),
+            TextSpan::InlineMath("a+b".into()),
+            text(", or "),
+            TextSpan::InlineMath("x_1 y_2".into()),
+            text("."),
+        ])]
+    );
+
+    assert_eq!(
+        parse_markdown_to_ast_with_options("$$\n\\frac{a}{b}\n$$\n\n> $$c$$", &opts),
+        vec![
+            Block::DisplayMath("\\frac{a}{b}".into()),
+            Block::BlockQuote(vec![Block::DisplayMath("c".into())]),
+        ]
+    );
+
+    // Dollar signs that don't delimit math are left as-is.
+    assert_eq!(
+        parse_markdown_to_ast_with_options("From $5 to $10, or $ 2 $.", &opts),
+        vec![Block::paragraph(vec![text("From $5 to $10, or $ 2 $.")])]
+    );
+
+    // Math is only converted if enabled.
+    assert_eq!(
+        parse_markdown_to_ast("$a+b$"),
+        vec![Block::paragraph(vec![text("$a+b$")])]
+    );
+}
+
+#[test]
+fn test_unclosed_code_fence() {
+    use pretty_assertions::assert_eq;
+
+    let (blocks, diagnostics) = parse_markdown_with_diagnostics(
+        "# Example\n\n```python\nprint(1)\n\nMore text.\n",
+        &Options::default(),
+    );
+
+    assert_eq!(blocks.len(), 2);
+    assert_eq!(
+        diagnostics
+            .iter()
+            .map(|diagnostic| (diagnostic.line, diagnostic.kind))
+            .collect::<Vec<_>>(),
+        vec![(3, DiagnosticKind::CodeBlock)]
+    );
+
+    // Closed fences don't produce warnings.
+    for input in [
+        "```\ncode\n```",
+        "~~~~\ncode\n~~~~~\n",
+        "> ```\n> code\n> ```",
+    ] {
+        let (_, diagnostics) =
+            parse_markdown_with_diagnostics(input, &Options::default());
+        assert_eq!(diagnostics, vec![], "input: {input:?}");
+    }
+}
+
+#[test]
+fn test_autolinks() {
+    use pretty_assertions::assert_eq;
+
+    let text = |text: &str| TextSpan::Text(text.into(), HashSet::new());
+    let link = |label: &str, destination: &str| TextSpan::Link {
+        label: Text(vec![text(label)]),
+        destination: destination.into(),
+        title: None,
+    };
+
+    assert_eq!(
+        parse_markdown_to_ast("Visit www.x.com or email a@b.com."),
+        vec![Block::paragraph(vec![
+            text("Visit "),
+            link("www.x.com", "http://www.x.com"),
+            text(" or email "),
+            link("a@b.com", "mailto:a@b.com"),
+            text("."),
+        ])]
+    );
+
+    // Trailing punctuation is not part of the URL, unless it's a balanced parenthesis.
+    assert_eq!(
+        parse_markdown_to_ast("(See https://example.com/a_(b)!)"),
+        vec![Block::paragraph(vec![
+            text("(See "),
+            link("https://example.com/a_(b)", "https://example.com/a_(b)"),
+            text("!)"),
+        ])]
+    );
+
+    // Not links.
+    for input in ["wwwx.com", "www.", "a@b", "@b.com"] {
+        assert_eq!(
+            parse_markdown_to_ast(input),
+            vec![Block::paragraph(vec![text(input)])]
+        );
+    }
+}
+
+#[test]
+fn test_inline_html_mark() {
+    use pretty_assertions::assert_eq;
+
+    // `<mark>` doesn't need --html-attributes, and composes with other styles.
+    assert_eq!(
+        parse_markdown_to_ast("a <mark>b **c**</mark> d"),
+        vec![Block::paragraph(vec![
+            TextSpan::Text("a ".into(), HashSet::new()),
+            TextSpan::Text("b ".into(), HashSet::from_iter([TextStyle::Highlight])),
+            TextSpan::Text(
+                "c".into(),
+                HashSet::from_iter([TextStyle::Highlight, TextStyle::Strong])
+            ),
+            TextSpan::Text(" d".into(), HashSet::new()),
+        ])]
+    );
+
+    let (_, diagnostics) =
+        parse_markdown_with_diagnostics("a</mark>", &Options::default());
+    assert_eq!(diagnostics.len(), 1);
+}
+
+#[test]
+fn test_inline_html_style_tags() {
+    use pretty_assertions::assert_eq;
+
+    let text = |text: &str, styles: &[TextStyle]| {
+        TextSpan::Text(text.into(), styles.iter().cloned().collect())
+    };
+
+    assert_eq!(
+        parse_markdown_to_ast(
+            "x<sub>2</sub> e<sup>i<sub>k</sub></sup><br>Press <KBD>Tab</KBD>"
+        ),
+        vec![Block::paragraph(vec![
+            text("x", &[]),
+            text("2", &[TextStyle::Subscript]),
+            text(" e", &[]),
+            text("i", &[TextStyle::Superscript]),
+            text("k", &[TextStyle::Superscript, TextStyle::Subscript]),
+            TextSpan::HardBreak,
+            text("Press ", &[]),
+            text("Tab", &[TextStyle::Keyboard]),
+        ])]
+    );
+
+    // Unknown tags are still skipped with a warning.
+    let (_, diagnostics) =
+        parse_markdown_with_diagnostics("a <u>b</u>", &Options::default());
+    assert_eq!(diagnostics.len(), 2);
+
+    // pulldown-cmark doesn't wrap the text of a tight list item in a paragraph.
+    let (blocks, diagnostics) = parse_markdown_with_diagnostics(
+        "* a <mark>b</mark> c<br>d<sup>2</sup> [e](f)",
+        &Options::default(),
+    );
+    assert_eq!(
+        blocks,
+        vec![Block::bullet_list(vec![ListItem(vec![Block::paragraph(
+            vec![
+                text("a ", &[]),
+                text("b", &[TextStyle::Highlight]),
+                text(" c", &[]),
+                TextSpan::HardBreak,
+                text("d", &[]),
+                text("2", &[TextStyle::Superscript]),
+                text(" ", &[]),
+                TextSpan::Link {
+                    label: Text(vec![text("e", &[])]),
+                    destination: "f".into(),
+                    title: None,
+                },
+            ]
+        )])])]
+    );
+    assert_eq!(diagnostics, vec![]);
+}
+
+#[test]
+fn test_task_lists() {
+    use pretty_assertions::assert_eq;
+
+    let text = |text: &str| TextSpan::Text(text.into(), HashSet::new());
+
+    assert_eq!(
+        parse_markdown_to_ast("- [x] done\n- [ ] todo\n- plain"),
+        vec![Block::bullet_list(vec![
+            ListItem(vec![Block::paragraph(vec![
+                TextSpan::TaskListMarker(true),
+                text("done")
+            ])]),
+            ListItem(vec![Block::paragraph(vec![
+                TextSpan::TaskListMarker(false),
+                text("todo")
+            ])]),
+            ListItem(vec![Block::paragraph(vec![text("plain")])]),
+        ])]
+    );
+
+    // In a loose list, the marker is part of the item's first paragraph.
+    assert_eq!(
+        parse_markdown_to_ast("- [x] done\n\n- [ ] todo"),
+        vec![Block::loose_bullet_list(vec![
+            ListItem(vec![Block::paragraph(vec![
+                TextSpan::TaskListMarker(true),
+                text("done")
+            ])]),
+            ListItem(vec![Block::paragraph(vec![
+                TextSpan::TaskListMarker(false),
+                text("todo")
+            ])]),
+        ])]
+    );
+}
+
+#[test]
+fn test_footnotes() {
+    use pretty_assertions::assert_eq;
+
+    let text = |text: &str| TextSpan::Text(text.into(), HashSet::new());
+
+    // Definitions are moved to the end of the document.
+    assert_eq!(
+        parse_markdown_to_ast("A[^1] and B[^1].\n\n[^1]: The note.\n\nAfter."),
+        vec![
+            Block::paragraph(vec![
+                text("A"),
+                TextSpan::FootnoteReference("1".into()),
+                text(" and B"),
+                TextSpan::FootnoteReference("1".into()),
+                text("."),
+            ]),
+            Block::paragraph(vec![text("After.")]),
+            Block::FootnoteDefinition {
+                label: "1".into(),
+                blocks: vec![Block::paragraph(vec![text("The note.")])],
+            },
+        ]
+    );
+
+    // Definitions nested in list items and block quotes are also moved to the end.
+    assert_eq!(
+        parse_markdown_to_ast("- item[^a]\n\n  [^a]: In a list.\n\n> Quote[^b]\n>\n> [^b]: In a quote.\n\nEnd."),
+        vec![
+            Block::List {
+                items: vec![ListItem(vec![Block::paragraph(vec![
+                    text("item"),
+                    TextSpan::FootnoteReference("a".into()),
+                ])])],
+                ordered: false,
+                start: 1,
+                tight: false,
+            },
+            Block::BlockQuote(vec![Block::paragraph(vec![
+                text("Quote"),
+                TextSpan::FootnoteReference("b".into()),
+            ])]),
+            Block::paragraph(vec![text("End.")]),
+            Block::FootnoteDefinition {
+                label: "a".into(),
+                blocks: vec![Block::paragraph(vec![text("In a list.")])],
+            },
+            Block::FootnoteDefinition {
+                label: "b".into(),
+                blocks: vec![Block::paragraph(vec![text("In a quote.")])],
+            },
+        ]
+    );
+}
+
+#[test]
+fn test_abbreviation_definitions_in_code() {
+    use pretty_assertions::assert_eq;
+
+    // Lines in code blocks that look like abbreviation definitions are kept.
+    let code: Vec<String> =
+        parse_markdown_to_ast("```\n*[x]: y\n```\n\n    *[z]: w\n\n*[y]: Why\n")
+            .into_iter()
+            .map(|block| match block {
+                Block::CodeBlock { code, .. } => code,
+                block => panic!("expected code block, got {block:?}"),
+            })
+            .collect();
+
+    assert_eq!(code, vec!["*[x]: y\n", "*[z]: w\n"]);
+}
+
+#[test]
+fn test_images() {
+    use pretty_assertions::assert_eq;
+
+    let text = |text: &str| TextSpan::Text(text.into(), HashSet::new());
+
+    assert_eq!(
+        parse_markdown_to_ast("![A *small* `logo`](logo.png \"Title\")"),
+        vec![Block::paragraph(vec![TextSpan::Image {
+            alt: "A small logo".into(),
+            destination: "logo.png".into(),
+        }])]
+    );
+
+    assert_eq!(
+        parse_markdown_to_ast(
+            "See ![chart](https://example.com/chart.png) or [![badge](badge.svg)](https://example.com)."
+        ),
+        vec![Block::paragraph(vec![
+            text("See "),
+            TextSpan::Image {
+                alt: "chart".into(),
+                destination: "https://example.com/chart.png".into(),
+            },
+            text(" or "),
+            TextSpan::Link {
+                label: Text(vec![TextSpan::Image {
+                    alt: "badge".into(),
+                    destination: "badge.svg".into(),
+                }]),
+                destination: "https://example.com".into(),
+                title: None,
+            },
+            text("."),
+        ])]
+    );
+}
+
+#[test]
+fn test_links() {
+    use pretty_assertions::assert_eq;
+
+    let text = |text: &str| TextSpan::Text(text.into(), HashSet::new());
+    let link = |label: &str, destination: &str| TextSpan::Link {
+        label: Text(vec![text(label)]),
+        destination: destination.into(),
+        title: None,
+    };
+
+    // Reference, collapsed, and shortcut links use the destination of the matching
+    // link reference definition.
+    assert_eq!(
+        parse_markdown_to_ast(
+            "[text][ref], [ref][], and [ref].\n\n[ref]: https://example.com/ref"
+        ),
+        vec![Block::paragraph(vec![
+            link("text", "https://example.com/ref"),
+            text(", "),
+            link("ref", "https://example.com/ref"),
+            text(", and "),
+            link("ref", "https://example.com/ref"),
+            text("."),
+        ])]
+    );
+
+    assert_eq!(
+        parse_markdown_to_ast("[text](https://example.com \"The title\")"),
+        vec![Block::paragraph(vec![TextSpan::Link {
+            label: Text(vec![text("text")]),
+            destination: "https://example.com".into(),
+            title: Some("The title".into()),
+        }])]
+    );
+
+    // A link without a destination is just its text.
+    assert_eq!(
+        parse_markdown_to_ast("[text]() and [empty][e]\n\n[e]: <>"),
+        vec![Block::paragraph(vec![text("text and empty")])]
+    );
+
+    assert_eq!(
+        parse_markdown_to_ast("<name@example.com>"),
+        vec![Block::paragraph(vec![TextSpan::Link {
+            label: Text(vec![TextSpan::Text(
+                "name@example.com".into(),
+                HashSet::new()
+            )]),
+            destination: "mailto:name@example.com".into(),
+            title: None,
+        }])]
+    );
+}
+
+#[test]
+fn test_text_to_string() {
+    use pretty_assertions::assert_eq;
+
+    let text = |text: &str| TextSpan::Text(text.into(), HashSet::new());
+
+    assert_eq!(
+        text_to_string(&Text(vec![
+            text("call "),
+            TextSpan::Code("f(x)".into()),
+            text(" or see "),
+            TextSpan::Link {
+                label: Text(vec![text("the "), TextSpan::Code("docs".into())]),
+                destination: "https://example.com".into(),
+                title: None,
+            },
+            TextSpan::HardBreak,
+            text("next"),
+            TextSpan::SoftBreak,
+            text("line"),
+        ])),
+        "call f(x) or see the docs\nnext line"
+    );
+
+    // Styles are dropped.
+    assert_eq!(
+        text_to_string(&Text(vec![
+            text("an "),
+            TextSpan::Text(
+                "emphasized".into(),
+                HashSet::from_iter(vec![TextStyle::Emphasis])
+            ),
+            text(" word"),
+        ])),
+        "an emphasized word"
+    );
+}
+
+#[test]
+fn test_table_alignments() {
+    use pretty_assertions::assert_eq;
+
+    let text = |text: &str| Text(vec![TextSpan::Text(text.into(), HashSet::new())]);
+
+    assert_eq!(
+        parse_markdown_to_ast("| a | b | c |\n|:--|--:|---|\n| 1 | 2 | 3 |"),
+        vec![Block::Table {
+            alignments: vec![Alignment::Left, Alignment::Right, Alignment::None],
+            headers: vec![text("a"), text("b"), text("c")],
+            rows: vec![vec![text("1"), text("2"), text("3")]],
+        }]
     );
 }
 
@@ -572,7 +2079,7 @@ fn test_structure() {
               world
             "
         )),
-        vec![Block::List(vec![ListItem(vec![
+        vec![Block::loose_bullet_list(vec![ListItem(vec![
             Block::paragraph(vec![TextSpan::Text("hello".into(), Default::default())]),
             Block::paragraph(vec![TextSpan::Text("world".into(), Default::default())])
         ])])]
@@ -593,18 +2100,20 @@ fn test_structure() {
             "
         )),
         vec![
-            Block::Heading(
-                HeadingLevel::H1,
-                Text(vec![TextSpan::Text("Example".into(), Default::default())])
-            ),
-            Block::List(vec![
+            Block::Heading {
+                level: HeadingLevel::H1,
+                text: Text(vec![TextSpan::Text("Example".into(), Default::default())]),
+                id: None,
+                classes: vec![],
+            },
+            Block::bullet_list(vec![
                 ListItem(vec![
                     Block::paragraph(vec![TextSpan::Text("A".into(), Default::default())]),
-                    Block::List(vec![
+                    Block::loose_bullet_list(vec![
                         ListItem(vec![
                             Block::paragraph(vec![TextSpan::Text("A.A".into(), Default::default())]),
                             Block::paragraph(vec![TextSpan::Text("hello world".into(), Default::default())]),
-                            Block::List(vec![
+                            Block::bullet_list(vec![
                                 ListItem(vec![
                                     Block::paragraph(vec![
                                         TextSpan::Text(
@@ -633,13 +2142,13 @@ fn test_structure() {
             "
         )),
         vec![
-            Block::List(vec![
+            Block::bullet_list(vec![
                 ListItem(vec![
                     Block::paragraph(vec![TextSpan::Text("A".into(), Default::default())]),
-                    Block::List(vec![
+                    Block::bullet_list(vec![
                         ListItem(vec![
                             Block::paragraph(vec![TextSpan::Text("A.A".into(), Default::default())]),
-                            Block::List(vec![ListItem(vec![
+                            Block::bullet_list(vec![ListItem(vec![
                                 Block::paragraph(vec![TextSpan::Text("A.A.A".into(), Default::default())]),
                             ])])
                         ]),
@@ -668,14 +2177,16 @@ fn test_structure() {
             "
         )),
         vec![
-            Block::Heading(
-                HeadingLevel::H1,
-                Text(vec![TextSpan::Text("Example".into(), Default::default())])
-            ),
-            Block::List(vec![
+            Block::Heading {
+                level: HeadingLevel::H1,
+                text: Text(vec![TextSpan::Text("Example".into(), Default::default())]),
+                id: None,
+                classes: vec![],
+            },
+            Block::bullet_list(vec![
                 ListItem(vec![
                     Block::paragraph(vec![TextSpan::Text("A".into(), Default::default())]),
-                    Block::List(vec![
+                    Block::bullet_list(vec![
                         ListItem(vec![
                             Block::paragraph(vec![TextSpan::Text("A.A".into(), Default::default())]),
                         ]),
@@ -683,7 +2194,7 @@ fn test_structure() {
                             Block::paragraph(vec![TextSpan::Text("A.B".into(), Default::default())]),
                         ]),
                     ]),
-                    Block::List(vec![
+                    Block::bullet_list(vec![
                         ListItem(vec![
                             Block::paragraph(vec![TextSpan::Text("A.C".into(), Default::default())])
                         ])
@@ -707,10 +2218,10 @@ fn test_structure() {
             "
         )),
         vec![
-            Block::List(vec![
+            Block::bullet_list(vec![
                 ListItem(vec![
                     Block::paragraph(vec![TextSpan::Text("A".into(), Default::default())]),
-                    Block::List(vec![
+                    Block::loose_bullet_list(vec![
                         ListItem(vec![
                             Block::paragraph(vec![TextSpan::Text("A.A".into(), Default::default())]),
                         ]),
@@ -746,17 +2257,19 @@ fn test_structure() {
             "
         )),
         vec![
-            Block::Heading(
-                HeadingLevel::H1,
-                Text(vec![TextSpan::Text("Example".into(), Default::default())])
-            ),
-            Block::List(vec![
+            Block::Heading {
+                level: HeadingLevel::H1,
+                text: Text(vec![TextSpan::Text("Example".into(), Default::default())]),
+                id: None,
+                classes: vec![],
+            },
+            Block::bullet_list(vec![
                 ListItem(vec![
                     Block::paragraph(vec![TextSpan::Text("A".into(), Default::default())]),
-                    Block::List(vec![
+                    Block::loose_bullet_list(vec![
                         ListItem(vec![
                             Block::paragraph(vec![TextSpan::Text("A.A".into(), Default::default())]),
-                            Block::List(vec![
+                            Block::bullet_list(vec![
                                 ListItem(vec![
                                     Block::paragraph(vec![
                                         TextSpan::Text(
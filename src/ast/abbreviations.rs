@@ -0,0 +1,178 @@
+//! Support for abbreviation definitions, as used by PHP Markdown Extra:
+//!
+//! ```markdown
+//! The HTML specification is maintained by the W3C.
+//!
+//! *[HTML]: HyperText Markup Language
+//! *[W3C]: World Wide Web Consortium
+//! ```
+//!
+//! Every occurrence of a defined abbreviation in the document text is converted to a
+//! [`TextSpan::Abbreviation`] with the expansion of that abbreviation.
+
+use super::{unflatten, Block, Text, TextSpan, TextStyle};
+
+use std::{collections::HashSet, ops::Range};
+
+use pulldown_cmark::{self as md, Event, Tag};
+
+/// An abbreviation and its expansion.
+pub(crate) struct Abbreviation {
+    term: String,
+    expansion: String,
+}
+
+/// Remove the abbreviation definitions from `input`, and return them.
+///
+/// Definition lines are replaced with whitespace of the same length, so that byte
+/// offsets and line numbers in the returned string match those of `input`. Lines
+/// inside code blocks are never definitions.
+pub(crate) fn extract_definitions(input: &str) -> (String, Vec<Abbreviation>) {
+    let mut output = String::with_capacity(input.len());
+    let mut abbreviations = Vec::new();
+
+    let code_blocks: Vec<Range<usize>> =
+        md::Parser::new_ext(input, unflatten::parser_options())
+            .into_offset_iter()
+            .filter_map(|(event, range)| match event {
+                Event::Start(Tag::CodeBlock(_)) => Some(range),
+                _ => None,
+            })
+            .collect();
+
+    let mut line_start = 0;
+
+    for line in input.split_inclusive('\n') {
+        let line_range = line_start..line_start + line.len();
+        line_start = line_range.end;
+
+        let in_code_block = code_blocks
+            .iter()
+            .any(|code| code.start < line_range.end && line_range.start < code.end);
+
+        match parse_definition(line).filter(|_| !in_code_block) {
+            Some(abbreviation) => {
+                abbreviations.push(abbreviation);
+
+                let content = line.trim_end_matches(&['\r', '\n'][..]);
+                output.push_str(&" ".repeat(content.len()));
+                output.push_str(&line[content.len()..]);
+            },
+            None => output.push_str(line),
+        }
+    }
+
+    (output, abbreviations)
+}
+
+/// Parse a `*[TERM]: expansion` definition line.
+fn parse_definition(line: &str) -> Option<Abbreviation> {
+    let rest = line.trim().strip_prefix("*[")?;
+    let (term, expansion) = rest.split_once("]:")?;
+
+    if term.is_empty() {
+        return None;
+    }
+
+    Some(Abbreviation {
+        term: term.to_owned(),
+        expansion: expansion.trim().to_owned(),
+    })
+}
+
+/// Convert occurrences of `abbreviations` in the text of `blocks` into
+/// [`TextSpan::Abbreviation`]s.
+pub(crate) fn apply(blocks: &mut [Block], abbreviations: &[Abbreviation]) {
+    super::visit_text_mut(blocks, &mut |text| apply_to_text(text, abbreviations));
+}
+
+fn apply_to_text(Text(spans): &mut Text, abbreviations: &[Abbreviation]) {
+    for span in std::mem::take(spans) {
+        match span {
+            TextSpan::Text(text, styles) => {
+                split_text(spans, &text, &styles, abbreviations)
+            },
+            TextSpan::Link {
+                mut label,
+                destination,
+                title,
+            } => {
+                apply_to_text(&mut label, abbreviations);
+                spans.push(TextSpan::Link {
+                    label,
+                    destination,
+                    title,
+                });
+            },
+            span => spans.push(span),
+        }
+    }
+}
+
+fn split_text(
+    spans: &mut Vec<TextSpan>,
+    mut text: &str,
+    styles: &HashSet<TextStyle>,
+    abbreviations: &[Abbreviation],
+) {
+    while let Some((start, abbreviation)) = find_first(text, abbreviations) {
+        if start > 0 {
+            spans.push(TextSpan::Text(text[..start].to_owned(), styles.clone()));
+        }
+
+        spans.push(TextSpan::Abbreviation {
+            label: Text(vec![TextSpan::Text(
+                abbreviation.term.clone(),
+                styles.clone(),
+            )]),
+            expansion: abbreviation.expansion.clone(),
+        });
+
+        text = &text[start + abbreviation.term.len()..];
+    }
+
+    if !text.is_empty() {
+        spans.push(TextSpan::Text(text.to_owned(), styles.clone()));
+    }
+}
+
+/// Find the first whole-word occurrence of any of `abbreviations` in `text`.
+///
+/// If several abbreviations occur at the same offset, the longest is used.
+fn find_first<'a>(
+    text: &str,
+    abbreviations: &'a [Abbreviation],
+) -> Option<(usize, &'a Abbreviation)> {
+    let is_word_char = |c: Option<char>| c.map(char::is_alphanumeric).unwrap_or(false);
+
+    let mut first: Option<(usize, &Abbreviation)> = None;
+
+    for abbreviation in abbreviations {
+        let found = text
+            .match_indices(&abbreviation.term)
+            .find(|&(start, term)| {
+                !is_word_char(text[..start].chars().last())
+                    && !is_word_char(text[start + term.len()..].chars().next())
+            });
+
+        let start = match found {
+            Some((start, _)) => start,
+            None => continue,
+        };
+
+        let is_better = match first {
+            None => true,
+            Some((first_start, first_abbreviation)) => {
+                start < first_start
+                    || (start == first_start
+                        && abbreviation.term.len() > first_abbreviation.term.len())
+            },
+        };
+
+        if is_better {
+            first = Some((start, abbreviation));
+        }
+    }
+
+    first
+}
@@ -0,0 +1,178 @@
+//! Support for the GitHub Flavored Markdown [autolink extension][gfm], which converts
+//! bare URLs, `www.` hosts, and email addresses in prose into links:
+//!
+//! ```markdown
+//! Visit https://example.com, www.example.com, or contact name@example.com.
+//! ```
+//!
+//! [gfm]: https://github.github.com/gfm/#autolinks-extension-
+
+use std::collections::HashSet;
+
+use super::{Block, Text, TextSpan, TextStyle};
+
+/// Convert the bare URLs and email addresses in the text of `blocks` into
+/// [`TextSpan::Link`]s.
+pub(crate) fn apply(blocks: &mut [Block]) {
+    super::visit_text_mut(blocks, &mut apply_to_text);
+}
+
+fn apply_to_text(Text(spans): &mut Text) {
+    // pulldown-cmark splits text at characters like `_` that could delimit emphasis.
+    for span in super::merge_adjacent_text(std::mem::take(spans)) {
+        match span {
+            TextSpan::Text(text, styles) => split_text(spans, &text, &styles),
+            span => spans.push(span),
+        }
+    }
+}
+
+fn split_text(spans: &mut Vec<TextSpan>, mut text: &str, styles: &HashSet<TextStyle>) {
+    while let Some(Autolink {
+        start,
+        end,
+        destination,
+    }) = find_autolink(text)
+    {
+        if start > 0 {
+            spans.push(TextSpan::Text(text[..start].to_owned(), styles.clone()));
+        }
+
+        spans.push(TextSpan::Link {
+            label: Text(vec![TextSpan::Text(
+                text[start..end].to_owned(),
+                styles.clone(),
+            )]),
+            destination,
+            title: None,
+        });
+
+        text = &text[end..];
+    }
+
+    if !text.is_empty() {
+        spans.push(TextSpan::Text(text.to_owned(), styles.clone()));
+    }
+}
+
+/// A link found in text. `start` and `end` are byte offsets.
+#[derive(Debug, PartialEq)]
+struct Autolink {
+    start: usize,
+    end: usize,
+    destination: String,
+}
+
+/// Find the first URL, `www.` host, or email address in `text`.
+fn find_autolink(text: &str) -> Option<Autolink> {
+    for (start, c) in text.char_indices() {
+        // Autolinks must start at the beginning of a word.
+        let at_word_start = text[..start]
+            .chars()
+            .last()
+            .map(|prev| prev.is_whitespace() || "(*_~".contains(prev))
+            .unwrap_or(true);
+
+        if at_word_start {
+            if let Some(link) = url_at(text, start) {
+                return Some(link);
+            }
+        }
+
+        if c == '@' {
+            if let Some(link) = email_around(text, start) {
+                return Some(link);
+            }
+        }
+    }
+
+    None
+}
+
+/// Returns the URL or `www.` host that begins at byte offset `start` of `text`.
+fn url_at(text: &str, start: usize) -> Option<Autolink> {
+    let rest = &text[start..];
+
+    let scheme = ["https://", "http://", "www."]
+        .into_iter()
+        .find(|prefix| rest.starts_with(prefix))?;
+
+    let len = rest
+        .find(|c: char| c.is_whitespace() || c == '<')
+        .unwrap_or(rest.len());
+    let url = trim_trailing_punctuation(&rest[..len]);
+
+    // Require a host name after the scheme.
+    let host = url.get(scheme.len()..).unwrap_or("");
+    if !host.starts_with(|c: char| c.is_alphanumeric()) {
+        return None;
+    }
+
+    let destination = if scheme == "www." {
+        format!("http://{url}")
+    } else {
+        url.to_owned()
+    };
+
+    Some(Autolink {
+        start,
+        end: start + url.len(),
+        destination,
+    })
+}
+
+/// Returns the email address containing the `@` at byte offset `at` of `text`.
+fn email_around(text: &str, at: usize) -> Option<Autolink> {
+    let is_local_char = |c: char| c.is_ascii_alphanumeric() || ".+-_".contains(c);
+    let is_domain_char = |c: char| c.is_ascii_alphanumeric() || ".-_".contains(c);
+
+    let start = text[..at]
+        .char_indices()
+        .rev()
+        .take_while(|(_, c)| is_local_char(*c))
+        .last()
+        .map(|(offset, _)| offset)?;
+
+    let domain_len = text[at + 1..]
+        .find(|c: char| !is_domain_char(c))
+        .unwrap_or(text.len() - at - 1);
+    let domain = text[at + 1..at + 1 + domain_len].trim_end_matches(['.', '-', '_']);
+
+    if !domain.contains('.') || domain.starts_with('.') {
+        return None;
+    }
+
+    let end = at + 1 + domain.len();
+
+    Some(Autolink {
+        start,
+        end,
+        destination: format!("mailto:{}", &text[start..end]),
+    })
+}
+
+/// Remove punctuation from the end of `url` that is more likely to be part of the
+/// surrounding sentence, like a full stop or a closing parenthesis.
+fn trim_trailing_punctuation(mut url: &str) -> &str {
+    loop {
+        let trimmed = url
+            .trim_end_matches(['.', ',', ':', ';', '!', '?', '"', '\'', '*', '_', '~']);
+
+        // Keep a closing parenthesis if it is balanced within the URL, e.g.
+        // `https://en.wikipedia.org/wiki/Rust_(programming_language)`.
+        let trimmed = match trimmed.strip_suffix(')') {
+            Some(without)
+                if trimmed.matches(')').count() > trimmed.matches('(').count() =>
+            {
+                without
+            },
+            _ => trimmed,
+        };
+
+        if trimmed.len() == url.len() {
+            return url;
+        }
+
+        url = trimmed;
+    }
+}
@@ -0,0 +1,137 @@
+//! Support for TeX math delimited by dollar signs, as used by Pandoc and GitHub:
+//!
+//! ```markdown
+//! The area of a circle is $\pi r^2$.
+//!
+//! $$
+//! \int_0^1 x^2 \, dx
+//! $$
+//! ```
+//!
+//! A paragraph that is entirely wrapped in `$$` is converted to a
+//! [`Block::DisplayMath`]. Other math is converted to [`TextSpan::InlineMath`].
+//!
+//! Math is recognized after the Markdown has been parsed, so Markdown escapes like
+//! `\{` inside math have already been replaced by the character they escape.
+
+use std::collections::HashSet;
+
+use super::{Block, ListItem, Text, TextSpan, TextStyle};
+
+/// Convert the `$...$` and `$$...$$` math in `blocks` into [`Block::DisplayMath`] and
+/// [`TextSpan::InlineMath`].
+pub(crate) fn apply(blocks: &mut [Block]) {
+    for block in blocks.iter_mut() {
+        match block {
+            Block::Paragraph(text) => {
+                if let Some(tex) = display_math(text) {
+                    *block = Block::DisplayMath(tex);
+                }
+            },
+            Block::List { items, .. } => {
+                for ListItem(blocks) in items {
+                    apply(blocks);
+                }
+            },
+            Block::BlockQuote(blocks) | Block::FootnoteDefinition { blocks, .. } => {
+                apply(blocks)
+            },
+            _ => (),
+        }
+    }
+
+    super::visit_text_mut(blocks, &mut apply_to_text);
+}
+
+/// Returns the TeX in `text`, if `text` is only unstyled text wrapped in `$$`.
+fn display_math(Text(spans): &Text) -> Option<String> {
+    let source: String = super::merge_adjacent_text(spans.clone())
+        .into_iter()
+        .map(|span| match span {
+            TextSpan::Text(text, styles) if styles.is_empty() => Some(text),
+            TextSpan::SoftBreak | TextSpan::HardBreak => Some("\n".to_owned()),
+            _ => None,
+        })
+        .collect::<Option<_>>()?;
+
+    let tex = source.strip_prefix("$$")?.strip_suffix("$$")?;
+
+    if tex.trim().is_empty() || tex.contains("$$") {
+        return None;
+    }
+
+    Some(tex.trim().to_owned())
+}
+
+fn apply_to_text(Text(spans): &mut Text) {
+    // pulldown-cmark splits text at characters like `_` that could delimit emphasis.
+    for span in super::merge_adjacent_text(std::mem::take(spans)) {
+        match span {
+            TextSpan::Text(text, styles) => split_text(spans, &text, &styles),
+            span => spans.push(span),
+        }
+    }
+}
+
+fn split_text(spans: &mut Vec<TextSpan>, mut text: &str, styles: &HashSet<TextStyle>) {
+    while let Some((start, end, delimiter)) = find_math(text) {
+        if start > 0 {
+            spans.push(TextSpan::Text(text[..start].to_owned(), styles.clone()));
+        }
+
+        let tex = &text[start + delimiter.len()..end - delimiter.len()];
+        spans.push(TextSpan::InlineMath(tex.to_owned()));
+
+        text = &text[end..];
+    }
+
+    if !text.is_empty() {
+        spans.push(TextSpan::Text(text.to_owned(), styles.clone()));
+    }
+}
+
+/// Returns the byte offsets of the start and end of the first `$...$` or `$$...$$`
+/// in `text`, and the delimiter used.
+///
+/// Like Pandoc, the opening `$` must not be followed by whitespace, and the closing
+/// `$` must not be preceded by whitespace or followed by a digit. This avoids
+/// treating prices like `$5 and $10` as math.
+fn find_math(text: &str) -> Option<(usize, usize, &'static str)> {
+    let mut search_from = 0;
+
+    while let Some(offset) = text[search_from..].find('$') {
+        let start = search_from + offset;
+
+        let delimiter = if text[start..].starts_with("$$") {
+            "$$"
+        } else {
+            "$"
+        };
+        let contents_start = start + delimiter.len();
+        search_from = contents_start;
+
+        if text[contents_start..].starts_with(char::is_whitespace) {
+            continue;
+        }
+
+        let is_closing = |offset: usize| {
+            let before = text[..offset].chars().last();
+            let after = text[offset + delimiter.len()..].chars().next();
+
+            offset > contents_start
+                && !before.map(char::is_whitespace).unwrap_or(true)
+                && !after.map(|c| c.is_ascii_digit()).unwrap_or(false)
+        };
+
+        let closing = text[contents_start..]
+            .match_indices(delimiter)
+            .map(|(offset, _)| contents_start + offset)
+            .find(|&offset| is_closing(offset));
+
+        if let Some(end) = closing {
+            return Some((start, end + delimiter.len(), delimiter));
+        }
+    }
+
+    None
+}
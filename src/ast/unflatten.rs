@@ -1,3 +1,5 @@
+use std::ops::Range;
+
 use pulldown_cmark::{self as md, Event, Tag};
 
 //======================================
@@ -8,10 +10,15 @@ use pulldown_cmark::{self as md, Event, Tag};
 pub(crate) enum UnflattenedEvent<'a> {
     /// This [`Event`] can never by [`Event::Start`] or [`Event::End`]. Those events
     /// are represented by
-    Event(Event<'a>),
+    ///
+    /// The [`Range`] is the byte offsets of the source text of this event.
+    Event(Event<'a>, Range<usize>),
     Nested {
         tag: Tag<'a>,
         events: Vec<UnflattenedEvent<'a>>,
+        /// Byte offsets of the source text of this element, from its start tag to its
+        /// end tag.
+        range: Range<usize>,
     },
 }
 
@@ -19,21 +26,29 @@ pub(crate) enum UnflattenedEvent<'a> {
 // Implementation
 //======================================
 
-pub(crate) fn parse_markdown_to_unflattened_events(input: &str) -> Vec<UnflattenedEvent> {
-    // Set up options and parser. Strikethroughs are not part of the CommonMark standard
-    // and we therefore must enable it explicitly.
+/// Returns the options used to parse Markdown input.
+pub(crate) fn parser_options() -> md::Options {
+    // Strikethroughs are not part of the CommonMark standard and we therefore must
+    // enable it explicitly.
     let mut options = md::Options::empty();
     options.insert(md::Options::ENABLE_STRIKETHROUGH);
     options.insert(md::Options::ENABLE_TABLES);
-    let parser = md::Parser::new_ext(input, options);
+    options.insert(md::Options::ENABLE_TASKLISTS);
+    options.insert(md::Options::ENABLE_FOOTNOTES);
+    options.insert(md::Options::ENABLE_HEADING_ATTRIBUTES);
+    options
+}
+
+pub(crate) fn parse_markdown_to_unflattened_events(input: &str) -> Vec<UnflattenedEvent> {
+    let parser = md::Parser::new_ext(input, parser_options());
 
     let mut unflattener = Unflattener {
         root: vec![],
         nested: vec![],
     };
 
-    for event in parser {
-        unflattener.handle_event(event);
+    for (event, range) in parser.into_offset_iter() {
+        unflattener.handle_event(event, range);
     }
 
     unflattener.finish()
@@ -41,29 +56,33 @@ pub(crate) fn parse_markdown_to_unflattened_events(input: &str) -> Vec<Unflatten
 
 struct Unflattener<'a> {
     root: Vec<UnflattenedEvent<'a>>,
-    nested: Vec<(Tag<'a>, Vec<UnflattenedEvent<'a>>)>,
+    nested: Vec<(Tag<'a>, Vec<UnflattenedEvent<'a>>, Range<usize>)>,
 }
 
 impl<'a> Unflattener<'a> {
-    fn handle_event(&mut self, event: Event<'a>) {
+    fn handle_event(&mut self, event: Event<'a>, range: Range<usize>) {
         match event {
             Event::Start(tag) => {
-                self.nested.push((tag, vec![]));
+                self.nested.push((tag, vec![], range));
             },
             Event::End(tag) => {
-                let (tag2, inner) = self.nested.pop().expect("expected nested events");
+                let (tag2, inner, range) =
+                    self.nested.pop().expect("expected nested events");
 
                 debug_assert_eq!(tag, tag2);
 
-                self.seq()
-                    .push(UnflattenedEvent::Nested { tag, events: inner });
+                self.seq().push(UnflattenedEvent::Nested {
+                    tag,
+                    events: inner,
+                    range,
+                });
             },
-            event => self.seq().push(UnflattenedEvent::Event(event)),
+            event => self.seq().push(UnflattenedEvent::Event(event, range)),
         }
     }
 
     fn seq(&mut self) -> &mut Vec<UnflattenedEvent<'a>> {
-        if let Some((_, seq)) = self.nested.last_mut() {
+        if let Some((_, seq, _)) = self.nested.last_mut() {
             seq
         } else {
             &mut self.root
@@ -0,0 +1,79 @@
+//! Support for Obsidian-style wikilinks:
+//!
+//! ```markdown
+//! See [[Getting Started]], or the [[Reference Manual|manual]].
+//! ```
+//!
+//! A wikilink is converted to a [`TextSpan::Link`] to the fragment of the
+//! [slugified](super::slugify) target name, e.g. `#getting-started`. Headings are
+//! given ids the same way, so that a wikilink to a heading in the same document links
+//! to that heading.
+
+use std::collections::HashSet;
+
+use super::{Block, Text, TextSpan, TextStyle};
+
+/// Convert the wikilinks in the text of `blocks` into [`TextSpan::Link`]s.
+pub(crate) fn apply(blocks: &mut [Block]) {
+    super::assign_heading_ids(blocks);
+
+    super::visit_text_mut(blocks, &mut apply_to_text);
+}
+
+fn apply_to_text(Text(spans): &mut Text) {
+    // pulldown-cmark emits unmatched `[` and `]` characters as separate text events.
+    for span in super::merge_adjacent_text(std::mem::take(spans)) {
+        match span {
+            TextSpan::Text(text, styles) => split_text(spans, &text, &styles),
+            span => spans.push(span),
+        }
+    }
+}
+
+fn split_text(spans: &mut Vec<TextSpan>, mut text: &str, styles: &HashSet<TextStyle>) {
+    while let Some((start, end)) = find_wikilink(text) {
+        let contents = &text[start + 2..end - 2];
+
+        let (target, display) = contents.split_once('|').unwrap_or((contents, contents));
+
+        if start > 0 {
+            spans.push(TextSpan::Text(text[..start].to_owned(), styles.clone()));
+        }
+
+        spans.push(TextSpan::Link {
+            label: Text(vec![TextSpan::Text(
+                display.trim().to_owned(),
+                styles.clone(),
+            )]),
+            destination: format!("#{}", super::slugify(target)),
+            title: None,
+        });
+
+        text = &text[end..];
+    }
+
+    if !text.is_empty() {
+        spans.push(TextSpan::Text(text.to_owned(), styles.clone()));
+    }
+}
+
+/// Returns the byte offsets of the start and end of the first `[[...]]` in `text`.
+fn find_wikilink(text: &str) -> Option<(usize, usize)> {
+    let mut search_from = 0;
+
+    while let Some(offset) = text[search_from..].find("[[") {
+        let start = search_from + offset;
+        let contents_start = start + 2;
+
+        let contents_len = text[contents_start..].find("]]")?;
+        let contents = &text[contents_start..contents_start + contents_len];
+
+        if !contents.trim().is_empty() && !contents.contains('[') {
+            return Some((start, contents_start + contents_len + 2));
+        }
+
+        search_from = contents_start;
+    }
+
+    None
+}
@@ -0,0 +1,138 @@
+//! Skip re-converting Markdown files that haven't changed since they were last
+//! converted.
+//!
+//! The cache is a small sidecar file stored next to the output notebook, which records
+//! a fingerprint of the input file contents and the notebook conversion options used to
+//! generate that notebook.
+
+use std::{
+    collections::hash_map::DefaultHasher,
+    fmt::Debug,
+    hash::{Hash, Hasher},
+    io,
+    path::{Path, PathBuf},
+};
+
+
+/// Returns the location of the cache sidecar file for the notebook at `output`.
+///
+/// E.g. `docs/README.nb` is cached in `docs/.README.nb.md2nb-cache`.
+pub(crate) fn cache_path(output: &Path) -> PathBuf {
+    let file_name = output
+        .file_name()
+        .expect("output path has no file name")
+        .to_string_lossy();
+
+    output.with_file_name(format!(".{file_name}.md2nb-cache"))
+}
+
+/// Compute a fingerprint of the Markdown `contents`, the conversion `opts`, and any
+/// other command-line `flags` that change the generated notebook.
+///
+/// The standard library hasher is not guaranteed to be stable between Rust releases.
+/// That is acceptable here: a changed fingerprint only causes an unnecessary
+/// reconversion.
+pub(crate) fn fingerprint(
+    contents: &str,
+    opts: &md2nb::Options,
+    flags: impl Debug,
+) -> u64 {
+    let mut hasher = DefaultHasher::new();
+
+    contents.hash(&mut hasher);
+    format!("{opts:?}").hash(&mut hasher);
+    format!("{flags:?}").hash(&mut hasher);
+
+    hasher.finish()
+}
+
+/// Returns `true` if `output` exists and was last generated from inputs with the
+/// specified `fingerprint`.
+pub(crate) fn is_up_to_date(output: &Path, fingerprint: u64) -> bool {
+    if !output.is_file() {
+        return false;
+    }
+
+    match std::fs::read_to_string(cache_path(output)) {
+        Ok(cached) => cached.trim() == fingerprint.to_string(),
+        Err(_) => false,
+    }
+}
+
+/// Record that `output` was generated from inputs with the specified `fingerprint`.
+pub(crate) fn record(output: &Path, fingerprint: u64) -> io::Result<()> {
+    std::fs::write(cache_path(output), fingerprint.to_string())
+}
+
+//======================================
+// Tests
+//======================================
+
+#[test]
+fn test_unchanged_input_is_up_to_date() {
+    let dir =
+        std::env::temp_dir().join(format!("md2nb-cache-test-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+
+    let output = dir.join("example.nb");
+
+    let opts = md2nb::Options::default();
+
+    let first = fingerprint("# Example", &opts, ());
+
+    // Nothing has been generated yet.
+    assert!(!is_up_to_date(&output, first));
+
+    // First run: the notebook is generated and its fingerprint is recorded.
+    std::fs::write(&output, "Notebook[{}]").unwrap();
+    record(&output, first).unwrap();
+
+    // Second run: unchanged input and options are skipped.
+    assert!(is_up_to_date(&output, fingerprint("# Example", &opts, ())));
+
+    // Changing either the input or the options requires reconversion.
+    assert!(!is_up_to_date(&output, fingerprint("# Changed", &opts, ())));
+    assert!(!is_up_to_date(
+        &output,
+        fingerprint(
+            "# Example",
+            &md2nb::Options {
+                nb: md2nb::nb::Options::builder()
+                    .create_external_language_cells(false)
+                    .build(),
+                ..md2nb::Options::default()
+            },
+            ()
+        )
+    ));
+    assert!(!is_up_to_date(
+        &output,
+        fingerprint("# Example", &opts, true)
+    ));
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_fingerprint_options() {
+    use md2nb::ast;
+
+    let opts = md2nb::Options::default();
+    let first = fingerprint("$x$", &opts, ());
+
+    // Markdown parsing options, like `--math`, change the generated notebook.
+    let math = md2nb::Options {
+        ast: ast::Options {
+            math: true,
+            ..ast::Options::default()
+        },
+        ..md2nb::Options::default()
+    };
+    assert_ne!(first, fingerprint("$x$", &math, ()));
+
+    let hero = md2nb::Options {
+        hero: true,
+        ..md2nb::Options::default()
+    };
+    assert_ne!(first, fingerprint("$x$", &hero, ()));
+}
@@ -0,0 +1,140 @@
+//! Convert Markdown into Wolfram Notebook cells.
+//!
+//! ```
+//! let cells = md2nb::markdown_to_cells("# Hello\n\nWorld", &md2nb::Options::default());
+//!
+//! assert_eq!(cells.unwrap().len(), 2);
+//! ```
+//!
+//! The [`ast`] module parses Markdown into [`ast::Block`]s, and the [`nb`] module
+//! converts those blocks into `Cell[..]` expressions. Neither requires a Wolfram
+//! Kernel; the `md2nb` binary uses a Kernel to save the cells as a notebook file.
+
+pub mod ast;
+pub mod nb;
+pub mod theme;
+
+
+use wolfram_expr::Expr;
+
+/// Options controlling how Markdown is converted into the cells of a notebook.
+#[derive(Debug, Clone, Default)]
+pub struct Options {
+    /// Options used to parse the Markdown.
+    pub ast: ast::Options,
+    /// Options used to convert the parsed Markdown into cells.
+    pub nb: nb::Options,
+    /// If set, a leading H1 heading and paragraph are displayed as a title and
+    /// subtitle. See [`nb::hero_cells()`].
+    pub hero: bool,
+    /// If set, Markdown that can't be converted yet is replaced by a placeholder cell,
+    /// instead of failing the conversion. See [`nb::unsupported_cell()`].
+    pub keep_going: bool,
+    /// If set, all of the cells are wrapped in a single cell group, titled by the
+    /// leading H1 heading or otherwise by this text. See [`nb::wrap_in_group()`].
+    pub group_title: Option<String>,
+}
+
+/// Convert the Markdown `input` into a sequence of `Cell[..]` expressions.
+///
+/// Warnings about the Markdown are printed to stderr.
+pub fn markdown_to_cells(
+    input: &str,
+    opts: &Options,
+) -> Result<Vec<Expr>, nb::Unsupported> {
+    let blocks = ast::parse_markdown_to_ast_with_options(input, &opts.ast);
+
+    blocks_to_cells(blocks, opts, |_| ())
+}
+
+/// Convert the parsed Markdown `blocks` of a notebook into a sequence of `Cell[..]`
+/// expressions.
+///
+/// If [`Options::keep_going`] is set, `on_unsupported` is called for each construct
+/// that is replaced by a placeholder cell.
+pub fn blocks_to_cells(
+    mut blocks: Vec<ast::Block>,
+    opts: &Options,
+    mut on_unsupported: impl FnMut(nb::Unsupported),
+) -> Result<Vec<Expr>, nb::Unsupported> {
+    let mut cells = if opts.hero {
+        nb::hero_cells(&mut blocks, &opts.nb)
+    } else {
+        Vec::new()
+    };
+
+    if opts.nb.table_of_contents {
+        cells.extend(nb::table_of_contents_cell(&mut blocks, &opts.nb));
+    }
+
+    for block in blocks {
+        match nb::try_block_to_cells(block, &opts.nb) {
+            Ok(block_cells) => cells.extend(block_cells),
+            Err(unsupported) if opts.keep_going => {
+                on_unsupported(unsupported);
+                cells.push(nb::unsupported_cell(unsupported));
+            },
+            Err(unsupported) => return Err(unsupported),
+        }
+    }
+
+    if let Some(title) = &opts.group_title {
+        cells = vec![nb::wrap_in_group(cells, title)];
+    }
+
+    if opts.nb.group_cells {
+        cells = nb::group_cells_by_heading(cells);
+    }
+
+    Ok(cells)
+}
+
+//======================================
+// Tests
+//======================================
+
+#[test]
+fn test_markdown_to_cells() {
+    use pretty_assertions::assert_eq;
+
+    let style = |cell: &Expr| cell.try_normal().unwrap().elements()[1].clone();
+
+    // The Markdown is parsed using the `ast` options.
+    let opts = Options {
+        ast: ast::Options {
+            math: true,
+            ..ast::Options::default()
+        },
+        ..Options::default()
+    };
+    let cells = markdown_to_cells("$$x^2$$", &opts).unwrap();
+    assert_eq!(
+        cells.iter().map(style).collect::<Vec<_>>(),
+        vec![Expr::string("Input")]
+    );
+
+    // Unsupported Markdown is an error, unless `keep_going` is set.
+    let markdown = "Start\n\n- ***";
+    assert_eq!(
+        markdown_to_cells(markdown, &Options::default()),
+        Err(nb::Unsupported("markdown rule inside list item"))
+    );
+
+    let opts = Options {
+        keep_going: true,
+        ..Options::default()
+    };
+    let mut unsupported = Vec::new();
+    let cells =
+        blocks_to_cells(ast::parse_markdown_to_ast(markdown), &opts, |construct| {
+            unsupported.push(construct)
+        })
+        .unwrap();
+
+    assert_eq!(cells.len(), 2);
+    assert_eq!(cells[1], nb::unsupported_cell(unsupported[0]));
+    assert_eq!(
+        unsupported,
+        vec![nb::Unsupported("markdown rule inside list item")]
+    );
+}
@@ -1,161 +1,956 @@
-mod ast;
-mod nb;
+mod cache;
+mod watchdog;
 
 
-use std::{path::PathBuf, process};
+use std::{
+    collections::hash_map::RandomState,
+    ffi::OsString,
+    fs::{File, OpenOptions},
+    hash::{BuildHasher, Hasher},
+    io,
+    path::{Path, PathBuf},
+    process,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, Mutex,
+    },
+    thread,
+    time::Duration,
+};
 
 use clap::Parser;
+use pulldown_cmark::HeadingLevel;
+
+use md2nb::{ast, nb, theme};
 
 use wolfram_app_discovery::WolframApp;
 use wolfram_expr::{Expr, Symbol};
-use wstp::kernel::{self, WolframKernelProcess};
+use wstp::kernel;
+
+use crate::watchdog::Watchdog;
 
 /// Convert Markdown files into Wolfram Notebooks.
 #[derive(Parser, Debug)]
 #[clap(author, version)]
 struct Args {
-    /// Markdown input file.
+    /// Markdown input file, or `-` to read from stdin.
     input: PathBuf,
 
     /// Output file location. (default: `<INPUT>.nb`)
     ///
     /// If this is a directory, the output notebook file will have the same file name
-    /// as the input file.
+    /// as the input file. An output file is required when reading from stdin.
+    ///
+    /// If this has a `.wxf` extension, the notebook expression is written in the
+    /// Wolfram Exchange Format instead, without launching a Wolfram Kernel, as with
+    /// `--no-kernel`.
     output: Option<PathBuf>,
 
+    /// Directory to write the output notebook to. The notebook file will have the same
+    /// file name as the input file.
+    ///
+    /// Unlike `<OUTPUT>`, this is always treated as a directory, and is created if it
+    /// does not exist.
+    #[clap(short = 'd', long, value_name = "DIR", conflicts_with = "output")]
+    output_dir: Option<PathBuf>,
+
     /// Opens the notebook after conversion completes
     #[clap(long)]
     open: bool,
 
+    /// Overwrite the output notebook if it already exists.
+    #[clap(long, short = 'f')]
+    force: bool,
+
     /// If set, disables conversion of code blocks to "ExternalLanguage" cells. Code
     /// blocks will instead be converted to inert "Program" cells.
     #[clap(long)]
     no_external_language_cells: bool,
+
+    /// If set, tab characters in prose are preserved as tab stops instead of being
+    /// collapsed.
+    #[clap(long)]
+    preserve_tabs: bool,
+
+    /// Width that notebook text wraps at: a number of points, `infinity`, or `paper`.
+    #[clap(long, value_name = "WIDTH")]
+    page_width: Option<nb::PageWidth>,
+
+    /// Cell style to use for headings at a level, e.g. `H1=Section`. May be repeated.
+    #[clap(long, value_name = "H<N>=STYLE")]
+    heading_style: Vec<nb::HeadingStyle>,
+
+    /// Cell evaluation language to use for fenced code blocks in a language, e.g.
+    /// `ruby=Ruby`. Use `Program` for a static code cell, or `Input` for Wolfram
+    /// Language code. May be repeated.
+    #[clap(long = "lang", value_name = "LANGUAGE=TARGET")]
+    language_map: Vec<nb::LanguageMapping>,
+
+    /// Parse Wolfram Language code blocks into typeset "Input" cells, instead of
+    /// leaving the code as plain text.
+    #[clap(long)]
+    parse_wolfram_code: bool,
+
+    /// Text of a header docked at the top of the notebook window.
+    #[clap(long, value_name = "TEXT")]
+    header_text: Option<String>,
+
+    /// Text of a footer docked below the header.
+    #[clap(long, value_name = "TEXT")]
+    footer_text: Option<String>,
+
+    /// Name of the evaluator (session) used to evaluate "ExternalLanguage" cells.
+    #[clap(long, value_name = "NAME")]
+    evaluator: Option<String>,
+
+    /// Configure "ExternalLanguage" cells so that re-evaluating them overwrites their
+    /// previous output.
+    #[clap(long)]
+    cell_auto_overwrite: bool,
+
+    /// Hard-wrap prose at this many columns, by inserting line breaks between words.
+    #[clap(long, value_name = "COLUMNS")]
+    wrap_prose: Option<usize>,
+
+    /// Give inline code a light gray background.
+    #[clap(long)]
+    inline_code_background: bool,
+
+    /// Load block quote, code block, and inline code colors from a `.toml` or `.json`
+    /// theme file.
+    #[clap(long, value_name = "PATH")]
+    theme_file: Option<PathBuf>,
+
+    /// Split paragraphs longer than this many characters into several cells.
+    #[clap(long, value_name = "N")]
+    max_cell_chars: Option<usize>,
+
+    /// How thematic breaks are displayed: `line`, `dots`, or `space`.
+    #[clap(long, value_name = "STYLE", default_value = "line")]
+    rule_style: nb::RuleStyle,
+
+    /// How line endings within a paragraph are displayed: `space`, `newline`, or
+    /// `none`.
+    #[clap(long, value_name = "MODE", default_value = "space")]
+    soft_break: nb::SoftBreakMode,
+
+    /// Center tables horizontally on the page.
+    #[clap(long)]
+    center_tables: bool,
+
+    /// Convert tables containing only plain text into an "Input" cell that creates a
+    /// sortable, filterable `Dataset`.
+    #[clap(long)]
+    table_as_dataset: bool,
+
+    /// Label each code cell with the range of Markdown source lines it came from.
+    #[clap(long)]
+    code_line_numbers: bool,
+
+    /// Show a gutter of line numbers to the left of multi-line code blocks that are
+    /// converted to "Program" cells.
+    #[clap(long)]
+    code_gutter: bool,
+
+    /// If set, inline HTML tags are interpreted based on their attributes. For example,
+    /// `<span style="color: red">` is converted to red text.
+    #[clap(long)]
+    html_attributes: bool,
+
+    /// Convert `[[Page Name]]` and `[[Page Name|text]]` wikilinks into links to the
+    /// heading whose text slugifies to `page-name`.
+    #[clap(long)]
+    wikilinks: bool,
+
+    /// Convert `$...$` and `$$...$$` into TeX math, displayed in TraditionalForm.
+    #[clap(long)]
+    math: bool,
+
+    /// If the document begins with an H1 heading directly followed by a paragraph,
+    /// display them as a title with a "Subtitle" cell below it.
+    #[clap(long)]
+    hero: bool,
+
+    /// Save each section beginning with a heading at level N (default: 1) as a separate
+    /// notebook, named after the section heading.
+    ///
+    /// Content before the first such heading is saved to `intro.nb`. Notebooks are
+    /// saved in the directory of the output file.
+    #[clap(
+        long,
+        value_name = "N",
+        min_values = 0,
+        require_equals = true,
+        default_missing_value = "1",
+        parse(try_from_str = nb::parse_heading_level)
+    )]
+    split_by_heading: Option<HeadingLevel>,
+
+    /// Wrap all generated cells in a single top-level cell group.
+    ///
+    /// The group is titled by the leading H1 heading if there is one, or otherwise by
+    /// the input file name.
+    #[clap(long)]
+    wrap_in_group: bool,
+
+    /// Group each heading with the cells that follow it, so that sections can be
+    /// collapsed in the front end.
+    #[clap(long)]
+    group_cells: bool,
+
+    /// Add a table of contents linking to each heading at the start of the notebook.
+    #[clap(long)]
+    table_of_contents: bool,
+
+    /// Store the Markdown input in a hidden "RawMarkdown" cell at the top of the
+    /// notebook, so the original source can be recovered.
+    #[clap(long)]
+    embed_markdown: bool,
+
+    /// Skip conversion if the input file and options are unchanged since the output
+    /// notebook was last generated with `--cache`.
+    ///
+    /// The cache is stored in a hidden file next to the output notebook.
+    #[clap(long, overrides_with = "no-cache")]
+    cache: bool,
+
+    /// Disables `--cache`.
+    #[clap(long, overrides_with = "cache")]
+    no_cache: bool,
+
+    /// After saving, load the notebook file back into the Kernel to check that it is a
+    /// valid notebook expression.
+    #[clap(long, conflicts_with = "no-kernel")]
+    validate_notebook: bool,
+
+    /// Write the notebook file directly, without launching a Wolfram Kernel.
+    ///
+    /// Images, math, `nb-cell` code blocks, and code parsed with
+    /// `--parse-wolfram-code` are converted by the Kernel, so they are written
+    /// unevaluated and are not displayed correctly.
+    #[clap(long)]
+    no_kernel: bool,
+
+    /// Print the converted cells to stdout in InputForm, instead of saving a notebook.
+    ///
+    /// The Wolfram Kernel is not launched, and no files are written.
+    #[clap(
+        long,
+        conflicts_with_all = &["open", "validate-notebook", "manifest"]
+    )]
+    dry_run: bool,
+
+    /// Connect to a running Wolfram Kernel that is listening on the named WSTP link,
+    /// instead of launching a new Kernel. The Kernel is left running afterwards.
+    ///
+    /// Names of the form `port@host` are connected to using TCP/IP, and other names
+    /// using shared memory.
+    #[clap(long, value_name = "NAME", conflicts_with = "no-kernel")]
+    link_name: Option<String>,
+
+    /// Path to the `WolframKernel` executable to launch.
+    ///
+    /// Defaults to the `MD2NB_KERNEL` environment variable, if set, and otherwise
+    /// to the Kernel of the default Wolfram Language installation.
+    #[clap(
+        long,
+        value_name = "PATH",
+        conflicts_with_all = &["no-kernel", "link-name"]
+    )]
+    kernel: Option<PathBuf>,
+
+    /// Abort the conversion if the Wolfram Kernel doesn't respond for this many
+    /// seconds, instead of waiting indefinitely.
+    #[clap(long, value_name = "SECONDS", conflicts_with = "no-kernel")]
+    timeout: Option<u64>,
+
+    /// Save each notebook with a single `Export[..]` of the complete notebook
+    /// expression, instead of writing the cells to a front end notebook one batch at
+    /// a time.
+    ///
+    /// This doesn't require a front end, but the notebook file is not formatted or
+    /// cached by the front end until it is next saved.
+    #[clap(long, conflicts_with = "no-kernel")]
+    export_notebook: bool,
+
+    /// Replace blocks containing unsupported Markdown with a placeholder cell, instead
+    /// of aborting the conversion.
+    #[clap(long)]
+    keep_going: bool,
+
+    /// Print conversion warnings to stdout as a JSON array, and exit without generating
+    /// a notebook.
+    ///
+    /// Each warning is an object with `line`, `column`, `kind`, and `message` fields.
+    #[clap(long)]
+    diagnostics_json: bool,
+
+    /// Write a JSON manifest listing the input file, the generated notebook, its
+    /// cell count, and any conversion warnings.
+    #[clap(long, value_name = "PATH")]
+    manifest: Option<PathBuf>,
+
+    /// Print the parsed Markdown events and AST to stderr, for debugging or for
+    /// including in bug reports.
+    #[clap(long, short = 'v')]
+    verbose: bool,
+}
+
+/// A notebook saved to a temporary file, that hasn't been moved to its output path yet.
+struct SavedNotebook {
+    output: PathBuf,
+    temp_output: PathBuf,
+    cell_count: usize,
+    is_valid: Option<bool>,
+}
+
+/// A converted file, as listed in the `--manifest` file.
+struct ManifestEntry {
+    input: PathBuf,
+    output: PathBuf,
+    cell_count: usize,
+    warnings: Vec<ast::Diagnostic>,
+}
+
+/// The result of converting an input file.
+struct Conversion {
+    /// The notebooks that were saved.
+    entries: Vec<ManifestEntry>,
+    /// Whether saving any of the notebooks failed. The errors have already been
+    /// printed.
+    failed: bool,
+}
+
+/// An error that stops the conversion.
+#[derive(Debug)]
+enum Error {
+    /// The input, output, or command-line options can't be used, e.g. because the
+    /// output file already exists.
+    Invalid(String),
+    /// An IO operation failed. `context` describes what was being done.
+    Io { context: String, err: io::Error },
+    /// No Wolfram Language installation could be found to launch a Kernel from.
+    WolframAppNotFound(wolfram_app_discovery::Error),
+    /// Launching or using the Wolfram Kernel failed.
+    Kernel(kernel::Error),
+    /// Communicating with the Wolfram Kernel failed.
+    Wstp(wstp::Error),
+}
+
+impl std::fmt::Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Error::Invalid(message) => write!(f, "{message}"),
+            Error::Io { context, err } => write!(f, "{context}: {err}"),
+            Error::WolframAppNotFound(err) => write!(
+                f,
+                "unable to find a Wolfram Language installation ({err}); set \
+                WOLFRAM_APP_DIRECTORY to the installation directory, or run \
+                `wolfram-app-discovery default` to see which installation is found \
+                (or use --no-kernel to convert without one)"
+            ),
+            Error::Kernel(err) => write!(f, "Wolfram Kernel error: {err:?}"),
+            Error::Wstp(err) => write!(f, "Wolfram Kernel WSTP connection error: {err}"),
+        }
+    }
+}
+
+impl From<kernel::Error> for Error {
+    fn from(err: kernel::Error) -> Error {
+        Error::Kernel(err)
+    }
+}
+
+impl From<wstp::Error> for Error {
+    fn from(err: wstp::Error) -> Error {
+        Error::Wstp(err)
+    }
+}
+
+impl Error {
+    /// Returns a closure that converts an IO error into an [`Error::Io`] described by
+    /// `context`, for use with [`Result::map_err`].
+    fn io(context: impl Into<String>) -> impl FnOnce(io::Error) -> Error {
+        let context = context.into();
+        move |err| Error::Io { context, err }
+    }
+}
+
+fn main() {
+    if let Err(err) = convert() {
+        eprintln!("error: {err}");
+        process::exit(1);
+    }
+}
+
+fn convert() -> Result<(), Error> {
+    let args = Args::parse();
+    let manifest = args.manifest.clone();
+
+    let Conversion { entries, failed } = match convert_file(args)? {
+        Some(conversion) => conversion,
+        None => return Ok(()),
+    };
+
+    if let Some(manifest) = &manifest {
+        std::fs::write(manifest, manifest_to_json(&entries)).map_err(Error::io(
+            format!("unable to write manifest {}", manifest.display()),
+        ))?;
+    }
+
+    if failed {
+        process::exit(1);
+    }
+
+    Ok(())
 }
 
-fn main() -> Result<(), kernel::Error> {
+/// Convert the input file specified by `args`.
+///
+/// Returns `None` if no notebooks were saved, e.g. because `--dry-run` was specified
+/// or the output is up to date.
+fn convert_file(args: Args) -> Result<Option<Conversion>, Error> {
     let Args {
         input,
         output,
+        output_dir,
         no_external_language_cells,
         open,
-    } = Args::parse();
+        force,
+        preserve_tabs,
+        page_width,
+        heading_style,
+        language_map,
+        parse_wolfram_code,
+        header_text,
+        footer_text,
+        evaluator,
+        cell_auto_overwrite,
+        wrap_prose,
+        max_cell_chars,
+        rule_style,
+        soft_break,
+        center_tables,
+        table_as_dataset,
+        inline_code_background,
+        theme_file,
+        code_line_numbers,
+        code_gutter,
+        html_attributes,
+        wikilinks,
+        math,
+        hero,
+        split_by_heading,
+        wrap_in_group,
+        group_cells,
+        table_of_contents,
+        embed_markdown,
+        cache,
+        no_cache,
+        validate_notebook,
+        no_kernel,
+        dry_run,
+        link_name,
+        kernel,
+        timeout,
+        export_notebook,
+        keep_going,
+        diagnostics_json,
+        manifest: _,
+        verbose,
+    } = args;
+
+    let cache = cache && !no_cache;
+
+    let contents: String = if is_stdin(&input) {
+        std::io::read_to_string(std::io::stdin())
+            .map_err(Error::io("unable to read stdin"))?
+    } else {
+        std::fs::read_to_string(&input).map_err(Error::io(format!(
+            "unable to read input file {}",
+            input.display()
+        )))?
+    };
+
+    let ast_options = ast::Options {
+        html_attributes,
+        wikilinks,
+        math,
+        verbose,
+    };
+
+    let (ast, diagnostics) =
+        ast::parse_markdown_with_diagnostics(&contents, &ast_options);
 
-    let contents: String =
-        std::fs::read_to_string(&input).expect("failed to read input file");
+    if diagnostics_json {
+        println!("{}", diagnostics_to_json(&diagnostics));
+        return Ok(None);
+    }
 
-    let ast = ast::parse_markdown_to_ast(&contents);
+    for diagnostic in &diagnostics {
+        eprintln!(
+            "warning: {}:{}:{}: {}",
+            input.display(),
+            diagnostic.line,
+            diagnostic.column,
+            diagnostic.message
+        );
+    }
 
-    /* For debugging.
-    println!("\n\n===== AST =====\n");
-    for block in &ast {
-        println!("block: {block:?}\n");
+    if verbose {
+        eprintln!("===== AST =====\n");
+        for block in &ast {
+            eprintln!("{block:#?}");
+        }
+        eprintln!("\n===== End AST =====\n");
     }
-    println!("\n\n===== End AST =====\n");
-    */
 
     //------------------------------------------------------------------
     // Parse the command-line options into notebook conversion `Options`
     //------------------------------------------------------------------
 
-    let nb_options = nb::Options {
-        create_external_language_cells: !no_external_language_cells,
+    let theme = match theme_file {
+        Some(path) => theme::Theme::load(&path).map_err(Error::Invalid)?,
+        None => theme::Theme::default(),
+    };
+
+    let current_dir = std::env::current_dir()
+        .map_err(Error::io("unable to determine the current directory"))?;
+
+    // Resolve relative image paths against the directory of the Markdown file, not
+    // the current directory of the Kernel.
+    let input_dir = match input.parent() {
+        Some(parent) if !is_stdin(&input) => current_dir.join(parent),
+        _ => current_dir.clone(),
+    };
+
+    let nb_options = nb::Options::builder()
+        .create_external_language_cells(!no_external_language_cells)
+        .preserve_tabs(preserve_tabs)
+        .page_width(page_width)
+        .heading_styles(
+            heading_style
+                .into_iter()
+                .map(|nb::HeadingStyle { level, style }| (level, style))
+                .collect(),
+        )
+        .language_map(
+            language_map
+                .into_iter()
+                .map(|nb::LanguageMapping { language, target }| (language, target))
+                .collect(),
+        )
+        .parse_wolfram_code(parse_wolfram_code)
+        .header_text(header_text)
+        .footer_text(footer_text)
+        .evaluator(evaluator)
+        .cell_auto_overwrite(cell_auto_overwrite)
+        .wrap_prose(wrap_prose)
+        .max_cell_chars(max_cell_chars)
+        .rule_style(rule_style)
+        .soft_break(soft_break)
+        .center_tables(center_tables)
+        .table_as_dataset(table_as_dataset)
+        .inline_code_background(inline_code_background)
+        .theme(theme)
+        .code_line_numbers(code_line_numbers)
+        .code_gutter(code_gutter)
+        .group_cells(group_cells)
+        .table_of_contents(table_of_contents)
+        .base_directory(Some(input_dir))
+        .build();
+
+    let options = md2nb::Options {
+        ast: ast_options,
+        nb: nb_options,
+        hero,
+        keep_going,
+        group_title: None,
     };
+    let nb_options = &options.nb;
 
     //-----------------------------------
     // Determine the output file location
     //-----------------------------------
 
-    // Make `output` into an absolute path. We need to resolve this relative to the
-    // current process's working directory, and before we pass it into the Wolfram Kernel
-    // process in NotebookSave.
-    let output = output.map(|output| output.canonicalize().unwrap());
+    if let Some(output_dir) = &output_dir {
+        if !output_dir.exists() && !dry_run {
+            std::fs::create_dir_all(output_dir).map_err(Error::io(format!(
+                "unable to create output directory {}",
+                output_dir.display()
+            )))?;
+        }
+    }
 
-    // If `output` is a directory, automatically determine the file name from `input`.
-    // E.g. `$ md2nb README.md` will automatically write to `./README.nb`.
-    let auto_file_name = format!("{}.nb", input.file_stem().unwrap().to_str().unwrap());
+    let output =
+        output_path(&input, output, output_dir, &current_dir).map_err(Error::Invalid)?;
 
-    let output = match output {
-        Some(output) if output.is_dir() => output.join(auto_file_name),
-        Some(output) => output,
-        None => std::env::current_dir().unwrap().join(auto_file_name),
+    // WXF output is serialized without a Kernel.
+    let no_kernel = no_kernel || is_wxf(&output);
+    if is_wxf(&output) && validate_notebook {
+        return Err(Error::Invalid(
+            "`--validate-notebook` can't be used with `.wxf` output".into(),
+        ));
+    }
+
+    // With `--split-by-heading`, each section is saved next to `output`, in a
+    // notebook named after the section heading.
+    let sections: Vec<(PathBuf, String, Vec<ast::Block>)> = match split_by_heading {
+        Some(level) => nb::split_by_heading(ast, level)
+            .into_iter()
+            .map(|(name, blocks)| {
+                let extension = if is_wxf(&output) { "wxf" } else { "nb" };
+                (
+                    output.with_file_name(format!("{name}.{extension}")),
+                    name,
+                    blocks,
+                )
+            })
+            .collect(),
+        None => {
+            // Input read from stdin has no file name, so use the output file name.
+            let title_path = if is_stdin(&input) { &output } else { &input };
+            let title = title_path
+                .file_stem()
+                .unwrap()
+                .to_string_lossy()
+                .into_owned();
+            vec![(output, title, ast)]
+        },
     };
 
-    // TODO: This has a TOCTOU race. `output` may not exist now, but another program
-    //       could create it before we do. Considering the startup time of the Kernel
-    //       and the time it takes to generate larger files, that span will often be
-    //       several seconds at least.
-    // TODO: Support an `--overwrite` or `-f, --force` option to disable this.
-    //       NotebookSave will overwrite by default.
-    if output.exists() {
-        panic!("error: output file already exists: {}", output.display())
+    // Besides the conversion options, these flags also change the saved notebooks.
+    let fingerprint = cache::fingerprint(
+        &contents,
+        &options,
+        (
+            split_by_heading,
+            wrap_in_group,
+            embed_markdown,
+            no_kernel,
+            export_notebook,
+        ),
+    );
+
+    if cache
+        && !dry_run
+        && sections
+            .iter()
+            .all(|(output, _, _)| cache::is_up_to_date(output, fingerprint))
+    {
+        for (output, _, _) in &sections {
+            println!("skipping: output is up to date: {}", output.display());
+        }
+        return Ok(None);
     }
 
+    // Create the output files now, instead of only checking that they don't exist.
+    // Considering the startup time of the Kernel and the time it takes to generate
+    // larger files, another program would otherwise often have several seconds to
+    // create an output file before we do.
+    let reservations: Vec<ReservedOutput> = if dry_run {
+        Vec::new()
+    } else {
+        sections
+            .iter()
+            .map(|(output, _, _)| ReservedOutput::reserve(output, force, cache))
+            .collect::<Result<_, _>>()
+            .map_err(Error::Invalid)?
+    };
+
     //----------------------------------------------------------------
     // Convert the Markdown AST to a sequence of Cell[..] expressions.
     //----------------------------------------------------------------
 
-    let cells: Vec<Expr> = ast
+    let notebooks: Vec<(PathBuf, Vec<Expr>)> = sections
         .into_iter()
-        .flat_map(|block| nb::block_to_cells(block, &nb_options))
-        .collect();
+        .map(|(output, title, blocks)| {
+            let options = md2nb::Options {
+                group_title: wrap_in_group.then_some(title),
+                ..options.clone()
+            };
 
-    //----------------------------------------------------------
-    // Launch the Kernel, and write the cells to a new notebook.
-    //----------------------------------------------------------
+            let mut cells = md2nb::blocks_to_cells(blocks, &options, |unsupported| {
+                eprintln!(
+                    "warning: {}: skipping unsupported Markdown: {unsupported}",
+                    input.display()
+                )
+            })
+            .map_err(|unsupported| {
+                Error::Invalid(format!(
+                    "{}: unsupported Markdown: {unsupported} (use --keep-going to \
+                     convert the rest of the document)",
+                    input.display()
+                ))
+            })?;
 
-    let mut kernel = launch_default_kernel()?;
+            if embed_markdown {
+                cells.insert(0, nb::raw_markdown_cell(&contents));
+            }
 
-    let nb_obj = create_notebook(&mut kernel)?;
+            Ok((output, cells))
+        })
+        .collect::<Result<_, Error>>()?;
 
-    for cell in cells {
-        // NotebookWrite[nb_obj, cell]
-        kernel
-            .link()
-            .put_eval_packet(&using_front_end(Expr::normal(
-                Symbol::new("System`NotebookWrite"),
-                vec![nb_obj.clone(), cell],
-            )))
-            .unwrap();
+    if dry_run {
+        print_cells(&mut io::stdout().lock(), &notebooks)
+            .map_err(Error::io("unable to write to stdout"))?;
+        return Ok(None);
     }
 
-    // NotebookSave[nb_obj, output]
-    kernel
-        .link()
-        .put_eval_packet(&using_front_end(Expr::normal(
-            Symbol::new("System`NotebookSave"),
-            vec![
-                nb_obj,
-                Expr::from(
-                    output
-                        .to_str()
-                        .expect("output file path cannot be converted to a &str"),
-                ),
-            ],
-        )))
-        .unwrap();
+    let (saved_notebooks, kernel_exited_cleanly) = if no_kernel {
+        (save_without_kernel(notebooks, nb_options), true)
+    } else {
+        // If the Kernel stops responding, kill it, remove the files that would
+        // otherwise be left behind, and exit.
+        let kernel_process = KernelProcess::default();
+
+        let partial_files: Vec<PathBuf> = reservations
+            .iter()
+            .filter(|reservation| reservation.created)
+            .map(|reservation| reservation.path.clone())
+            .chain(notebooks.iter().map(|(output, _)| temp_output_path(output)))
+            .collect();
+
+        let watchdog = Watchdog::start(timeout.map(Duration::from_secs), {
+            let kernel_process = kernel_process.clone();
+
+            move || {
+                eprintln!(
+                    "error: the Wolfram Kernel did not respond within {} seconds",
+                    timeout.unwrap_or_default()
+                );
+
+                kernel_process.kill();
+
+                for path in partial_files {
+                    let _ = std::fs::remove_file(path);
+                }
+
+                process::exit(1);
+            }
+        });
+
+        let kernel = match &link_name {
+            Some(name) => connect_to_kernel(name)?,
+            None => launch_kernel(kernel, &kernel_process)?,
+        };
+
+        save_with_kernel(
+            kernel.with_watchdog(watchdog),
+            notebooks,
+            nb_options,
+            validate_notebook,
+            export_notebook,
+        )?
+    };
+
+    let mut manifest_entries = Vec::new();
+    let mut failed = false;
+
+    for (
+        SavedNotebook {
+            output,
+            temp_output,
+            cell_count,
+            is_valid,
+        },
+        mut reservation,
+    ) in saved_notebooks.into_iter().zip(reservations)
+    {
+        let saved =
+            kernel_exited_cleanly && temp_output.is_file() && is_valid != Some(false);
+
+        // Close the reserved file, so that it can be replaced on all platforms.
+        reservation.close();
+
+        if let Err(err) = finish_output(&temp_output, &output, saved) {
+            eprintln!(
+                "error: unable to write output file {}: {err}",
+                output.display()
+            );
+            failed = true;
+            continue;
+        }
+
+        if saved {
+            reservation.keep();
+        }
+
+        match is_valid {
+            Some(true) => println!("validated notebook: {}", output.display()),
+            Some(false) => {
+                eprintln!("error: saved notebook is not valid: {}", output.display());
+                failed = true;
+                continue;
+            },
+            None => (),
+        }
+
+        if !saved {
+            eprintln!("error: notebook was not saved: {}", output.display());
+            failed = true;
+            continue;
+        }
+
+        if cache {
+            if let Err(err) = cache::record(&output, fingerprint) {
+                eprintln!("warning: unable to write conversion cache: {err}");
+            }
+        }
+
+        //----------------------------------------------------------------------------
+        // If `--open` was specified, open the output file in the default application.
+        //----------------------------------------------------------------------------
+
+        if open {
+            match open_command(&output) {
+                Some(command) => {
+                    if let Err(err) = run_open_command(command) {
+                        eprintln!("error: `--open` failed: {err}")
+                    }
+                },
+                None => eprintln!("warning: `--open` is not supported on this platform."),
+            }
+        }
+
+        manifest_entries.push(ManifestEntry {
+            input: input.clone(),
+            output,
+            cell_count,
+            warnings: diagnostics.clone(),
+        });
+    }
+
+    if !no_kernel {
+        unsafe {
+            // Shut the WSTP library down gracefully.
+            wstp::shutdown()?;
+        }
+    }
+
+    Ok(Some(Conversion {
+        entries: manifest_entries,
+        failed,
+    }))
+}
+
+/// Write `notebooks` to temporary output files using the Wolfram Kernel.
+///
+/// Returns the saved notebooks, and whether the Kernel exited cleanly after saving
+/// them. A Kernel connected to using `--link-name` is left running.
+fn save_with_kernel(
+    mut kernel: Kernel,
+    notebooks: Vec<(PathBuf, Vec<Expr>)>,
+    nb_options: &nb::Options,
+    validate_notebook: bool,
+    export_notebook: bool,
+) -> Result<(Vec<SavedNotebook>, bool), Error> {
+    //----------------------------------
+    // Write the cells to new notebooks.
+    //----------------------------------
+
+    let mut saved_notebooks: Vec<SavedNotebook> = Vec::new();
+
+    for (output, cells) in notebooks {
+        let cell_count = cells.len();
+
+        // Save to a temporary file, which is renamed to `output` only once the
+        // notebook has been completely written, so that a failed conversion never
+        // leaves a truncated notebook behind.
+        let temp_output = temp_output_path(&output);
+        let temp_output_str = temp_output.to_str().ok_or_else(|| {
+            Error::Invalid(format!(
+                "output file path is not valid UTF-8: {}",
+                temp_output.display()
+            ))
+        })?;
+
+        let pending_results = if export_notebook {
+            // Export[temp_output, Notebook[{cells...}, options...], "NB"]
+            //
+            // The Kernel evaluates the cells, like `NotebookWrite`, but the
+            // notebook is written without using the front end.
+            kernel.link().put_eval_packet(&Expr::normal(
+                Symbol::new("System`Export"),
+                vec![
+                    Expr::from(temp_output_str),
+                    nb::cells_to_notebook_expr(cells, nb_options),
+                    Expr::string("NB"),
+                ],
+            ))?;
+
+            1
+        } else {
+            let nb_obj = create_notebook(&mut kernel, nb::notebook_options(nb_options))?;
+
+            // NotebookWrite[nb_obj, {cells...}]
+            let write_packets = notebook_write_exprs(&nb_obj, cells);
+            let write_count = write_packets.len();
+
+            for packet in write_packets {
+                kernel.link().put_eval_packet(&using_front_end(packet))?;
+            }
+
+            // NotebookSave[nb_obj, temp_output]
+            kernel
+                .link()
+                .put_eval_packet(&using_front_end(Expr::normal(
+                    Symbol::new("System`NotebookSave"),
+                    vec![nb_obj, Expr::from(temp_output_str)],
+                )))?;
+
+            write_count + 1
+        };
+
+        // The results of writing the notebook haven't been read yet.
+        let is_valid = if validate_notebook {
+            Some(check_notebook_file(
+                &mut kernel,
+                &temp_output,
+                pending_results,
+            )?)
+        } else {
+            // Read the results before creating the next notebook.
+            discard_results(&mut kernel, pending_results)?;
+            None
+        };
+
+        saved_notebooks.push(SavedNotebook {
+            output,
+            temp_output,
+            cell_count,
+            is_valid,
+        });
+    }
+
+    // A Kernel we connected to is left running for later conversions. All of the
+    // results have been read, so the notebooks have been saved.
+    if let KernelConnection::Connected(_) = kernel.connection {
+        return Ok((saved_notebooks, true));
+    }
 
     //-----------------------------------------------------
     // Send `Quit[]` to the Kernel and wait for it to exit.
     //-----------------------------------------------------
 
-    kernel
-        .link()
-        .put_eval_packet(&Expr::from(Expr::normal(
-            Symbol::new("System`Quit"),
-            vec![],
-        )))
-        .unwrap();
+    kernel.link().put_eval_packet(&Expr::from(Expr::normal(
+        Symbol::new("System`Quit"),
+        vec![],
+    )))?;
 
     // Wait until the Kernel has shut down before proceeding.
     // If we don't wait for the previous evaluations to finish, then the output
     // file may not have been written yet if we try to `--open` it below.
+    let mut kernel_exited_cleanly = true;
+
     loop {
         match kernel.link().get_token() {
             Ok(_) => (),
             Err(err) => {
                 if err.code() != Some(wstp::sys::WSECLOSED) {
                     println!("error: unexpected Kernel WSTP connection error: {err}");
+                    kernel_exited_cleanly = false;
                 }
                 break;
             },
@@ -164,91 +959,653 @@ fn main() -> Result<(), kernel::Error> {
 
     drop(kernel);
 
-    //----------------------------------------------------------------------------
-    // If `--open` was specified, open the output file in the default application.
-    //----------------------------------------------------------------------------
+    Ok((saved_notebooks, kernel_exited_cleanly))
+}
 
-    if open {
-        if cfg!(target_os = "macos") {
-            if let Err(err) = process::Command::new("open").arg(&output).output() {
-                eprintln!("error: `--open` failed: {err}")
-            }
-        } else {
-            eprintln!("warning: `--open` is not supported on this platform.")
+/// Print the cells of `notebooks` to `out` in InputForm, one cell per line, for
+/// `--dry-run`.
+///
+/// If there are several notebooks, e.g. with `--split-by-heading`, the cells of each
+/// notebook are preceded by a comment containing the notebook's output path.
+fn print_cells(
+    out: &mut impl io::Write,
+    notebooks: &[(PathBuf, Vec<Expr>)],
+) -> io::Result<()> {
+    for (output, cells) in notebooks {
+        if notebooks.len() > 1 {
+            writeln!(out, "(* {} *)", output.display())?;
         }
-    }
 
-    unsafe {
-        // Shut the WSTP library down gracefully.
-        wstp::shutdown()?;
+        for cell in cells {
+            write!(out, "{}", nb::write_notebook_string(cell))?;
+        }
     }
 
     Ok(())
 }
 
-fn using_front_end(expr: Expr) -> Expr {
-    Expr::normal(Symbol::new("System`UsingFrontEnd"), vec![expr])
-}
-
-fn create_notebook(kernel: &mut WolframKernelProcess) -> Result<Expr, kernel::Error> {
-    let () = kernel
-        .link()
-        .put_eval_packet(&using_front_end(Expr::normal(
-            Symbol::new("System`CreateNotebook"),
-            vec![],
-        )))?;
+/// Write `notebooks` to temporary `.nb` or `.wxf` output files, without using the
+/// Wolfram Kernel.
+fn save_without_kernel(
+    notebooks: Vec<(PathBuf, Vec<Expr>)>,
+    nb_options: &nb::Options,
+) -> Vec<SavedNotebook> {
+    notebooks
+        .into_iter()
+        .map(|(output, cells)| {
+            if cells.iter().any(nb::requires_kernel) {
+                eprintln!(
+                    "warning: images, math, and other content converted by the Wolfram \
+                     Kernel are not displayed correctly with `--no-kernel` or `.wxf` \
+                     output: {}",
+                    output.display()
+                );
+            }
 
-    skip_to_next_return_packet(kernel.link())?;
+            let cell_count = cells.len();
 
-    Ok(get_system_expr(kernel.link())?)
-}
+            let contents = if is_wxf(&output) {
+                nb::cells_to_wxf(cells, nb_options)
+            } else {
+                let notebook = nb::cells_to_notebook_expr(cells, nb_options);
+                nb::write_notebook_string(&notebook).into_bytes()
+            };
 
-fn launch_default_kernel() -> Result<WolframKernelProcess, kernel::Error> {
-    let app = WolframApp::try_default()
-        .expect("unable to find any Wolfram Language installations");
+            let temp_output = temp_output_path(&output);
 
-    let kernel = app.kernel_executable_path().unwrap();
+            if let Err(err) = std::fs::write(&temp_output, contents) {
+                eprintln!(
+                    "error: unable to write notebook file {}: {err}",
+                    temp_output.display()
+                );
+            }
 
-    WolframKernelProcess::launch(&kernel)
+            SavedNotebook {
+                output,
+                temp_output,
+                cell_count,
+                is_valid: None,
+            }
+        })
+        .collect()
 }
 
-fn skip_to_next_return_packet(link: &mut wstp::Link) -> Result<(), wstp::Error> {
-    use wstp::sys::*;
+/// Determine the location of the output notebook file.
+///
+/// The returned path is absolute. We need to resolve relative paths against the
+/// current process's working directory before we pass them into the Wolfram Kernel
+/// process in NotebookSave.
+fn output_path(
+    input: &Path,
+    output: Option<PathBuf>,
+    output_dir: Option<PathBuf>,
+    current_dir: &Path,
+) -> Result<PathBuf, String> {
+    // If the output is a directory, automatically determine the file name from `input`.
+    // E.g. `$ md2nb README.md` will automatically write to `./README.nb`.
+    let auto_file_name = || {
+        if is_stdin(input) {
+            return Err(
+                "an <OUTPUT> file must be specified when reading from stdin (`-`)"
+                    .to_owned(),
+            );
+        }
 
-    loop {
-        match link.raw_next_packet()? {
-            RETURNPKT => break,
-            _pkt => {
-                // println!("\npacket: {pkt}");
-                // dump_tokens(link, 0).unwrap();
-                let () = link.new_packet()?;
-                continue;
-            },
+        match input.file_stem() {
+            Some(stem) => Ok(format!("{}.nb", stem.to_string_lossy())),
+            None => Err(format!(
+                "unable to determine output file name from input: {}",
+                input.display()
+            )),
         }
+    };
+
+    let output = output.map(|output| current_dir.join(output));
+    let output_dir = output_dir.map(|output_dir| current_dir.join(output_dir));
+
+    match (output, output_dir) {
+        (Some(_), Some(_)) => {
+            Err("<OUTPUT> and --output-dir cannot both be specified".to_owned())
+        },
+        (None, Some(output_dir)) => {
+            if output_dir.exists() && !output_dir.is_dir() {
+                return Err(format!(
+                    "--output-dir is not a directory: {}",
+                    output_dir.display()
+                ));
+            }
+
+            Ok(output_dir.join(auto_file_name()?))
+        },
+        (Some(output), None) if output.is_dir() => Ok(output.join(auto_file_name()?)),
+        (Some(output), None) => Ok(output),
+        (None, None) => Ok(current_dir.join(auto_file_name()?)),
     }
+}
 
-    Ok(())
+/// Returns `true` if the notebook should be written to `output` in the Wolfram
+/// Exchange Format, instead of as a `.nb` file.
+fn is_wxf(output: &Path) -> bool {
+    output.extension() == Some("wxf".as_ref())
 }
 
-fn get_system_expr(link: &mut wstp::Link) -> Result<Expr, wstp::Error> {
-    link.get_expr_with_resolver(&mut |sym: &str| {
-        let abs = format!("System`{sym}");
-        Some(Symbol::try_new(&abs).expect("unexpected WSTP symbol syntax"))
-    })
+/// Returns `true` if `input` is `-`, meaning that the Markdown is read from stdin.
+fn is_stdin(input: &Path) -> bool {
+    input == Path::new("-")
 }
 
-/// Read all remaining data on the link and debug print it.
-#[allow(dead_code)]
-fn dump_tokens(link: &mut wstp::Link, indent: usize) -> Result<(), wstp::Error> {
-    use wstp::Token;
+/// An output file created before the notebook is generated, so that no other program
+/// can create it in the meantime.
+///
+/// The notebook is saved to a temporary file, which replaces the reserved file once
+/// it is complete. If the reserved file was created by md2nb, it is removed again
+/// unless [`ReservedOutput::keep()`] is called.
+struct ReservedOutput {
+    path: PathBuf,
+    file: Option<File>,
+    created: bool,
+}
 
-    let pad = format!("{:indent$}", "");
+impl ReservedOutput {
+    /// Create `output`, or return an error if it already exists and shouldn't be
+    /// overwritten.
+    ///
+    /// Existing files are only overwritten if `force` is set, or if the notebook was
+    /// previously generated with `--cache` and `cache` is set.
+    fn reserve(output: &Path, force: bool, cache: bool) -> Result<Self, String> {
+        let overwrite = force || (cache && cache::cache_path(output).is_file());
 
-    let token = link.get_token()?;
+        let mut options = OpenOptions::new();
+        options.write(true);
 
-    match token {
-        Token::Integer(value) => println!("token: {pad}{value}"),
-        Token::Real(value) => println!("token: {pad}{value}"),
+        let created = if overwrite {
+            options.create(true);
+            !output.exists()
+        } else {
+            options.create_new(true);
+            true
+        };
+
+        let file = options.open(output).map_err(|err| match err.kind() {
+            io::ErrorKind::AlreadyExists => format!(
+                "output file already exists: {} (use --force to overwrite it)",
+                output.display()
+            ),
+            _ => format!("unable to create output file {}: {err}", output.display()),
+        })?;
+
+        Ok(ReservedOutput {
+            path: output.to_owned(),
+            file: Some(file),
+            created,
+        })
+    }
+
+    /// Close the reserved file, without giving up the reservation.
+    fn close(&mut self) {
+        self.file = None;
+    }
+
+    /// Keep the output file, now that the notebook has been moved to it.
+    fn keep(mut self) {
+        self.created = false;
+    }
+}
+
+impl Drop for ReservedOutput {
+    fn drop(&mut self) {
+        self.close();
+
+        if self.created {
+            let _ = std::fs::remove_file(&self.path);
+        }
+    }
+}
+
+/// Returns the command that opens `path` in the default application for its file
+/// type, or `None` if `--open` isn't supported on this platform.
+fn open_command(path: &Path) -> Option<process::Command> {
+    let mut command = if cfg!(target_os = "macos") {
+        process::Command::new("open")
+    } else if cfg!(target_os = "linux") {
+        process::Command::new("xdg-open")
+    } else if cfg!(target_os = "windows") {
+        // The empty argument is the window title. Otherwise, `start` would use a
+        // quoted path as the title.
+        let mut command = process::Command::new("cmd");
+        command.args(["/C", "start", ""]);
+        command
+    } else {
+        return None;
+    };
+
+    command.arg(path);
+
+    Some(command)
+}
+
+/// Run a command returned by [`open_command()`], and wait for it to finish.
+fn run_open_command(mut command: process::Command) -> Result<(), String> {
+    let program = command.get_program().to_string_lossy().into_owned();
+
+    let output = command.output().map_err(|err| match err.kind() {
+        io::ErrorKind::NotFound => format!("`{program}` command was not found"),
+        _ => format!("unable to run `{program}`: {err}"),
+    })?;
+
+    if !output.status.success() {
+        return Err(format!(
+            "`{program}` failed ({}): {}",
+            output.status,
+            String::from_utf8_lossy(&output.stderr).trim()
+        ));
+    }
+
+    Ok(())
+}
+
+/// Returns the path of the temporary file the notebook is saved to before it is moved
+/// to `output`.
+///
+/// The temporary file is in the same directory as `output`, so that it can be renamed
+/// atomically.
+fn temp_output_path(output: &Path) -> PathBuf {
+    let file_name = output
+        .file_name()
+        .expect("output path has no file name")
+        .to_string_lossy();
+
+    output.with_file_name(format!(".{file_name}.md2nb-tmp"))
+}
+
+/// Move the notebook saved to `temp_output` to `output` if it was `saved`
+/// successfully, or otherwise remove any partially written `temp_output`.
+fn finish_output(temp_output: &Path, output: &Path, saved: bool) -> std::io::Result<()> {
+    if saved {
+        return std::fs::rename(temp_output, output);
+    }
+
+    match std::fs::remove_file(temp_output) {
+        Err(err) if err.kind() != std::io::ErrorKind::NotFound => Err(err),
+        _ => Ok(()),
+    }
+}
+
+/// Format `diagnostics` as a JSON array of `{line, column, kind, message}` objects.
+fn diagnostics_to_json(diagnostics: &[ast::Diagnostic]) -> String {
+    let objects: Vec<String> = diagnostics
+        .iter()
+        .map(|diagnostic| {
+            format!(
+                r#"{{"line": {}, "column": {}, "kind": {}, "message": {}}}"#,
+                diagnostic.line,
+                diagnostic.column,
+                json_string(diagnostic.kind.as_str()),
+                json_string(&diagnostic.message)
+            )
+        })
+        .collect();
+
+    format!("[{}]", objects.join(", "))
+}
+
+/// Format `entries` as a JSON array of `{input, output, cells, warnings}` objects.
+fn manifest_to_json(entries: &[ManifestEntry]) -> String {
+    let objects: Vec<String> = entries
+        .iter()
+        .map(|entry| {
+            format!(
+                r#"{{"input": {}, "output": {}, "cells": {}, "warnings": {}}}"#,
+                json_string(&entry.input.to_string_lossy()),
+                json_string(&entry.output.to_string_lossy()),
+                entry.cell_count,
+                diagnostics_to_json(&entry.warnings)
+            )
+        })
+        .collect();
+
+    format!("[{}]", objects.join(", "))
+}
+
+fn json_string(string: &str) -> String {
+    let mut json = String::from('"');
+
+    for c in string.chars() {
+        match c {
+            '"' => json.push_str("\\\""),
+            '\\' => json.push_str("\\\\"),
+            '\n' => json.push_str("\\n"),
+            '\r' => json.push_str("\\r"),
+            '\t' => json.push_str("\\t"),
+            c if c.is_control() => json.push_str(&format!("\\u{:04x}", c as u32)),
+            c => json.push(c),
+        }
+    }
+
+    json.push('"');
+    json
+}
+
+/// The maximum number of cells written by each `NotebookWrite[..]` evaluation.
+///
+/// Writing cells in batches avoids a round trip to the Kernel for every cell, while
+/// keeping each WSTP packet reasonably small.
+const NOTEBOOK_WRITE_BATCH_SIZE: usize = 100;
+
+/// Returns the `NotebookWrite[nb_obj, {cells...}]` expressions that write `cells`
+/// to `nb_obj`, in order.
+fn notebook_write_exprs(nb_obj: &Expr, cells: Vec<Expr>) -> Vec<Expr> {
+    cells
+        .chunks(NOTEBOOK_WRITE_BATCH_SIZE)
+        .map(|batch| {
+            Expr::normal(
+                Symbol::new("System`NotebookWrite"),
+                vec![nb_obj.clone(), Expr::list(batch.to_vec())],
+            )
+        })
+        .collect()
+}
+
+fn using_front_end(expr: Expr) -> Expr {
+    Expr::normal(Symbol::new("System`UsingFrontEnd"), vec![expr])
+}
+
+fn create_notebook(
+    kernel: &mut Kernel,
+    options: Vec<Expr>,
+) -> Result<Expr, kernel::Error> {
+    // CreateNotebook["Default", options...]
+    let mut args = vec![Expr::string("Default")];
+    args.extend(options);
+
+    let () = kernel
+        .link()
+        .put_eval_packet(&using_front_end(Expr::normal(
+            Symbol::new("System`CreateNotebook"),
+            args,
+        )))?;
+
+    kernel.skip_to_next_return_packet()?;
+
+    Ok(get_system_expr(kernel.link())?)
+}
+
+/// Returns `true` if the file at `path` can be loaded with `Get` as a `Notebook[..]`
+/// expression.
+///
+/// `pending_results` is the number of previously sent evaluations whose results have
+/// not been read from the link yet. Those results are discarded.
+fn check_notebook_file(
+    kernel: &mut Kernel,
+    path: &Path,
+    pending_results: usize,
+) -> Result<bool, kernel::Error> {
+    discard_results(kernel, pending_results)?;
+
+    let path = path
+        .to_str()
+        .expect("output file path cannot be converted to a &str");
+
+    // Quiet[Check[MatchQ[Get[path], _Notebook], False]]
+    let () = kernel.link().put_eval_packet(&Expr::normal(
+        Symbol::new("System`Quiet"),
+        vec![Expr::normal(
+            Symbol::new("System`Check"),
+            vec![
+                Expr::normal(
+                    Symbol::new("System`MatchQ"),
+                    vec![
+                        Expr::normal(Symbol::new("System`Get"), vec![Expr::from(path)]),
+                        Expr::normal(
+                            Symbol::new("System`Blank"),
+                            vec![Expr::from(Symbol::new("System`Notebook"))],
+                        ),
+                    ],
+                ),
+                Expr::from(Symbol::new("System`False")),
+            ],
+        )],
+    ))?;
+
+    kernel.skip_to_next_return_packet()?;
+
+    let result = get_system_expr(kernel.link())?;
+
+    Ok(result.try_symbol() == Some(&Symbol::new("System`True")))
+}
+
+/// Read and discard the results of `count` previously sent evaluations.
+fn discard_results(kernel: &mut Kernel, count: usize) -> Result<(), wstp::Error> {
+    for _ in 0..count {
+        kernel.skip_to_next_return_packet()?;
+        let () = kernel.link().new_packet()?;
+    }
+
+    Ok(())
+}
+
+/// A connection to a Wolfram Kernel.
+struct Kernel {
+    connection: KernelConnection,
+    /// Reset each time the link is used, so that it only times out if the Kernel
+    /// stops responding.
+    watchdog: Watchdog,
+}
+
+/// A Wolfram Kernel launched by `md2nb`, or a running Kernel connected to using
+/// `--link-name`.
+enum KernelConnection {
+    Launched(wstp::Link),
+    Connected(wstp::Link),
+}
+
+/// Shared handle to a Wolfram Kernel child process launched by [`launch_kernel`].
+///
+/// Unlike `wstp::kernel::WolframKernelProcess`, the process can be killed from
+/// another thread, e.g. by the [`Watchdog`] while the main thread is blocked reading
+/// from the Kernel.
+#[derive(Clone, Default)]
+struct KernelProcess(Arc<Mutex<Option<process::Child>>>);
+
+impl KernelProcess {
+    fn set(&self, child: process::Child) {
+        *self.0.lock().unwrap_or_else(|err| err.into_inner()) = Some(child);
+    }
+
+    /// Kill the Kernel process and wait for it to exit. Does nothing if no process
+    /// has been launched.
+    fn kill(&self) {
+        let child = self.0.lock().unwrap_or_else(|err| err.into_inner()).take();
+
+        if let Some(mut child) = child {
+            let _ = child.kill();
+            let _ = child.wait();
+        }
+    }
+}
+
+impl Kernel {
+    fn new(connection: KernelConnection) -> Self {
+        Kernel {
+            connection,
+            watchdog: Watchdog::start(None, || ()),
+        }
+    }
+
+    fn with_watchdog(self, watchdog: Watchdog) -> Self {
+        Kernel { watchdog, ..self }
+    }
+
+    fn link(&mut self) -> &mut wstp::Link {
+        self.watchdog.reset();
+
+        match &mut self.connection {
+            KernelConnection::Launched(link) | KernelConnection::Connected(link) => link,
+        }
+    }
+
+    /// Read and discard packets until the next `ReturnPacket[..]`.
+    ///
+    /// The watchdog is reset for each packet, so that an evaluation that is still
+    /// sending e.g. messages or `Print[..]` output doesn't time out.
+    fn skip_to_next_return_packet(&mut self) -> Result<(), wstp::Error> {
+        loop {
+            match self.link().raw_next_packet()? {
+                wstp::sys::RETURNPKT => return Ok(()),
+                _ => {
+                    let () = self.link().new_packet()?;
+                },
+            }
+        }
+    }
+}
+
+/// Launch the Wolfram Kernel chosen by [`kernel_executable`], storing the child
+/// process in `process`.
+fn launch_kernel(
+    kernel: Option<PathBuf>,
+    process: &KernelProcess,
+) -> Result<Kernel, Error> {
+    // Note: This is the same as `WolframKernelProcess::launch()`, which doesn't
+    //       provide access to the child process, except that each launch uses a
+    //       different link name, so that concurrent launches don't collide.
+    let name = unique_link_name();
+
+    let kernel = kernel_executable(kernel, std::env::var_os("MD2NB_KERNEL"))?;
+
+    let listener = thread::spawn({
+        let name = name.clone();
+
+        // This will block until the Kernel connects.
+        move || wstp::Link::listen(wstp::Protocol::SharedMemory, &name)
+    });
+
+    let child = process::Command::new(&kernel)
+        .args(["-wstp", "-linkprotocol", "SharedMemory", "-linkconnect"])
+        .args(["-linkname", &name])
+        .spawn()
+        .map_err(Error::io(format!(
+            "unable to launch Wolfram Kernel {}",
+            kernel.display()
+        )))?;
+
+    process.set(child);
+
+    let link = listener.join().map_err(|_| {
+        Error::Invalid(
+            "unable to launch Wolfram Kernel: listening thread panicked".into(),
+        )
+    })??;
+
+    Ok(Kernel::new(KernelConnection::Launched(link)))
+}
+
+/// Returns a shared memory link name that is not used by any other Kernel launch.
+///
+/// The name includes the process id and a per-process launch counter. A random suffix
+/// avoids collisions with links left behind by an earlier process with the same id.
+fn unique_link_name() -> String {
+    static LAUNCH_COUNT: AtomicUsize = AtomicUsize::new(0);
+
+    let random = RandomState::new().build_hasher().finish();
+
+    format!(
+        "md2nb-{}-{}-{random:016x}",
+        process::id(),
+        LAUNCH_COUNT.fetch_add(1, Ordering::Relaxed)
+    )
+}
+
+/// Returns the `WolframKernel` executable to launch: `kernel` (from `--kernel`) if
+/// set, then `env_kernel` (from `MD2NB_KERNEL`) if set, and otherwise the Kernel of
+/// the default Wolfram Language installation.
+fn kernel_executable(
+    kernel: Option<PathBuf>,
+    env_kernel: Option<OsString>,
+) -> Result<PathBuf, Error> {
+    let (kernel, source) = match (kernel, env_kernel) {
+        (Some(kernel), _) => (kernel, "--kernel"),
+        (None, Some(env_kernel)) if !env_kernel.is_empty() => {
+            (PathBuf::from(env_kernel), "MD2NB_KERNEL")
+        },
+        (None, _) => return default_kernel_executable(),
+    };
+
+    if !kernel.is_file() {
+        return Err(Error::Invalid(format!(
+            "{source} path does not exist: {}",
+            kernel.display()
+        )));
+    }
+
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+
+        let mode = std::fs::metadata(&kernel)
+            .map_err(Error::io(format!(
+                "unable to read {source} path {}",
+                kernel.display()
+            )))?
+            .permissions()
+            .mode();
+
+        if mode & 0o111 == 0 {
+            return Err(Error::Invalid(format!(
+                "{source} path is not executable: {}",
+                kernel.display()
+            )));
+        }
+    }
+
+    Ok(kernel)
+}
+
+/// Returns the path to the `WolframKernel` executable of the default Wolfram
+/// Language installation.
+fn default_kernel_executable() -> Result<PathBuf, Error> {
+    let app = WolframApp::try_default().map_err(Error::WolframAppNotFound)?;
+
+    app.kernel_executable_path().map_err(|err| {
+        Error::Invalid(format!(
+            "unable to locate the WolframKernel executable: {err}"
+        ))
+    })
+}
+
+/// Connect to a Wolfram Kernel listening on the WSTP link named `link_name`.
+fn connect_to_kernel(link_name: &str) -> Result<Kernel, kernel::Error> {
+    let protocol = if link_name.contains('@') {
+        wstp::Protocol::TCPIP
+    } else {
+        wstp::Protocol::SharedMemory
+    };
+
+    let mut link = wstp::Link::connect(protocol, link_name)?;
+    link.activate()?;
+
+    Ok(Kernel::new(KernelConnection::Connected(link)))
+}
+
+fn get_system_expr(link: &mut wstp::Link) -> Result<Expr, wstp::Error> {
+    link.get_expr_with_resolver(&mut |sym: &str| {
+        let abs = format!("System`{sym}");
+        Some(Symbol::try_new(&abs).expect("unexpected WSTP symbol syntax"))
+    })
+}
+
+/// Read all remaining data on the link and debug print it.
+#[allow(dead_code)]
+fn dump_tokens(link: &mut wstp::Link, indent: usize) -> Result<(), wstp::Error> {
+    use wstp::Token;
+
+    let pad = format!("{:indent$}", "");
+
+    let token = link.get_token()?;
+
+    match token {
+        Token::Integer(value) => println!("token: {pad}{value}"),
+        Token::Real(value) => println!("token: {pad}{value}"),
         Token::String(value) => println!("token: {pad}{}", value.as_str()),
         Token::Symbol(value) => println!("token: {pad}{}", value.as_str()),
         Token::Function { length } => {
@@ -264,3 +1621,590 @@ fn dump_tokens(link: &mut wstp::Link, indent: usize) -> Result<(), wstp::Error>
 
     Ok(())
 }
+
+//======================================
+// Tests
+//======================================
+
+#[test]
+fn test_cli_args() {
+    use clap::CommandFactory;
+
+    Args::command().debug_assert();
+
+    // `--split-by-heading` defaults to H1, and doesn't consume the input file name.
+    let args = Args::try_parse_from(["md2nb", "--split-by-heading", "in.md"]).unwrap();
+    assert_eq!(args.split_by_heading, Some(HeadingLevel::H1));
+    assert_eq!(args.input, PathBuf::from("in.md"));
+
+    let args = Args::try_parse_from(["md2nb", "--split-by-heading=H2", "in.md"]).unwrap();
+    assert_eq!(args.split_by_heading, Some(HeadingLevel::H2));
+}
+
+#[test]
+fn test_error_message() {
+    let err = std::fs::read_to_string("does/not/exist.md")
+        .map_err(Error::io("unable to read input file does/not/exist.md"))
+        .unwrap_err();
+
+    assert!(matches!(err, Error::Io { .. }));
+    assert!(err
+        .to_string()
+        .starts_with("unable to read input file does/not/exist.md: "));
+
+    assert_eq!(
+        Error::Invalid("output file already exists".into()).to_string(),
+        "output file already exists"
+    );
+}
+
+#[test]
+fn test_wolfram_app_not_found() {
+    // Discovery uses the installation in WOLFRAM_APP_DIRECTORY when it is set, and
+    // fails if it doesn't exist.
+    std::env::set_var("WOLFRAM_APP_DIRECTORY", "/md2nb/does/not/exist");
+    let result = default_kernel_executable();
+    std::env::remove_var("WOLFRAM_APP_DIRECTORY");
+
+    let err = match result {
+        Err(err @ Error::WolframAppNotFound(_)) => err,
+        Err(err) => panic!("unexpected error: {err}"),
+        Ok(path) => panic!("found a Kernel in a non-existent installation: {path:?}"),
+    };
+
+    let message = err.to_string();
+    assert!(message.starts_with("unable to find a Wolfram Language installation"));
+    assert!(message.contains("WOLFRAM_APP_DIRECTORY"));
+    assert!(message.contains("wolfram-app-discovery"));
+    assert!(!message.contains('\n'));
+}
+
+#[test]
+#[cfg(unix)]
+fn test_kernel_process_kill() {
+    use std::time::Instant;
+
+    // Killing before a process is launched does nothing.
+    let kernel_process = KernelProcess::default();
+    kernel_process.kill();
+
+    // A Kernel that never exits on its own.
+    kernel_process.set(process::Command::new("sleep").arg("60").spawn().unwrap());
+
+    let start = Instant::now();
+    kernel_process.clone().kill();
+
+    assert!(start.elapsed() < Duration::from_secs(30));
+    assert!(kernel_process.0.lock().unwrap().is_none());
+}
+
+#[test]
+fn test_unique_link_name() {
+    let first = unique_link_name();
+    let second = unique_link_name();
+
+    assert_ne!(first, second);
+    assert!(first.starts_with(&format!("md2nb-{}-", process::id())));
+}
+
+#[test]
+#[ignore = "requires a Wolfram Kernel"]
+fn test_concurrent_kernel_launch() {
+    let launches: Vec<_> = (0..2)
+        .map(|_| {
+            thread::spawn(|| {
+                let kernel_process = KernelProcess::default();
+                let mut kernel = launch_kernel(None, &kernel_process).unwrap();
+
+                let () = kernel
+                    .link()
+                    .put_eval_packet(&Expr::normal(
+                        Symbol::new("System`Plus"),
+                        vec![Expr::from(1), Expr::from(2)],
+                    ))
+                    .unwrap();
+                kernel.skip_to_next_return_packet().unwrap();
+                let result = get_system_expr(kernel.link()).unwrap();
+
+                kernel_process.kill();
+
+                result
+            })
+        })
+        .collect();
+
+    for launch in launches {
+        assert_eq!(launch.join().unwrap(), Expr::from(3));
+    }
+}
+
+#[test]
+#[cfg(unix)]
+fn test_kernel_executable() {
+    use std::os::unix::fs::PermissionsExt;
+
+    use pretty_assertions::assert_eq;
+
+    let dir = std::env::temp_dir().join(format!("md2nb-kernel-test-{}", process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+
+    let cli_kernel = dir.join("CliKernel");
+    let env_kernel = dir.join("EnvKernel");
+    for kernel in [&cli_kernel, &env_kernel] {
+        std::fs::write(kernel, "#!/bin/sh\n").unwrap();
+        std::fs::set_permissions(kernel, std::fs::Permissions::from_mode(0o755)).unwrap();
+    }
+
+    let kernel = |cli: Option<&Path>, env: Option<&Path>| {
+        kernel_executable(
+            cli.map(Path::to_path_buf),
+            env.map(|path| path.as_os_str().to_owned()),
+        )
+        .map_err(|err| err.to_string())
+    };
+
+    // `--kernel` takes precedence over `MD2NB_KERNEL`.
+    assert_eq!(
+        kernel(Some(&cli_kernel), Some(&env_kernel)),
+        Ok(cli_kernel.clone())
+    );
+    assert_eq!(kernel(None, Some(&env_kernel)), Ok(env_kernel.clone()));
+
+    let missing = dir.join("Missing");
+    assert_eq!(
+        kernel(Some(&missing), Some(&env_kernel)),
+        Err(format!(
+            "--kernel path does not exist: {}",
+            missing.display()
+        ))
+    );
+
+    std::fs::set_permissions(&env_kernel, std::fs::Permissions::from_mode(0o644))
+        .unwrap();
+    assert_eq!(
+        kernel(None, Some(&env_kernel)),
+        Err(format!(
+            "MD2NB_KERNEL path is not executable: {}",
+            env_kernel.display()
+        ))
+    );
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_print_cells() {
+    use pretty_assertions::assert_eq;
+
+    let cells: Vec<Expr> = ast::parse_markdown_to_ast("# Title\n\nSome *text*.")
+        .into_iter()
+        .flat_map(|block| nb::block_to_cells(block, &nb::Options::default()))
+        .collect();
+
+    let mut out = Vec::new();
+    print_cells(&mut out, &[(PathBuf::from("doc.nb"), cells.clone())]).unwrap();
+
+    assert_eq!(
+        String::from_utf8(out).unwrap(),
+        concat!(
+            "Cell[TextData[RowBox[{\"Title\"}]], \"Title\"]\n",
+            "Cell[TextData[RowBox[{\"Some \", StyleBox[\"text\", FontSlant -> \"Italic\"], \
+             \".\"}]], \"Text\"]\n",
+        )
+    );
+
+    // Each notebook's cells are labeled with its output path.
+    let mut out = Vec::new();
+    print_cells(
+        &mut out,
+        &[
+            (PathBuf::from("a.nb"), cells[..1].to_vec()),
+            (PathBuf::from("b.nb"), Vec::new()),
+        ],
+    )
+    .unwrap();
+
+    assert_eq!(
+        String::from_utf8(out).unwrap(),
+        "(* a.nb *)\nCell[TextData[RowBox[{\"Title\"}]], \"Title\"]\n(* b.nb *)\n"
+    );
+}
+
+#[test]
+fn test_output_path() {
+    use pretty_assertions::assert_eq;
+
+    let dir = std::env::temp_dir().join(format!("md2nb-output-test-{}", process::id()));
+    let out_dir = dir.join("out");
+    std::fs::create_dir_all(&out_dir).unwrap();
+
+    let input = Path::new("docs/README.md");
+
+    // Default: next to the current directory.
+    assert_eq!(
+        output_path(input, None, None, &dir),
+        Ok(dir.join("README.nb"))
+    );
+
+    // --output-dir: always a directory, even if it doesn't exist yet.
+    assert_eq!(
+        output_path(input, None, Some(out_dir.clone()), &dir),
+        Ok(out_dir.join("README.nb"))
+    );
+    assert_eq!(
+        output_path(input, None, Some(PathBuf::from("new")), &dir),
+        Ok(dir.join("new/README.nb"))
+    );
+
+    // --output-dir cannot be an existing file.
+    std::fs::write(dir.join("file"), "").unwrap();
+    assert!(output_path(input, None, Some(PathBuf::from("file")), &dir).is_err());
+
+    // <OUTPUT> may be either a directory or a file.
+    assert_eq!(
+        output_path(input, Some(PathBuf::from("out")), None, &dir),
+        Ok(out_dir.join("README.nb"))
+    );
+    assert_eq!(
+        output_path(input, Some(PathBuf::from("out/Other.nb")), None, &dir),
+        Ok(out_dir.join("Other.nb"))
+    );
+
+    assert!(
+        output_path(input, Some(out_dir.clone()), Some(out_dir.clone()), &dir).is_err()
+    );
+
+    // Input from stdin requires an explicit output file.
+    let stdin = Path::new("-");
+    assert_eq!(
+        output_path(stdin, Some(PathBuf::from("out/Piped.nb")), None, &dir),
+        Ok(out_dir.join("Piped.nb"))
+    );
+    assert!(output_path(stdin, None, None, &dir).is_err());
+    assert!(output_path(stdin, None, Some(out_dir.clone()), &dir).is_err());
+    assert!(output_path(stdin, Some(out_dir), None, &dir).is_err());
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_reserve_output() {
+    let dir = std::env::temp_dir().join(format!("md2nb-reserve-test-{}", process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+
+    let output = dir.join("out.nb");
+    let _ = std::fs::remove_file(&output);
+    let _ = std::fs::remove_file(cache::cache_path(&output));
+
+    // A reserved file is removed again if it isn't kept.
+    let reservation = ReservedOutput::reserve(&output, false, false).unwrap();
+    assert!(output.is_file());
+    assert!(ReservedOutput::reserve(&output, false, false).is_err());
+    drop(reservation);
+    assert!(!output.exists());
+
+    ReservedOutput::reserve(&output, false, false)
+        .unwrap()
+        .keep();
+    assert!(output.is_file());
+
+    // Existing files are only overwritten with --force.
+    assert!(ReservedOutput::reserve(&output, false, false).is_err());
+    assert!(ReservedOutput::reserve(&output, false, true).is_err());
+
+    // Files that existed before they were reserved are never removed.
+    drop(ReservedOutput::reserve(&output, true, false).unwrap());
+    assert!(output.is_file());
+
+    // A notebook generated with --cache can be regenerated with --cache.
+    std::fs::write(cache::cache_path(&output), "0").unwrap();
+    drop(ReservedOutput::reserve(&output, false, true).unwrap());
+    assert!(ReservedOutput::reserve(&output, false, false).is_err());
+    assert!(output.is_file());
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+#[cfg(unix)]
+fn test_open_command() {
+    use std::os::unix::fs::PermissionsExt;
+
+    use pretty_assertions::assert_eq;
+
+    let dir = std::env::temp_dir().join(format!("md2nb-open-test-{}", process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+
+    let notebook = dir.join("out.nb");
+    let command = open_command(&notebook).expect("--open is supported on this platform");
+    let program = command.get_program().to_string_lossy().into_owned();
+
+    // Replace the launcher with a script that records the path it was asked to open.
+    let stub = dir.join(&program);
+    let opened = dir.join("opened.txt");
+    std::fs::write(
+        &stub,
+        format!("#!/bin/sh\necho \"$1\" > '{}'\n", opened.display()),
+    )
+    .unwrap();
+    std::fs::set_permissions(&stub, std::fs::Permissions::from_mode(0o755)).unwrap();
+
+    let mut command = open_command(&notebook).unwrap();
+    command.env("PATH", &dir);
+    assert_eq!(run_open_command(command), Ok(()));
+    assert_eq!(
+        std::fs::read_to_string(&opened).unwrap().trim_end(),
+        notebook.to_str().unwrap()
+    );
+
+    // A missing launcher command is reported clearly.
+    std::fs::remove_file(&stub).unwrap();
+    let mut command = open_command(&notebook).unwrap();
+    command.env("PATH", &dir);
+    assert_eq!(
+        run_open_command(command),
+        Err(format!("`{program}` command was not found"))
+    );
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_finish_output() {
+    let dir = std::env::temp_dir().join(format!("md2nb-atomic-test-{}", process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+
+    let output = dir.join("example.nb");
+    let temp_output = temp_output_path(&output);
+    assert_eq!(temp_output, dir.join(".example.nb.md2nb-tmp"));
+
+    // A failed save leaves neither a partial temporary file nor an output file.
+    std::fs::write(&temp_output, "Notebook[{Cell[").unwrap();
+    finish_output(&temp_output, &output, false).unwrap();
+    assert!(!temp_output.exists());
+    assert!(!output.exists());
+
+    // A failure before anything was written is not an error.
+    finish_output(&temp_output, &output, false).unwrap();
+
+    // A successful save is moved to the output path.
+    std::fs::write(&temp_output, "Notebook[{}]").unwrap();
+    finish_output(&temp_output, &output, true).unwrap();
+    assert!(!temp_output.exists());
+    assert_eq!(std::fs::read_to_string(&output).unwrap(), "Notebook[{}]");
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_diagnostics_json() {
+    use pretty_assertions::assert_eq;
+
+    let (_, diagnostics) = ast::parse_markdown_with_diagnostics(
+        "# Title\n\nSome <img src=\"x.png\"> text",
+        &ast::Options::default(),
+    );
+
+    assert_eq!(
+        diagnostics_to_json(&diagnostics),
+        r#"[{"line": 3, "column": 6, "kind": "inline-html", "message": "skipping inline HTML"}]"#
+    );
+
+    assert_eq!(diagnostics_to_json(&[]), "[]");
+    assert_eq!(json_string("a \"b\"\n"), r#""a \"b\"\n""#);
+}
+
+#[test]
+fn test_manifest_json() {
+    use pretty_assertions::assert_eq;
+
+    let dir = std::env::temp_dir().join(format!("md2nb-manifest-test-{}", process::id()));
+    let out_dir = dir.join("out");
+    std::fs::create_dir_all(&dir).unwrap();
+
+    let mut entries = Vec::new();
+
+    for (name, contents) in [("a", "# A"), ("b", "B <img>")] {
+        let input = dir.join(format!("{name}.md"));
+        std::fs::write(&input, contents).unwrap();
+
+        let args = Args::try_parse_from([
+            "md2nb".as_ref(),
+            "--no-kernel".as_ref(),
+            "--output-dir".as_ref(),
+            out_dir.as_os_str(),
+            input.as_os_str(),
+        ])
+        .unwrap();
+
+        let conversion = convert_file(args).unwrap().unwrap();
+        assert!(!conversion.failed);
+
+        entries.extend(conversion.entries);
+    }
+
+    for entry in &entries {
+        assert!(entry.output.is_file());
+    }
+
+    let path = |path: PathBuf| json_string(&path.to_string_lossy());
+
+    assert_eq!(
+        manifest_to_json(&entries),
+        format!(
+            concat!(
+                r#"[{{"input": {}, "output": {}, "cells": 1, "warnings": []}}, "#,
+                r#"{{"input": {}, "output": {}, "cells": 1, "warnings": "#,
+                r#"[{{"line": 1, "column": 3, "kind": "inline-html", "message": "skipping inline HTML"}}]}}]"#
+            ),
+            path(dir.join("a.md")),
+            path(out_dir.join("a.nb")),
+            path(dir.join("b.md")),
+            path(out_dir.join("b.nb")),
+        )
+    );
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+#[ignore = "requires a Wolfram Kernel"]
+fn test_nb_cell_code_block() {
+    let cells: Vec<Expr> = ast::parse_markdown_to_ast(
+        "```nb-cell\nCell[\"x\", \"Text\", Background -> Red]\n```",
+    )
+    .into_iter()
+    .flat_map(|block| nb::block_to_cells(block, &nb::Options::default()))
+    .collect();
+
+    let mut kernel = launch_kernel(None, &KernelProcess::default()).unwrap();
+
+    let () = kernel.link().put_eval_packet(&cells[0]).unwrap();
+    kernel.skip_to_next_return_packet().unwrap();
+
+    assert_eq!(
+        get_system_expr(kernel.link()).unwrap(),
+        Expr::normal(
+            Symbol::new("System`Cell"),
+            vec![
+                Expr::string("x"),
+                Expr::string("Text"),
+                Expr::rule(
+                    Symbol::new("System`Background"),
+                    Expr::normal(
+                        Symbol::new("System`RGBColor"),
+                        vec![Expr::from(1), Expr::from(0), Expr::from(0)],
+                    ),
+                ),
+            ],
+        )
+    );
+}
+
+#[test]
+#[ignore = "requires a Wolfram Kernel"]
+fn test_code_block_special_characters() {
+    use pretty_assertions::assert_eq;
+
+    let cells: Vec<Expr> = ast::parse_markdown_to_ast(
+        "```c\nprintf(\"%s\\n\", x);\n```\n\n```\n^\\d+\\s*\"[^\"\\\\]*\"$\n```",
+    )
+    .into_iter()
+    .flat_map(|block| nb::block_to_cells(block, &nb::Options::default()))
+    .collect();
+
+    let mut kernel = launch_kernel(None, &KernelProcess::default()).unwrap();
+
+    // Quotes, backslashes, and newlines in the code survive the round trip through
+    // the Kernel unchanged.
+    for cell in cells {
+        let () = kernel.link().put_eval_packet(&cell).unwrap();
+        kernel.skip_to_next_return_packet().unwrap();
+
+        assert_eq!(get_system_expr(kernel.link()).unwrap(), cell);
+    }
+}
+
+#[test]
+#[ignore = "requires a Wolfram Kernel"]
+fn test_check_notebook_file() {
+    let dir = std::env::temp_dir().join(format!("md2nb-validate-test-{}", process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+
+    let valid = dir.join("valid.nb");
+    std::fs::write(&valid, r#"Notebook[{Cell["Hello", "Text"]}]"#).unwrap();
+
+    let invalid = dir.join("invalid.nb");
+    std::fs::write(&invalid, "Notebook[{Cell[").unwrap();
+
+    let mut kernel = launch_kernel(None, &KernelProcess::default()).unwrap();
+
+    assert!(check_notebook_file(&mut kernel, &valid, 0).unwrap());
+    assert!(!check_notebook_file(&mut kernel, &invalid, 0).unwrap());
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_notebook_write_exprs() {
+    use pretty_assertions::assert_eq;
+
+    let nb_obj = Expr::normal(Symbol::new("System`NotebookObject"), vec![]);
+
+    let cells: Vec<Expr> = (0..250)
+        .map(|index| {
+            Expr::normal(
+                Symbol::new("System`Cell"),
+                vec![Expr::string(index.to_string()), Expr::string("Text")],
+            )
+        })
+        .collect();
+
+    let exprs = notebook_write_exprs(&nb_obj, cells.clone());
+
+    // The cells are written in batches, in their original order.
+    let batches: Vec<Vec<Expr>> = exprs
+        .iter()
+        .map(|expr| {
+            let (obj, batch) = match expr.try_normal().unwrap().elements() {
+                [obj, batch] => (obj, batch),
+                _ => panic!("expected NotebookWrite[obj, cells]: {expr}"),
+            };
+            assert_eq!(obj, &nb_obj);
+            batch.try_normal().unwrap().elements().to_vec()
+        })
+        .collect();
+
+    assert_eq!(
+        batches.iter().map(Vec::len).collect::<Vec<_>>(),
+        vec![100, 100, 50]
+    );
+    assert_eq!(batches.concat(), cells);
+
+    assert!(notebook_write_exprs(&nb_obj, Vec::new()).is_empty());
+}
+
+#[test]
+#[ignore = "requires a Wolfram Kernel"]
+fn test_export_notebook() {
+    let dir = std::env::temp_dir().join(format!("md2nb-export-test-{}", process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+
+    let output = dir.join("exported.nb");
+    let cells =
+        md2nb::markdown_to_cells("# Title\n\nText", &md2nb::Options::default()).unwrap();
+
+    let (saved, exited_cleanly) = save_with_kernel(
+        launch_kernel(None, &KernelProcess::default()).unwrap(),
+        vec![(output, cells)],
+        &nb::Options::default(),
+        true,
+        true,
+    )
+    .unwrap();
+
+    assert!(exited_cleanly);
+    assert_eq!(saved[0].is_valid, Some(true));
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
@@ -1,423 +1,5567 @@
-use pulldown_cmark::HeadingLevel;
+mod wxf;
 
-use wolfram_expr::{Expr, Symbol};
+use std::{
+    collections::BTreeMap,
+    fmt, mem,
+    path::{Path, PathBuf},
+    str::FromStr,
+};
 
-use crate::ast::{Block, ListItem, Text, TextSpan, TextStyle};
+use pulldown_cmark::{Alignment, HeadingLevel};
+
+use wolfram_expr::{Expr, ExprKind, Symbol};
+
+use crate::{
+    ast::{Block, Color, ListItem, SourceLines, Text, TextSpan, TextStyle},
+    theme::Theme,
+};
 
 #[derive(Debug, Clone)]
 pub struct Options {
     pub create_external_language_cells: bool,
+    /// If set, tab characters in prose are emitted as separate `"\t"` elements of the
+    /// `RowBox`, instead of being left embedded in the surrounding text string.
+    ///
+    /// This preserves manual tab alignment, which the front end otherwise collapses.
+    pub preserve_tabs: bool,
+    /// The `PageWidth` notebook option, controlling the width that text and code wraps
+    /// at. If `None`, the front end default is used.
+    pub page_width: Option<PageWidth>,
+    /// If set, code cells are labeled with the range of Markdown source lines they
+    /// were converted from.
+    pub code_line_numbers: bool,
+    /// If set, multi-line "Program" cells are laid out as a two-column grid, with the
+    /// line number of each line of code in a gutter on the left.
+    pub code_gutter: bool,
+    /// Name of the evaluator (kernel session) used to evaluate "ExternalLanguage"
+    /// cells. If `None`, the front end default evaluator is used.
+    pub evaluator: Option<String>,
+    /// If set, evaluatable code cells have `CellAutoOverwrite -> True`, so that
+    /// re-evaluating them replaces their previous output.
+    pub cell_auto_overwrite: bool,
+    /// If set, text is hard-wrapped at this many columns by inserting newlines at word
+    /// boundaries. Inline code and links are never broken.
+    pub wrap_prose: Option<usize>,
+    /// If set, tables are centered horizontally on the page.
+    pub center_tables: bool,
+    /// If set, tables whose cells are all plain text are converted to an `"Input"`
+    /// cell that constructs a `Dataset` of the table rows, instead of a static grid.
+    pub table_as_dataset: bool,
+    /// If set, inline code is given a light gray background, like block quotes.
+    pub inline_code_background: bool,
+    /// Text of a docked cell shown at the top of the notebook window.
+    pub header_text: Option<String>,
+    /// Text of a docked cell shown after the header, e.g. for page or version info.
+    ///
+    /// The front end only supports docking cells at the top of the window, so this is
+    /// the last of the notebook's `DockedCells`.
+    pub footer_text: Option<String>,
+    /// If set, paragraphs longer than this many characters are split at word
+    /// boundaries into several `"Text"` cells.
+    pub max_cell_chars: Option<usize>,
+    /// Cell styles to use for headings, overriding the default style for that heading
+    /// level.
+    pub heading_styles: BTreeMap<HeadingLevel, String>,
+    /// Cell evaluation languages to use for fenced code blocks, keyed by the
+    /// lowercase language of the info string, overriding the built-in languages.
+    ///
+    /// A language mapped to `"Program"` is a static code cell, and one mapped to
+    /// `"Input"` is Wolfram Language code.
+    pub language_map: BTreeMap<String, String>,
+    /// If set, Wolfram Language code blocks are parsed into boxes by the Kernel, so
+    /// that the `"Input"` cells are typeset like code entered in the front end.
+    /// Otherwise the code is plain text in the cell.
+    pub parse_wolfram_code: bool,
+    /// How Markdown thematic breaks (`***`) are displayed.
+    pub rule_style: RuleStyle,
+    /// How soft line breaks within a paragraph are displayed.
+    pub soft_break: SoftBreakMode,
+    /// Colors used for block quotes, code blocks, and inline code. An inline code
+    /// background set by the theme is used even if
+    /// [`Options::inline_code_background`] is not set.
+    pub theme: Theme,
+    /// If set, each heading cell and the cells that follow it are grouped into a
+    /// `CellGroupData[..]`, so that sections can be collapsed in the front end. See
+    /// [`group_cells_by_heading()`].
+    pub group_cells: bool,
+    /// If set, a table of contents linking to each heading is added at the start of
+    /// the notebook. See [`table_of_contents_cell()`].
+    pub table_of_contents: bool,
+    /// Directory that relative image paths are resolved against, usually the
+    /// directory containing the Markdown file. If `None`, relative paths are resolved
+    /// by the Kernel against its current directory.
+    pub base_directory: Option<PathBuf>,
+}
+
+/// Value of the `PageWidth` notebook option.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum PageWidth {
+    /// Wrap at a fixed width, in printer's points.
+    Points(u32),
+    /// Never wrap text to the page width.
+    Infinity,
+    /// Wrap at the width of the printed page.
+    Paper,
+}
+
+/// Appearance of a Markdown thematic break.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RuleStyle {
+    /// A thin horizontal line across the full width of the notebook.
+    Line,
+    /// A centered row of asterisks.
+    Dots,
+    /// Extra vertical space, with no visible mark.
+    Space,
+}
+
+/// Display of a soft line break, i.e. a line ending within a paragraph.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SoftBreakMode {
+    /// A single space, as in rendered HTML.
+    Space,
+    /// A line break, like a Markdown hard break.
+    Newline,
+    /// Nothing, joining the lines directly. This is appropriate for CJK text, which
+    /// doesn't separate words with spaces.
+    None,
+}
+
+/// A `H<N>=<style>` mapping from a heading level to the cell style used for headings
+/// at that level.
+#[derive(Debug, Clone, PartialEq)]
+pub struct HeadingStyle {
+    pub level: HeadingLevel,
+    pub style: String,
+}
+
+/// A `<language>=<target>` mapping from the language of a fenced code block to the
+/// cell evaluation language used for it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct LanguageMapping {
+    pub language: String,
+    pub target: String,
+}
+
+/// Builder for [`Options`], constructed using [`Options::builder()`].
+///
+/// Fields that are not explicitly set keep their [`Options::default()`] value.
+#[derive(Debug, Clone)]
+pub struct OptionsBuilder {
+    opts: Options,
 }
 
 struct State {
     list_depth: u8,
+    /// Whether the innermost list being converted is an ordered list.
+    list_ordered: bool,
+    /// Whether the innermost list being converted is a tight list.
+    list_tight: bool,
 }
 
-pub fn block_to_cells(block: Block, opts: &Options) -> Vec<Expr> {
-    let mut state = State { list_depth: 0 };
+/// State used to hard-wrap text at [`Options::wrap_prose`] columns.
+struct ProseWrap {
+    width: usize,
+    /// The column the next character of text will be placed at.
+    column: usize,
+    /// Whether the most recent text ended with a space that a line break can be placed
+    /// after.
+    after_space: bool,
+}
 
-    block_to_cells_(&mut state, opts, block)
+impl Default for Options {
+    fn default() -> Self {
+        Options {
+            create_external_language_cells: true,
+            preserve_tabs: false,
+            page_width: None,
+            code_line_numbers: false,
+            code_gutter: false,
+            evaluator: None,
+            cell_auto_overwrite: false,
+            wrap_prose: None,
+            center_tables: false,
+            table_as_dataset: false,
+            inline_code_background: false,
+            header_text: None,
+            footer_text: None,
+            max_cell_chars: None,
+            heading_styles: BTreeMap::new(),
+            language_map: BTreeMap::new(),
+            parse_wolfram_code: false,
+            rule_style: RuleStyle::Line,
+            soft_break: SoftBreakMode::Space,
+            theme: Theme::default(),
+            group_cells: false,
+            table_of_contents: false,
+            base_directory: None,
+        }
+    }
 }
 
-fn block_to_cells_(state: &mut State, opts: &Options, block: Block) -> Vec<Expr> {
-    match block {
-        Block::Heading(level, text) => {
-            let style = match level {
-                HeadingLevel::H1 => "Title",
-                HeadingLevel::H2 => "Chapter",
-                HeadingLevel::H3 => "Section",
-                HeadingLevel::H4 => "Subsection",
-                HeadingLevel::H5 => "Subsubsection",
-                HeadingLevel::H6 => "Subsubsubsection",
-            };
+impl Options {
+    pub fn builder() -> OptionsBuilder {
+        OptionsBuilder {
+            opts: Options::default(),
+        }
+    }
+}
 
-            vec![Expr::normal(
-                Symbol::new("System`Cell"),
-                vec![text_to_text_data(text), Expr::from(style)],
-            )]
-        },
-        Block::Paragraph(text) => vec![Expr::normal(
-            Symbol::new("System`Cell"),
-            vec![text_to_text_data(text), Expr::from("Text")],
-        )],
-        Block::List(items) => {
-            let mut list_cells = Vec::new();
+impl OptionsBuilder {
+    pub fn create_external_language_cells(mut self, value: bool) -> Self {
+        self.opts.create_external_language_cells = value;
+        self
+    }
 
-            state.list_depth += 1;
+    pub fn preserve_tabs(mut self, value: bool) -> Self {
+        self.opts.preserve_tabs = value;
+        self
+    }
 
-            for item in items {
-                list_cells.extend(list_item_to_cells(state, item));
-            }
+    pub fn page_width(mut self, value: Option<PageWidth>) -> Self {
+        self.opts.page_width = value;
+        self
+    }
 
-            state.list_depth -= 1;
+    pub fn code_line_numbers(mut self, value: bool) -> Self {
+        self.opts.code_line_numbers = value;
+        self
+    }
 
-            list_cells
-        },
-        Block::CodeBlock {
-            info_string: label,
-            code: code_text,
-        } => {
-            let external_language: Option<&str> =
-                // The languages listed here should be all of those currently supported
-                // by ExternalEvaluate.
-                match label.map(|s| s.to_lowercase()).as_deref() {
-                    Some("python") => Some("Python"),
-                    Some("shell" | "bash" | "sh" | "zsh") => Some("Shell"),
-                    Some("julia") => Some("Julia"),
-                    Some("r") => Some("R"),
-                    Some("octave") => Some("Octave"),
-                    Some("java") => Some("Java"),
-                    Some("node" | "nodejs" | "js" | "javascript") => Some("NodeJS"),
-                    Some("jupyter") => Some("Jupyter"),
-                    Some("sql") => Some("SQL"),
-                    Some("sql-jdbc") => Some("SQL-JDBC"),
-                    Some(_) => None,
-                    None => None,
-                };
+    pub fn code_gutter(mut self, value: bool) -> Self {
+        self.opts.code_gutter = value;
+        self
+    }
 
-            match external_language {
-                // Only create "ExternalLanguage" cells if the option is set (enabled by
-                // default).
-                Some(lang) if opts.create_external_language_cells => {
-                    vec![Expr::normal(
-                        Symbol::new("System`Cell"),
-                        vec![
-                            Expr::string(code_text),
-                            Expr::string("ExternalLanguage"),
-                            Expr::rule(
-                                Symbol::new("System`CellEvaluationLanguage"),
-                                Expr::string(lang),
-                            ),
-                        ],
-                    )]
-                },
-                _ => {
-                    vec![Expr::normal(
-                        Symbol::new("System`Cell"),
-                        vec![Expr::string(code_text), Expr::string("Program")],
-                    )]
-                },
-            }
-        },
-        Block::BlockQuote(quote_blocks) => {
-            let quote_cells: Vec<Expr> = quote_blocks
-                .into_iter()
-                .flat_map(|block| block_to_cells(block, opts))
-                .collect();
+    pub fn evaluator(mut self, value: Option<String>) -> Self {
+        self.opts.evaluator = value;
+        self
+    }
 
-            // TODO: Use a dedicated "BlockQuote" cell style. There is no "BlockQuote"
-            //       style in the default Wolfram notebook stylesheet, but we could add
-            //       a StyleData definition to this notebook.
-            let cell = Expr::normal(
-                Symbol::new("System`Cell"),
-                vec![
-                    Expr::normal(
-                        Symbol::new("System`BoxData"),
-                        vec![Expr::list(quote_cells)],
-                    ),
-                    Expr::string("Text"),
-                    // Only the left side should have a frame:
-                    //   CellFrame -> {{4, 0}, {0, 0}}
-                    Expr::rule(
-                        Symbol::new("System`CellFrame"),
-                        Expr::list(vec![
-                            Expr::list(vec![Expr::from(4), Expr::from(0)]),
-                            Expr::list(vec![Expr::from(0), Expr::from(0)]),
-                        ]),
-                    ),
-                    // The cell frame should have a medium-light gray color:
-                    //   CellFrameColor -> GrayLevel[0.8]
-                    Expr::rule(
-                        Symbol::new("System`CellFrameColor"),
-                        Expr::normal(
-                            Symbol::new("System`GrayLevel"),
-                            vec![Expr::real(0.8)],
-                        ),
-                    ),
-                    // The cell background should be a light gray color:
-                    //   Background -> GrayLevel[0.95]
-                    Expr::rule(
-                        Symbol::new("System`Background"),
-                        Expr::normal(
-                            Symbol::new("System`GrayLevel"),
-                            vec![Expr::real(0.95)],
-                        ),
-                    ),
-                ],
-            );
-            vec![cell]
-        },
-        Block::Table { headers, rows } => {
-            let mut grid_rows: Vec<Expr> = Vec::new();
+    pub fn cell_auto_overwrite(mut self, value: bool) -> Self {
+        self.opts.cell_auto_overwrite = value;
+        self
+    }
 
-            let header_row = headers
-                .into_iter()
-                .map(|content: Text| {
-                    Expr::normal(
-                        Symbol::new("System`Cell"),
-                        vec![text_to_text_data(content), Expr::from("Subsubsubsection")],
-                    )
-                })
-                .collect();
+    pub fn wrap_prose(mut self, value: Option<usize>) -> Self {
+        self.opts.wrap_prose = value;
+        self
+    }
 
-            grid_rows.push(Expr::list(header_row));
+    pub fn center_tables(mut self, value: bool) -> Self {
+        self.opts.center_tables = value;
+        self
+    }
 
-            for row_content in rows {
-                let row: Vec<Expr> = row_content
-                    .into_iter()
-                    .map(|content: Text| {
-                        Expr::normal(
-                            Symbol::new("System`Cell"),
-                            vec![text_to_text_data(content), Expr::from("Text")],
-                        )
-                    })
-                    .collect();
+    pub fn table_as_dataset(mut self, value: bool) -> Self {
+        self.opts.table_as_dataset = value;
+        self
+    }
 
-                grid_rows.push(Expr::list(row));
-            }
+    pub fn inline_code_background(mut self, value: bool) -> Self {
+        self.opts.inline_code_background = value;
+        self
+    }
 
-            let grid_box = Expr::normal(
-                Symbol::new("System`GridBox"),
-                vec![
-                    Expr::list(grid_rows),
-                    // GridBoxItemSize -> {
-                    //     "Columns" -> {{Automatic}},
-                    //     "Rows" -> {{Automatic}}
-                    // }
-                    Expr::rule(
-                        Symbol::new("System`GridBoxItemSize"),
-                        Expr::list(vec![
-                            Expr::rule(
-                                Expr::from("Columns"),
-                                Expr::list(vec![Expr::list(vec![Expr::from(
-                                    Symbol::new("System`Automatic"),
-                                )])]),
-                            ),
-                            Expr::rule(
-                                Expr::from("Rows"),
-                                Expr::list(vec![Expr::list(vec![Expr::from(
-                                    Symbol::new("System`Automatic"),
-                                )])]),
-                            ),
-                        ]),
-                    ),
-                ],
-            );
+    pub fn header_text(mut self, value: Option<String>) -> Self {
+        self.opts.header_text = value;
+        self
+    }
 
-            vec![Expr::normal(
-                Symbol::new("System`Cell"),
-                vec![
-                    Expr::normal(Symbol::new("System`BoxData"), vec![grid_box]),
-                    Expr::from("Text"),
-                ],
-            )]
-        },
-        Block::Rule => {
-            // Note: This formatting is based on the menu item:
-            //         Insert > Horizontal Line > Paste Thick Line Object
-            // TODO:
-            //     Support inserting lines of different thickness, and with different
-            //     left-side margins based on context.
-            //
-            //     For example, `***` is supported within block quotes. Improve how it
-            //     looks when used in that context.
-            vec![Expr::normal(
-                Symbol::new("System`Cell"),
-                vec![
-                    Expr::string(""),
-                    Expr::string("Text"),
-                    // Editable->False,
-                    Expr::rule(
-                        Symbol::new("System`Editable"),
-                        Expr::symbol(Symbol::new("System`False")),
-                    ),
-                    // Selectable->False,
-                    // CellFrame->{{0, 0}, {0, 3}},
-                    Expr::rule(
-                        Symbol::new("System`CellFrame"),
-                        Expr::list(vec![
-                            Expr::list(vec![Expr::from(0), Expr::from(0)]),
-                            Expr::list(vec![Expr::from(0), Expr::from(3)]),
-                        ]),
-                    ),
-                    // ShowCellBracket->False,
-                    Expr::rule(
-                        Symbol::new("System`ShowCellBracket"),
-                        Expr::symbol(Symbol::new("System`False")),
-                    ),
-                    // CellMargins->{{0, 0}, {1, 1}},
-                    Expr::rule(
-                        Symbol::new("System`CellMargins"),
-                        Expr::list(vec![
-                            Expr::list(vec![Expr::from(0), Expr::from(0)]),
-                            Expr::list(vec![Expr::from(1), Expr::from(1)]),
-                        ]),
-                    ),
-                    // CellElementSpacings->{"CellMinHeight"->1},
-                    Expr::rule(
-                        Symbol::new("System`CellElementSpacings"),
-                        Expr::list(vec![Expr::rule(
-                            Expr::from("CellMinHeight"),
-                            Expr::from(1),
-                        )]),
-                    ),
-                    // CellFrameMargins->0,
-                    Expr::rule(Symbol::new("System`CellFrameMargins"), Expr::from(0)),
-                    // CellFrameColor->GrayLevel[0.75],
-                    Expr::rule(
-                        Symbol::new("System`CellFrameColor"),
-                        Expr::normal(
-                            Symbol::new("System`GrayLevel"),
-                            vec![Expr::real(0.75)],
-                        ),
-                    ),
-                    // CellSize->{Inherited, 5}
-                    Expr::rule(
-                        Symbol::new("System`CellSize"),
-                        Expr::list(vec![
-                            Expr::from(Symbol::new("System`Inherited")),
-                            Expr::from(5),
-                        ]),
-                    ),
-                ],
-            )]
-        },
+    pub fn footer_text(mut self, value: Option<String>) -> Self {
+        self.opts.footer_text = value;
+        self
+    }
+
+    pub fn max_cell_chars(mut self, value: Option<usize>) -> Self {
+        self.opts.max_cell_chars = value;
+        self
+    }
+
+    pub fn heading_styles(mut self, value: BTreeMap<HeadingLevel, String>) -> Self {
+        self.opts.heading_styles = value;
+        self
+    }
+
+    pub fn language_map(mut self, value: BTreeMap<String, String>) -> Self {
+        self.opts.language_map = value;
+        self
+    }
+
+    pub fn parse_wolfram_code(mut self, value: bool) -> Self {
+        self.opts.parse_wolfram_code = value;
+        self
+    }
+
+    pub fn rule_style(mut self, value: RuleStyle) -> Self {
+        self.opts.rule_style = value;
+        self
+    }
+
+    pub fn soft_break(mut self, value: SoftBreakMode) -> Self {
+        self.opts.soft_break = value;
+        self
+    }
+
+    pub fn theme(mut self, value: Theme) -> Self {
+        self.opts.theme = value;
+        self
+    }
+
+    pub fn group_cells(mut self, value: bool) -> Self {
+        self.opts.group_cells = value;
+        self
+    }
+
+    pub fn table_of_contents(mut self, value: bool) -> Self {
+        self.opts.table_of_contents = value;
+        self
+    }
+
+    pub fn base_directory(mut self, value: Option<PathBuf>) -> Self {
+        self.opts.base_directory = value;
+        self
+    }
+
+    pub fn build(self) -> Options {
+        self.opts
     }
 }
 
-fn list_item_to_cells(state: &mut State, ListItem(blocks): ListItem) -> Vec<Expr> {
-    let mut cells = vec![];
+impl ProseWrap {
+    /// Returns `true` if a line break should be inserted before `len` characters of
+    /// text that cannot be broken, and advances past that text.
+    fn break_before(&mut self, len: usize) -> bool {
+        let should_break =
+            self.after_space && self.column > 0 && self.column + len > self.width;
 
-    for block in blocks {
-        match block {
-            Block::Paragraph(text) => {
-                let style = match state.list_depth {
-                    0 => panic!(),
-                    1 => "Item",
-                    2 => "Subitem",
-                    3 => "Subsubitem",
-                    _ => todo!("return list depth error"),
-                };
+        if should_break {
+            self.column = 0;
+        }
 
-                cells.push(Expr::normal(
-                    Symbol::new("System`Cell"),
-                    vec![text_to_text_data(text), Expr::from(style)],
-                ));
-            },
-            Block::List(items) => {
-                let mut list_cells = Vec::new();
+        self.column += len;
+        self.after_space = false;
+
+        should_break
+    }
+
+    /// Returns `text` with line breaks inserted in place of spaces, so that no line is
+    /// longer than `width` columns, except for single words that are longer than that.
+    fn wrap_text(&mut self, text: &str) -> String {
+        let mut wrapped = String::with_capacity(text.len());
+
+        for (index, word) in text.split(' ').enumerate() {
+            if index > 0 {
+                wrapped.push(' ');
+                self.column += 1;
+                self.after_space = true;
+            }
 
-                state.list_depth += 1;
+            if word.is_empty() {
+                continue;
+            }
+
+            let len = word.chars().count();
 
-                for item in items {
-                    list_cells.extend(list_item_to_cells(state, item));
+            if self.after_space && self.column > 0 && self.column + len > self.width {
+                // Replace the preceding space with the line break. If the space was
+                // at the end of an earlier span, put the break before this word.
+                if wrapped.ends_with(' ') {
+                    wrapped.pop();
                 }
+                wrapped.push('\n');
+                self.column = 0;
+            }
+
+            wrapped.push_str(word);
+            self.column += len;
+            self.after_space = false;
+        }
 
-                state.list_depth -= 1;
+        wrapped
+    }
+}
 
-                cells.extend(list_cells);
-            },
-            Block::BlockQuote(_) => {
-                todo!("handle markdown block quote inside list items")
-            },
-            Block::Heading(_, _) => todo!("handle markdown headings inside list items"),
-            Block::CodeBlock { .. } => {
-                todo!("handle markdown code block inside list item")
+impl FromStr for PageWidth {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            "infinity" => Ok(PageWidth::Infinity),
+            "paper" => Ok(PageWidth::Paper),
+            _ => match value.parse() {
+                Ok(points) => Ok(PageWidth::Points(points)),
+                Err(_) => Err(format!(
+                    "invalid page width: {value:?} (expected a number of points, \
+                     'infinity', or 'paper')"
+                )),
             },
-            Block::Table { .. } => todo!("handle markdown table inside list item"),
-            Block::Rule => todo!("handle markdown rule inside list item"),
         }
     }
+}
 
-    cells
+impl FromStr for RuleStyle {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            "line" => Ok(RuleStyle::Line),
+            "dots" => Ok(RuleStyle::Dots),
+            "space" => Ok(RuleStyle::Space),
+            _ => Err(format!(
+                "invalid rule style: {value:?} (expected 'line', 'dots', or 'space')"
+            )),
+        }
+    }
 }
 
-/// Returns a `TextData[{...}]` expression.
-fn text_to_text_data(text: Text) -> Expr {
-    Expr::normal(Symbol::new("System`TextData"), vec![text_to_boxes(text)])
+impl FromStr for SoftBreakMode {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            "space" => Ok(SoftBreakMode::Space),
+            "newline" => Ok(SoftBreakMode::Newline),
+            "none" => Ok(SoftBreakMode::None),
+            _ => Err(format!(
+                "invalid soft break mode: {value:?} (expected 'space', 'newline', or 'none')"
+            )),
+        }
+    }
 }
 
-// Returns a `RowBox[{...}]` expression.
-fn text_to_boxes(text: Text) -> Expr {
-    let mut row = Vec::new();
+impl FromStr for HeadingStyle {
+    type Err = String;
 
-    for span in text {
-        match span {
-            TextSpan::Text(text, styles) => {
-                let mut style_rules: Vec<Expr> = Vec::new();
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        let (level, style) = value.split_once('=').ok_or_else(|| {
+            format!("invalid heading style: {value:?} (expected H<N>=<style>)")
+        })?;
 
-                for style in styles {
-                    let (lhs, rhs) = match style {
-                        TextStyle::Emphasis => {
-                            (Symbol::new("System`FontSlant"), "Italic")
-                        },
-                        TextStyle::Strong => (Symbol::new("System`FontWeight"), "Bold"),
-                        TextStyle::Strikethrough => todo!("strikethrough text"),
-                    };
+        let level = parse_heading_level(level)?;
 
-                    style_rules.push(Expr::normal(
-                        Symbol::new("System`Rule"),
-                        vec![Expr::from(lhs), Expr::string(rhs)],
-                    ));
-                }
+        let style = style.trim();
 
-                let expr = if style_rules.is_empty() {
-                    Expr::string(text)
-                } else {
-                    style_rules.insert(0, Expr::string(text));
+        if style.is_empty() {
+            return Err(format!("heading style for {level:?} cannot be empty"));
+        }
 
-                    Expr::normal(Symbol::new("System`StyleBox"), style_rules)
-                };
+        Ok(HeadingStyle {
+            level,
+            style: style.to_owned(),
+        })
+    }
+}
 
-                row.push(expr);
-            },
-            TextSpan::Code(code) => row.push(Expr::normal(
-                Symbol::new("System`StyleBox"),
-                vec![Expr::string(code), Expr::string("Code")],
-            )),
-            TextSpan::Link { label, destination } => row.push(Expr::normal(
-                Symbol::new("System`ButtonBox"),
+impl FromStr for LanguageMapping {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        let (language, target) = value.split_once('=').ok_or_else(|| {
+            format!("invalid language mapping: {value:?} (expected <language>=<target>)")
+        })?;
+
+        let (language, target) = (language.trim(), target.trim());
+
+        if language.is_empty() || target.is_empty() {
+            return Err(format!(
+                "invalid language mapping: {value:?} (language and target cannot be empty)"
+            ));
+        }
+
+        Ok(LanguageMapping {
+            language: language.to_lowercase(),
+            target: target.to_owned(),
+        })
+    }
+}
+
+/// Parse a heading level, written as either `H<N>` or `<N>`.
+pub fn parse_heading_level(value: &str) -> Result<HeadingLevel, String> {
+    let value = value.trim();
+    let number = value.strip_prefix(['H', 'h']).unwrap_or(value);
+
+    match number {
+        "1" => Ok(HeadingLevel::H1),
+        "2" => Ok(HeadingLevel::H2),
+        "3" => Ok(HeadingLevel::H3),
+        "4" => Ok(HeadingLevel::H4),
+        "5" => Ok(HeadingLevel::H5),
+        "6" => Ok(HeadingLevel::H6),
+        _ => Err(format!(
+            "invalid heading level: {value:?} (expected H1 through H6)"
+        )),
+    }
+}
+
+/// Returns the notebook-level options that should be set on the generated notebook.
+pub fn notebook_options(opts: &Options) -> Vec<Expr> {
+    let mut options = Vec::new();
+
+    if let Some(page_width) = opts.page_width {
+        let value = match page_width {
+            PageWidth::Points(points) => Expr::from(i64::from(points)),
+            PageWidth::Infinity => Expr::from(Symbol::new("System`Infinity")),
+            PageWidth::Paper => Expr::from(Symbol::new("System`PaperWidth")),
+        };
+
+        options.push(Expr::rule(Symbol::new("System`PageWidth"), value));
+    }
+
+    // DockedCells -> {Cell["<header>", "DockedCell"], Cell["<footer>", "DockedCell"]}
+    let docked_cells: Vec<Expr> = [&opts.header_text, &opts.footer_text]
+        .into_iter()
+        .flatten()
+        .map(|text| {
+            Expr::normal(
+                Symbol::new("System`Cell"),
+                vec![Expr::string(text.as_str()), Expr::string("DockedCell")],
+            )
+        })
+        .collect();
+
+    if !docked_cells.is_empty() {
+        options.push(Expr::rule(
+            Symbol::new("System`DockedCells"),
+            Expr::list(docked_cells),
+        ));
+    }
+
+    options
+}
+
+/// Returns a `Notebook[{cells}, options]` expression containing `cells`.
+pub fn cells_to_notebook_expr(cells: Vec<Expr>, opts: &Options) -> Expr {
+    let mut elements = vec![Expr::list(cells)];
+    elements.extend(notebook_options(opts));
+
+    Expr::normal(Symbol::new("System`Notebook"), elements)
+}
+
+/// Returns the contents of a `.nb` file containing the notebook expression `expr`.
+///
+/// Unlike a notebook saved by the front end, the file is written without a cache
+/// header, and any expressions in `expr` that would be evaluated by
+/// `NotebookWrite[..]`, like the `ToBoxes[..]` of an image or math cell, are written
+/// unevaluated.
+pub fn write_notebook_string(expr: &Expr) -> String {
+    let mut string = String::new();
+    write_expr(&mut string, expr);
+    string.push('\n');
+    string
+}
+
+/// Returns the contents of a `.wxf` file containing the notebook expression for
+/// `cells`, serialized in the [Wolfram Exchange Format][wxf].
+///
+/// Like [`write_notebook_string`], this doesn't require a Wolfram Kernel, so any
+/// expressions that `NotebookWrite[..]` would evaluate are written unevaluated.
+///
+/// [wxf]: https://reference.wolfram.com/language/tutorial/WXFFormatDescription.html
+pub fn cells_to_wxf(cells: Vec<Expr>, opts: &Options) -> Vec<u8> {
+    wxf::expr_to_wxf(&cells_to_notebook_expr(cells, opts))
+}
+
+/// Returns true if `cell` contains expressions that the Kernel must evaluate to
+/// produce the final notebook content.
+pub fn requires_kernel(cell: &Expr) -> bool {
+    match cell.kind() {
+        ExprKind::Normal(normal) => {
+            normal.has_head(&Symbol::new("System`ToBoxes"))
+                || normal.has_head(&Symbol::new("System`ToExpression"))
+                || normal.elements().iter().any(requires_kernel)
+        },
+        _ => false,
+    }
+}
+
+/// Write `expr` to `out` in Wolfram Language InputForm syntax.
+fn write_expr(out: &mut String, expr: &Expr) {
+    match expr.kind() {
+        ExprKind::Integer(int) => out.push_str(&int.to_string()),
+        ExprKind::Real(real) => {
+            // `{:?}` always includes a decimal point, e.g. `1.0` and `1e-7`.
+            let real = format!("{:?}", **real);
+            out.push_str(&real.replacen('e', "*^", 1));
+        },
+        ExprKind::String(string) => write_string(out, string),
+        ExprKind::Symbol(symbol) => {
+            let name = symbol.as_str();
+            out.push_str(name.strip_prefix("System`").unwrap_or(name));
+        },
+        ExprKind::Normal(normal) => {
+            let elements = normal.elements();
+
+            if normal.has_head(&Symbol::new("System`List")) {
+                out.push('{');
+                write_sequence(out, elements);
+                out.push('}');
+            } else if normal.has_head(&Symbol::new("System`Rule"))
+                && elements.len() == 2
+                && !matches!(elements[0].kind(), ExprKind::Normal(lhs) if lhs.has_head(&Symbol::new("System`Rule")))
+            {
+                write_expr(out, &elements[0]);
+                out.push_str(" -> ");
+                write_expr(out, &elements[1]);
+            } else {
+                write_expr(out, normal.head());
+                out.push('[');
+                write_sequence(out, elements);
+                out.push(']');
+            }
+        },
+    }
+}
+
+fn write_sequence(out: &mut String, elements: &[Expr]) {
+    for (index, element) in elements.iter().enumerate() {
+        if index > 0 {
+            out.push_str(", ");
+        }
+        write_expr(out, element);
+    }
+}
+
+/// Write `string` as a Wolfram Language string literal. Non-ASCII characters are
+/// written as `\:xxxx` escapes, so that the file is readable in any encoding.
+fn write_string(out: &mut String, string: &str) {
+    out.push('"');
+
+    for c in string.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\t' => out.push_str("\\t"),
+            '\r' => out.push_str("\\r"),
+            ' '..='~' => out.push(c),
+            c if u32::from(c) <= 0xFFFF => {
+                out.push_str(&format!("\\:{:04x}", u32::from(c)))
+            },
+            c => out.push_str(&format!("\\|{:06x}", u32::from(c))),
+        }
+    }
+
+    out.push('"');
+}
+
+/// Returns a hidden `"RawMarkdown"` cell containing the Markdown `input` that the
+/// notebook was generated from, so that the source can be recovered later.
+pub fn raw_markdown_cell(input: &str) -> Expr {
+    // Cell["<input>", "RawMarkdown", CellOpen -> False]
+    Expr::normal(
+        Symbol::new("System`Cell"),
+        vec![
+            Expr::string(input),
+            Expr::string("RawMarkdown"),
+            Expr::rule(
+                Symbol::new("System`CellOpen"),
+                Expr::from(Symbol::new("System`False")),
+            ),
+        ],
+    )
+}
+
+/// If `blocks` begins with an H1 heading directly followed by a paragraph, remove
+/// them and return them as a "hero" block: the `"Title"` cell, followed by the
+/// paragraph in a `"Subtitle"` cell, with reduced spacing between the two.
+///
+/// Returns an empty list if `blocks` does not begin with that pattern.
+pub fn hero_cells(blocks: &mut Vec<Block>, opts: &Options) -> Vec<Expr> {
+    if !matches!(
+        blocks.as_slice(),
+        [
+            Block::Heading {
+                level: HeadingLevel::H1,
+                ..
+            },
+            Block::Paragraph(_),
+            ..
+        ]
+    ) {
+        return Vec::new();
+    }
+
+    let mut hero = blocks.drain(..2);
+    let (title, subtitle) = match (hero.next(), hero.next()) {
+        (Some(title), Some(Block::Paragraph(subtitle))) => (title, subtitle),
+        _ => unreachable!(),
+    };
+    drop(hero);
+
+    let inherited = || Expr::from(Symbol::new("System`Inherited"));
+
+    // CellMargins -> {{Inherited, Inherited}, {bottom, top}}
+    let margins = |bottom: Expr, top: Expr| {
+        Expr::rule(
+            Symbol::new("System`CellMargins"),
+            Expr::list(vec![
+                Expr::list(vec![inherited(), inherited()]),
+                Expr::list(vec![bottom, top]),
+            ]),
+        )
+    };
+
+    let mut cells: Vec<Expr> = block_to_cells(title, opts)
+        .into_iter()
+        .map(|cell| cell_with_options(cell, vec![margins(Expr::from(0), inherited())]))
+        .collect();
+
+    let anchors = text_anchors(&subtitle);
+
+    cells.push(with_cell_tags(
+        Expr::normal(
+            Symbol::new("System`Cell"),
+            vec![
+                text_to_text_data(subtitle, opts),
+                Expr::from("Subtitle"),
+                margins(inherited(), Expr::from(0)),
+            ],
+        ),
+        anchors,
+    ));
+
+    cells
+}
+
+/// Returns a `"Text"` cell containing a bulleted list of links to each heading in
+/// `blocks`, indented by heading level, or `None` if there are no headings.
+///
+/// The links target the `CellTags` of each heading cell. Headings without a `{#id}`
+/// attribute are given an id derived from their text, e.g. `getting-started`.
+pub fn table_of_contents_cell(blocks: &mut [Block], opts: &Options) -> Option<Expr> {
+    crate::ast::assign_heading_ids(blocks);
+
+    let headings: Vec<(HeadingLevel, Text, String)> = blocks
+        .iter()
+        .filter_map(|block| match block {
+            Block::Heading {
+                level,
+                text,
+                id: Some(id),
+                ..
+            } => Some((*level, text.clone(), id.clone())),
+            _ => None,
+        })
+        .collect();
+
+    let top_level = headings.iter().map(|(level, _, _)| *level).min()?;
+
+    let mut row = Vec::new();
+
+    for (index, (level, text, id)) in headings.into_iter().enumerate() {
+        if index > 0 {
+            row.push(Expr::string("\n"));
+        }
+
+        let indent = "\t".repeat(level as usize - top_level as usize);
+        row.push(Expr::string(format!("{indent}\u{2022} ")));
+
+        // ButtonBox[label, BaseStyle -> "Link", ButtonData -> "id"]
+        row.push(Expr::normal(
+            Symbol::new("System`ButtonBox"),
+            vec![
+                text_to_boxes_(text, opts, None),
+                Expr::rule(Symbol::new("System`BaseStyle"), Expr::string("Link")),
+                Expr::rule(Symbol::new("System`ButtonData"), Expr::string(id)),
+            ],
+        ));
+    }
+
+    Some(Expr::normal(
+        Symbol::new("System`Cell"),
+        vec![
+            Expr::normal(Symbol::new("System`TextData"), vec![Expr::list(row)]),
+            Expr::string("Text"),
+        ],
+    ))
+}
+
+/// Split `blocks` into sections that each begin with a heading at `level`.
+///
+/// Each section is returned with a slug of its heading text, for use as a file name.
+/// Blocks before the first such heading are returned as a section named `"intro"`.
+pub fn split_by_heading(
+    blocks: Vec<Block>,
+    level: HeadingLevel,
+) -> Vec<(String, Vec<Block>)> {
+    let mut sections: Vec<(String, Vec<Block>)> = Vec::new();
+
+    for block in blocks {
+        match &block {
+            Block::Heading {
+                level: heading_level,
+                text,
+                ..
+            } if *heading_level == level => {
+                let mut name = crate::ast::slugify(&text_content(text));
+                if name.is_empty() {
+                    name = format!("section-{}", sections.len() + 1);
+                }
+
+                sections.push((name, vec![block]))
+            },
+            _ => match sections.last_mut() {
+                Some((_, section)) => section.push(block),
+                None => sections.push(("intro".to_owned(), vec![block])),
+            },
+        }
+    }
+
+    sections
+}
+
+/// Wrap `cells` in a single top-level `CellGroupData[{...}, Open]` cell group, headed
+/// by a `"Title"` cell.
+///
+/// If the first cell is already a `"Title"` cell (because the document begins with an
+/// H1 heading), it is used as the group title. Otherwise, a new `"Title"` cell
+/// containing `default_title` is added.
+pub fn wrap_in_group(mut cells: Vec<Expr>, default_title: &str) -> Expr {
+    let starts_with_title = cells
+        .first()
+        .map(|cell| cell_style(cell) == Some("Title"))
+        .unwrap_or(false);
+
+    if !starts_with_title {
+        cells.insert(
+            0,
+            Expr::normal(
+                Symbol::new("System`Cell"),
+                vec![Expr::string(default_title), Expr::string("Title")],
+            ),
+        );
+    }
+
+    cell_group(cells)
+}
+
+/// Group each heading cell with the cells that follow it, up to the next heading at
+/// the same or a higher level, into a `CellGroupData[{...}, Open]` cell group.
+///
+/// Headings are ranked by cell style, from `"Title"` down to `"Subsubsubsection"`.
+/// Cells with other styles are never the head of a group. The contents of existing
+/// cell groups, like the group created by [`wrap_in_group()`], are grouped after the
+/// first cell of the group.
+pub fn group_cells_by_heading(cells: Vec<Expr>) -> Vec<Expr> {
+    // The heading rank and cells of each group that is still open.
+    let mut open_groups: Vec<(u8, Vec<Expr>)> = Vec::new();
+    let mut grouped = Vec::new();
+
+    fn close_group(open_groups: &mut Vec<(u8, Vec<Expr>)>, grouped: &mut Vec<Expr>) {
+        let (_, mut cells) = open_groups.pop().expect("no open cell group");
+
+        let group = if cells.len() == 1 {
+            cells.remove(0)
+        } else {
+            cell_group(cells)
+        };
+
+        match open_groups.last_mut() {
+            Some((_, parent)) => parent.push(group),
+            None => grouped.push(group),
+        }
+    }
+
+    for cell in cells {
+        let cell = group_existing_cell_group(cell);
+
+        if let Some(rank) = cell_style(&cell).and_then(heading_rank) {
+            while open_groups.last().is_some_and(|(open, _)| *open >= rank) {
+                close_group(&mut open_groups, &mut grouped);
+            }
+
+            open_groups.push((rank, vec![cell]));
+        } else {
+            match open_groups.last_mut() {
+                Some((_, group)) => group.push(cell),
+                None => grouped.push(cell),
+            }
+        }
+    }
+
+    while !open_groups.is_empty() {
+        close_group(&mut open_groups, &mut grouped);
+    }
+
+    grouped
+}
+
+/// If `cell` is a `Cell[CellGroupData[{first, rest...}, ..]]`, group the `rest` cells
+/// by heading.
+fn group_existing_cell_group(cell: Expr) -> Expr {
+    let group = match cell.try_normal() {
+        Some(normal) if normal.has_head(&Symbol::new("System`Cell")) => {
+            match normal.elements() {
+                [group] => group.try_normal(),
+                _ => None,
+            }
+        },
+        _ => None,
+    };
+
+    let group = match group {
+        Some(group) if group.has_head(&Symbol::new("System`CellGroupData")) => group,
+        _ => return cell,
+    };
+
+    let mut group_args = group.elements().to_vec();
+
+    if let Some([first, rest @ ..]) = group_args
+        .first()
+        .and_then(Expr::try_normal)
+        .map(|cells| cells.elements())
+    {
+        let mut cells = vec![first.clone()];
+        cells.extend(group_cells_by_heading(rest.to_vec()));
+        group_args[0] = Expr::list(cells);
+    }
+
+    Expr::normal(
+        Symbol::new("System`Cell"),
+        vec![Expr::normal(
+            Symbol::new("System`CellGroupData"),
+            group_args,
+        )],
+    )
+}
+
+/// Returns the rank of the heading cell style `style`, where `"Title"` is the highest
+/// rank (0), or `None` if `style` is not a heading style.
+fn heading_rank(style: &str) -> Option<u8> {
+    let rank = match style {
+        "Title" => 0,
+        "Subtitle" => 1,
+        "Chapter" => 2,
+        "Subchapter" => 3,
+        "Section" => 4,
+        "Subsection" => 5,
+        "Subsubsection" => 6,
+        "Subsubsubsection" => 7,
+        _ => return None,
+    };
+
+    Some(rank)
+}
+
+/// Returns a `Cell[CellGroupData[{cells}, Open]]` cell group.
+fn cell_group(cells: Vec<Expr>) -> Expr {
+    Expr::normal(
+        Symbol::new("System`Cell"),
+        vec![Expr::normal(
+            Symbol::new("System`CellGroupData"),
+            vec![Expr::list(cells), Expr::from(Symbol::new("System`Open"))],
+        )],
+    )
+}
+
+/// A Markdown construct that can't be converted to cells yet.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Unsupported(pub &'static str);
+
+impl fmt::Display for Unsupported {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let Unsupported(construct) = self;
+        write!(f, "{construct}")
+    }
+}
+
+/// Convert `block` into a sequence of `Cell[..]` expressions.
+///
+/// # Panics
+///
+/// Panics if `block` contains Markdown that can't be converted yet. Use
+/// [`try_block_to_cells()`] to handle that case instead.
+pub fn block_to_cells(block: Block, opts: &Options) -> Vec<Expr> {
+    match try_block_to_cells(block, opts) {
+        Ok(cells) => cells,
+        Err(Unsupported(construct)) => todo!("{construct}"),
+    }
+}
+
+/// Convert `block` to cells like [`block_to_cells()`], but return the unsupported
+/// construct if `block` contains Markdown that can't be converted yet.
+pub fn try_block_to_cells(
+    block: Block,
+    opts: &Options,
+) -> Result<Vec<Expr>, Unsupported> {
+    let mut state = State {
+        list_depth: 0,
+        list_ordered: false,
+        list_tight: false,
+    };
+
+    block_to_cells_(&mut state, opts, block)
+}
+
+/// Returns a placeholder `"Text"` cell standing in for an unsupported `construct`.
+pub fn unsupported_cell(Unsupported(construct): Unsupported) -> Expr {
+    Expr::normal(
+        Symbol::new("System`Cell"),
+        vec![
+            Expr::string(format!("[unsupported: {construct}]")),
+            Expr::string("Text"),
+        ],
+    )
+}
+
+fn block_to_cells_(
+    state: &mut State,
+    opts: &Options,
+    block: Block,
+) -> Result<Vec<Expr>, Unsupported> {
+    let cells = match block {
+        Block::Heading {
+            level,
+            text,
+            id,
+            classes: _,
+        } => {
+            let style = match opts.heading_styles.get(&level) {
+                Some(style) => style.as_str(),
+                None => default_heading_style(level),
+            };
+
+            // A `{#id}` heading attribute is a cell tag, like an HTML anchor.
+            let mut anchors = text_anchors(&text);
+            anchors.extend(id);
+
+            vec![with_cell_tags(
+                Expr::normal(
+                    Symbol::new("System`Cell"),
+                    vec![text_to_text_data(text, opts), Expr::from(style)],
+                ),
+                anchors,
+            )]
+        },
+        // An image on its own is a standalone picture cell.
+        Block::Paragraph(Text(spans))
+            if matches!(spans.as_slice(), [TextSpan::Image { .. }]) =>
+        {
+            let (alt, destination) = match spans.into_iter().next() {
+                Some(TextSpan::Image { alt, destination }) => (alt, destination),
+                _ => unreachable!(),
+            };
+
+            vec![Expr::normal(
+                Symbol::new("System`Cell"),
                 vec![
-                    text_to_boxes(label),
+                    image_box_data(&alt, &destination, opts.base_directory.as_deref()),
+                    Expr::from("Text"),
+                ],
+            )]
+        },
+        Block::Paragraph(text) => {
+            let texts = match opts.max_cell_chars {
+                Some(max_chars) => split_text(text, max_chars),
+                None => vec![text],
+            };
+
+            texts
+                .into_iter()
+                .map(|text| {
+                    let anchors = text_anchors(&text);
+
+                    with_cell_tags(
+                        Expr::normal(
+                            Symbol::new("System`Cell"),
+                            vec![text_to_text_data(text, opts), Expr::from("Text")],
+                        ),
+                        anchors,
+                    )
+                })
+                .collect()
+        },
+        Block::List {
+            items,
+            ordered,
+            start,
+            tight,
+        } => list_to_cells(state, opts, items, ordered, start, tight)?,
+        Block::CodeBlock {
+            info_string: label,
+            code: code_text,
+            lines,
+        } => {
+            // Ignore any stray formatting characters, e.g. `*python*`.
+            let language: Option<String> = label.as_deref().map(|info_string| {
+                info_string_language(info_string)
+                    .chars()
+                    .filter(|c| c.is_alphanumeric() || *c == '-')
+                    .flat_map(char::to_lowercase)
+                    .collect()
+            });
+
+            // A literal `Cell[...]` expression, spliced into the notebook as-is. The
+            // Kernel evaluates `ToExpression[..]` when the cell is written by
+            // `NotebookWrite`.
+            if language.as_deref() == Some("nb-cell") {
+                return Ok(vec![Expr::normal(
+                    Symbol::new("System`ToExpression"),
+                    vec![Expr::string(code_text.trim())],
+                )]);
+            }
+
+            // The `CellEvaluationLanguage` of the code, or "Input" for Wolfram
+            // Language code, which is evaluated by the notebook's own Kernel.
+            let builtin_language: Option<&str> =
+                // The languages listed here should be all of those currently supported
+                // by ExternalEvaluate.
+                match language.as_deref() {
+                    Some("wolfram" | "wl" | "wolfram-language" | "mathematica") => {
+                        Some("Input")
+                    },
+                    Some("python" | "python3") => Some("Python"),
+                    Some("shell" | "bash" | "sh" | "zsh") => Some("Shell"),
+                    Some("julia") => Some("Julia"),
+                    Some("r") => Some("R"),
+                    Some("ruby" | "rb") => Some("Ruby"),
+                    Some("octave") => Some("Octave"),
+                    Some("java") => Some("Java"),
+                    Some("node" | "nodejs" | "js" | "javascript") => Some("NodeJS"),
+                    Some("jupyter") => Some("Jupyter"),
+                    Some("sql") => Some("SQL"),
+                    Some("sql-jdbc") => Some("SQL-JDBC"),
+                    Some(_) => None,
+                    None => None,
+                };
+
+            // Languages mapped by the user override the built-in languages. Code
+            // mapped to "Program" is never evaluated.
+            let evaluation_language = match language
+                .as_deref()
+                .and_then(|language| opts.language_map.get(language))
+            {
+                Some(target) if target == "Program" => None,
+                Some(target) => Some(target.as_str()),
+                None => builtin_language,
+            };
+
+            let cell = match evaluation_language {
+                Some("Input") => {
+                    let contents = if opts.parse_wolfram_code {
+                        wolfram_code_box_data(&code_text)
+                    } else {
+                        Expr::string(code_text)
+                    };
+
+                    let mut cell_args = vec![contents, Expr::string("Input")];
+                    cell_args.extend(cell_auto_overwrite_option(opts));
+
+                    Expr::normal(Symbol::new("System`Cell"), cell_args)
+                },
+                // Only create "ExternalLanguage" cells if the option is set (enabled by
+                // default).
+                Some(lang) if opts.create_external_language_cells => {
+                    let mut cell_args = vec![
+                        Expr::string(code_text),
+                        Expr::string("ExternalLanguage"),
+                        Expr::rule(
+                            Symbol::new("System`CellEvaluationLanguage"),
+                            Expr::string(lang),
+                        ),
+                    ];
+
+                    // Evaluator -> "<name>"
+                    if let Some(evaluator) = &opts.evaluator {
+                        cell_args.push(Expr::rule(
+                            Symbol::new("System`Evaluator"),
+                            Expr::string(evaluator.as_str()),
+                        ));
+                    }
+
+                    // CellAutoOverwrite -> True
+                    cell_args.extend(cell_auto_overwrite_option(opts));
+
+                    Expr::normal(Symbol::new("System`Cell"), cell_args)
+                },
+                _ if opts.code_gutter && code_text.trim_end().contains('\n') => {
                     Expr::normal(
-                        Symbol::new("System`Rule"),
+                        Symbol::new("System`Cell"),
+                        vec![code_gutter_boxes(&code_text), Expr::string("Program")],
+                    )
+                },
+                _ => Expr::normal(
+                    Symbol::new("System`Cell"),
+                    vec![Expr::string(code_text), Expr::string("Program")],
+                ),
+            };
+
+            // Background -> the theme color
+            let cell = match opts.theme.code_block_background {
+                Some(color) => cell_with_options(
+                    cell,
+                    vec![Expr::rule(
+                        Symbol::new("System`Background"),
+                        color_expr(color),
+                    )],
+                ),
+                None => cell,
+            };
+
+            match lines {
+                // CellLabel -> "lines 3-5", ShowCellLabel -> True
+                Some(SourceLines { first, last }) if opts.code_line_numbers => {
+                    vec![cell_with_options(
+                        cell,
                         vec![
-                            Expr::from(Symbol::new("System`BaseStyle")),
-                            Expr::string("Hyperlink"),
+                            Expr::rule(
+                                Symbol::new("System`CellLabel"),
+                                Expr::string(format!("lines {first}-{last}")),
+                            ),
+                            Expr::rule(
+                                Symbol::new("System`ShowCellLabel"),
+                                Expr::from(Symbol::new("System`True")),
+                            ),
                         ],
-                    ),
+                    )]
+                },
+                _ => vec![cell],
+            }
+        },
+        Block::BlockQuote(quote_blocks) => block_quote_to_cells(opts, quote_blocks)?,
+        Block::Table {
+            alignments,
+            headers,
+            rows,
+        } => {
+            if opts.table_as_dataset {
+                if let Some(cell) = dataset_cell(&headers, &rows) {
+                    return Ok(vec![cell]);
+                }
+            }
+
+            let mut grid_rows: Vec<Expr> = Vec::new();
+
+            let header_row = headers
+                .into_iter()
+                .map(|content: Text| {
                     Expr::normal(
-                        Symbol::new("System`Rule"),
+                        Symbol::new("System`Cell"),
                         vec![
-                            Expr::from(Symbol::new("System`ButtonData")),
-                            Expr::normal(
-                                Symbol::new("System`List"),
-                                vec![
-                                    Expr::normal(
-                                        Symbol::new("System`URL"),
-                                        vec![Expr::string(destination.clone())],
-                                    ),
-                                    Expr::from(Symbol::new("System`None")),
-                                ],
-                            ),
-                        ],
-                    ),
-                    Expr::normal(
-                        Symbol::new("System`Rule"),
-                        vec![
-                            Expr::from(Symbol::new("System`ButtonNote")),
-                            Expr::string(destination),
+                            text_to_text_data(content, opts),
+                            Expr::from("Subsubsubsection"),
                         ],
+                    )
+                })
+                .collect();
+
+            grid_rows.push(Expr::list(header_row));
+
+            for row_content in rows {
+                let row: Vec<Expr> = row_content
+                    .into_iter()
+                    .map(|content: Text| {
+                        Expr::normal(
+                            Symbol::new("System`Cell"),
+                            vec![text_to_text_data(content, opts), Expr::from("Text")],
+                        )
+                    })
+                    .collect();
+
+                grid_rows.push(Expr::list(row));
+            }
+
+            let mut grid_box_args = vec![
+                Expr::list(grid_rows),
+                // GridBoxItemSize -> {
+                //     "Columns" -> {{Automatic}},
+                //     "Rows" -> {{Automatic}}
+                // }
+                Expr::rule(
+                    Symbol::new("System`GridBoxItemSize"),
+                    Expr::list(vec![
+                        Expr::rule(
+                            Expr::from("Columns"),
+                            Expr::list(vec![Expr::list(vec![Expr::from(Symbol::new(
+                                "System`Automatic",
+                            ))])]),
+                        ),
+                        Expr::rule(
+                            Expr::from("Rows"),
+                            Expr::list(vec![Expr::list(vec![Expr::from(Symbol::new(
+                                "System`Automatic",
+                            ))])]),
+                        ),
+                    ]),
+                ),
+            ];
+
+            // GridBoxAlignment -> {"Columns" -> {Left, Right, Automatic, ...}}
+            if alignments
+                .iter()
+                .any(|alignment| *alignment != Alignment::None)
+            {
+                let columns = alignments
+                    .iter()
+                    .map(|alignment| {
+                        Expr::from(Symbol::new(match alignment {
+                            Alignment::None => "System`Automatic",
+                            Alignment::Left => "System`Left",
+                            Alignment::Center => "System`Center",
+                            Alignment::Right => "System`Right",
+                        }))
+                    })
+                    .collect();
+
+                grid_box_args.push(Expr::rule(
+                    Symbol::new("System`GridBoxAlignment"),
+                    Expr::list(vec![Expr::rule(
+                        Expr::from("Columns"),
+                        Expr::list(columns),
+                    )]),
+                ));
+            }
+
+            let grid_box = Expr::normal(Symbol::new("System`GridBox"), grid_box_args);
+
+            let mut cell_args = vec![
+                Expr::normal(Symbol::new("System`BoxData"), vec![grid_box]),
+                Expr::from("Text"),
+            ];
+
+            // TextAlignment -> Center
+            if opts.center_tables {
+                cell_args.push(Expr::rule(
+                    Symbol::new("System`TextAlignment"),
+                    Expr::from(Symbol::new("System`Center")),
+                ));
+            }
+
+            vec![Expr::normal(Symbol::new("System`Cell"), cell_args)]
+        },
+        Block::Rule => vec![rule_cell(opts.rule_style)],
+        // HTML can't be displayed in a notebook, so show the markup verbatim.
+        Block::Html(html) => vec![Expr::normal(
+            Symbol::new("System`Cell"),
+            vec![Expr::string(html), Expr::string("Program")],
+        )],
+        Block::DisplayMath(tex) => vec![Expr::normal(
+            Symbol::new("System`Cell"),
+            vec![
+                Expr::normal(Symbol::new("System`BoxData"), vec![math_boxes(&tex)]),
+                Expr::from("Input"),
+            ],
+        )],
+        Block::FootnoteDefinition { label, mut blocks } => {
+            // Begin the footnote with its label, e.g. "1. Footnote text".
+            if let Some(Block::Paragraph(Text(spans))) = blocks.first_mut() {
+                spans.insert(0, TextSpan::Text(format!("{label}. "), Default::default()));
+            }
+
+            let mut cells = Vec::new();
+            for block in blocks {
+                cells.extend(block_to_cells_(state, opts, block)?);
+            }
+
+            // Tag the first cell, so that the references to this footnote link to it.
+            if !cells.is_empty() {
+                let first = cells.remove(0);
+                cells.insert(0, with_cell_tags(first, vec![footnote_tag(&label)]));
+            }
+
+            cells
+        },
+    };
+
+    Ok(cells)
+}
+
+/// Returns the cell tag of the footnote with `label`.
+fn footnote_tag(label: &str) -> String {
+    format!("footnote-{label}")
+}
+
+/// Returns an `"Input"` cell containing `Dataset[{<|header -> value, ...|>, ...}]`
+/// code for a table, or `None` if the table contains anything other than plain text.
+fn dataset_cell(headers: &[Text], rows: &[Vec<Text>]) -> Option<Expr> {
+    let headers: Vec<String> = headers.iter().map(plain_text).collect::<Option<_>>()?;
+
+    // Duplicate column names can't be distinguished in an Association.
+    if (1..headers.len()).any(|i| headers[..i].contains(&headers[i])) {
+        return None;
+    }
+
+    let mut row_exprs = Vec::new();
+
+    for row in rows {
+        let rules: Vec<String> = headers
+            .iter()
+            .zip(row)
+            .map(|(header, value)| {
+                let value = plain_text(value)?;
+
+                // Keep numbers numeric, so that the column sorts numerically.
+                let is_number = {
+                    let digits = value.strip_prefix('-').unwrap_or(&value);
+                    let (whole, fraction) =
+                        digits.split_once('.').unwrap_or((digits, "0"));
+
+                    [whole, fraction].iter().all(|part| {
+                        !part.is_empty() && part.chars().all(|c| c.is_ascii_digit())
+                    })
+                };
+
+                let value = if is_number {
+                    value
+                } else {
+                    wolfram_string(&value)
+                };
+
+                Some(format!("{} -> {value}", wolfram_string(header)))
+            })
+            .collect::<Option<_>>()?;
+
+        row_exprs.push(format!("<|{}|>", rules.join(", ")));
+    }
+
+    Some(Expr::normal(
+        Symbol::new("System`Cell"),
+        vec![
+            Expr::string(format!("Dataset[{{{}}}]", row_exprs.join(", "))),
+            Expr::string("Input"),
+        ],
+    ))
+}
+
+/// Returns the content of `text` if it is only unstyled text.
+fn plain_text(Text(spans): &Text) -> Option<String> {
+    spans
+        .iter()
+        .map(|span| match span {
+            TextSpan::Text(text, styles) if styles.is_empty() => Some(text.as_str()),
+            _ => None,
+        })
+        .collect()
+}
+
+/// Returns `string` as a Wolfram Language string literal.
+fn wolfram_string(string: &str) -> String {
+    format!("\"{}\"", string.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+fn block_quote_to_cells(
+    opts: &Options,
+    quote_blocks: Vec<Block>,
+) -> Result<Vec<Expr>, Unsupported> {
+    let contains_code = quote_blocks
+        .iter()
+        .any(|block| matches!(block, Block::CodeBlock { .. }));
+
+    if !contains_code {
+        return Ok(vec![block_quote_cell(opts, quote_blocks)?]);
+    }
+
+    // Code blocks can't be rendered inside the single merged block quote cell, so
+    // instead emit a group of framed cells. Consecutive non-code blocks are merged into
+    // a block quote cell, and each code block becomes a framed cell of its own.
+    let mut group_cells = Vec::new();
+    let mut pending_blocks = Vec::new();
+
+    for block in quote_blocks {
+        if let Block::CodeBlock { .. } = block {
+            if !pending_blocks.is_empty() {
+                group_cells.push(block_quote_cell(opts, mem::take(&mut pending_blocks))?);
+            }
+
+            for cell in try_block_to_cells(block, opts)? {
+                group_cells
+                    .push(cell_with_options(cell, block_quote_options(&opts.theme)));
+            }
+        } else {
+            pending_blocks.push(block);
+        }
+    }
+
+    if !pending_blocks.is_empty() {
+        group_cells.push(block_quote_cell(opts, pending_blocks)?);
+    }
+
+    Ok(vec![Expr::normal(
+        Symbol::new("System`Cell"),
+        vec![Expr::normal(
+            Symbol::new("System`CellGroupData"),
+            vec![
+                Expr::list(group_cells),
+                Expr::from(Symbol::new("System`Open")),
+            ],
+        )],
+    )])
+}
+
+/// Returns the cell used to display a Markdown thematic break.
+fn rule_cell(style: RuleStyle) -> Expr {
+    match style {
+        // Note: This formatting is based on the menu item:
+        //         Insert > Horizontal Line > Paste Thick Line Object
+        //       but with a thinner 1pt frame.
+        // TODO:
+        //     Support inserting lines of different thickness, and with different
+        //     left-side margins based on context.
+        //
+        //     For example, `***` is supported within block quotes. Improve how it
+        //     looks when used in that context.
+        RuleStyle::Line => Expr::normal(
+            Symbol::new("System`Cell"),
+            vec![
+                Expr::string(""),
+                Expr::string("Text"),
+                // Editable->False,
+                Expr::rule(
+                    Symbol::new("System`Editable"),
+                    Expr::symbol(Symbol::new("System`False")),
+                ),
+                // Selectable->False,
+                // CellFrame->{{0, 0}, {0, 1}},
+                Expr::rule(
+                    Symbol::new("System`CellFrame"),
+                    Expr::list(vec![
+                        Expr::list(vec![Expr::from(0), Expr::from(0)]),
+                        Expr::list(vec![Expr::from(0), Expr::from(1)]),
+                    ]),
+                ),
+                // ShowCellBracket->False,
+                Expr::rule(
+                    Symbol::new("System`ShowCellBracket"),
+                    Expr::symbol(Symbol::new("System`False")),
+                ),
+                // CellMargins->{{0, 0}, {1, 1}},
+                Expr::rule(
+                    Symbol::new("System`CellMargins"),
+                    Expr::list(vec![
+                        Expr::list(vec![Expr::from(0), Expr::from(0)]),
+                        Expr::list(vec![Expr::from(1), Expr::from(1)]),
+                    ]),
+                ),
+                // CellElementSpacings->{"CellMinHeight"->1},
+                Expr::rule(
+                    Symbol::new("System`CellElementSpacings"),
+                    Expr::list(vec![Expr::rule(
+                        Expr::from("CellMinHeight"),
+                        Expr::from(1),
+                    )]),
+                ),
+                // CellFrameMargins->0,
+                Expr::rule(Symbol::new("System`CellFrameMargins"), Expr::from(0)),
+                // CellFrameColor->GrayLevel[0.75],
+                Expr::rule(
+                    Symbol::new("System`CellFrameColor"),
+                    Expr::normal(Symbol::new("System`GrayLevel"), vec![Expr::real(0.75)]),
+                ),
+                // CellSize->{Inherited, 5}
+                Expr::rule(
+                    Symbol::new("System`CellSize"),
+                    Expr::list(vec![
+                        Expr::from(Symbol::new("System`Inherited")),
+                        Expr::from(5),
+                    ]),
+                ),
+            ],
+        ),
+        // Cell["* * *", "Text", TextAlignment -> Center, Editable -> False]
+        RuleStyle::Dots => Expr::normal(
+            Symbol::new("System`Cell"),
+            vec![
+                Expr::string("*   *   *"),
+                Expr::string("Text"),
+                Expr::rule(
+                    Symbol::new("System`TextAlignment"),
+                    Expr::from(Symbol::new("System`Center")),
+                ),
+                Expr::rule(
+                    Symbol::new("System`Editable"),
+                    Expr::from(Symbol::new("System`False")),
+                ),
+            ],
+        ),
+        // Cell["", "Text", CellSize -> {Inherited, 30}, Editable -> False,
+        //      ShowCellBracket -> False]
+        RuleStyle::Space => Expr::normal(
+            Symbol::new("System`Cell"),
+            vec![
+                Expr::string(""),
+                Expr::string("Text"),
+                Expr::rule(
+                    Symbol::new("System`CellSize"),
+                    Expr::list(vec![
+                        Expr::from(Symbol::new("System`Inherited")),
+                        Expr::from(30),
+                    ]),
+                ),
+                Expr::rule(
+                    Symbol::new("System`Editable"),
+                    Expr::from(Symbol::new("System`False")),
+                ),
+                Expr::rule(
+                    Symbol::new("System`ShowCellBracket"),
+                    Expr::from(Symbol::new("System`False")),
+                ),
+            ],
+        ),
+    }
+}
+
+/// Returns a single framed `Cell` containing the cells for `quote_blocks`.
+fn block_quote_cell(
+    opts: &Options,
+    quote_blocks: Vec<Block>,
+) -> Result<Expr, Unsupported> {
+    let mut quote_cells = Vec::new();
+    for block in quote_blocks {
+        quote_cells.extend(try_block_to_cells(block, opts)?);
+    }
+
+    // TODO: Use a dedicated "BlockQuote" cell style. There is no "BlockQuote"
+    //       style in the default Wolfram notebook stylesheet, but we could add
+    //       a StyleData definition to this notebook.
+    let mut cell_args = vec![
+        Expr::normal(Symbol::new("System`BoxData"), vec![Expr::list(quote_cells)]),
+        Expr::string("Text"),
+    ];
+    cell_args.extend(block_quote_options(&opts.theme));
+
+    Ok(Expr::normal(Symbol::new("System`Cell"), cell_args))
+}
+
+/// Returns the cell options that give a cell the appearance of a block quote.
+fn block_quote_options(theme: &Theme) -> Vec<Expr> {
+    let gray_level = |level: f64| {
+        Expr::normal(Symbol::new("System`GrayLevel"), vec![Expr::real(level)])
+    };
+
+    vec![
+        // Only the left side should have a frame:
+        //   CellFrame -> {{4, 0}, {0, 0}}
+        Expr::rule(
+            Symbol::new("System`CellFrame"),
+            Expr::list(vec![
+                Expr::list(vec![Expr::from(4), Expr::from(0)]),
+                Expr::list(vec![Expr::from(0), Expr::from(0)]),
+            ]),
+        ),
+        // Unless set by the theme, the cell frame should have a medium-light gray
+        // color:
+        //   CellFrameColor -> GrayLevel[0.8]
+        Expr::rule(
+            Symbol::new("System`CellFrameColor"),
+            theme
+                .block_quote_frame_color
+                .map(color_expr)
+                .unwrap_or_else(|| gray_level(0.8)),
+        ),
+        // Unless set by the theme, the cell background should be a light gray color:
+        //   Background -> GrayLevel[0.95]
+        Expr::rule(
+            Symbol::new("System`Background"),
+            theme
+                .block_quote_background
+                .map(color_expr)
+                .unwrap_or_else(|| gray_level(0.95)),
+        ),
+    ]
+}
+
+/// Returns the language named by the info string of a fenced code block.
+///
+/// The language is the first word of the info string. Pandoc-style attribute info
+/// strings, like `{.python .numberLines}`, name the language with their first class.
+fn info_string_language(info_string: &str) -> &str {
+    let attributes = info_string
+        .trim()
+        .strip_prefix('{')
+        .and_then(|rest| rest.strip_suffix('}'));
+
+    match attributes {
+        Some(attributes) => attributes
+            .split_whitespace()
+            .find_map(|attribute| attribute.strip_prefix('.'))
+            .unwrap_or(""),
+        None => info_string.split_whitespace().next().unwrap_or(""),
+    }
+}
+
+/// Returns a `BoxData[GridBox[...]]` expression with a row for each line of `code`:
+/// the line number, followed by the line itself.
+fn code_gutter_boxes(code: &str) -> Expr {
+    let rows = code
+        .lines()
+        .enumerate()
+        .map(|(index, line)| {
+            // StyleBox["<N>", FontColor -> GrayLevel[0.5]]
+            let line_number = Expr::normal(
+                Symbol::new("System`StyleBox"),
+                vec![
+                    Expr::string((index + 1).to_string()),
+                    Expr::rule(
+                        Symbol::new("System`FontColor"),
+                        Expr::normal(
+                            Symbol::new("System`GrayLevel"),
+                            vec![Expr::real(0.5)],
+                        ),
                     ),
                 ],
-            )),
-            TextSpan::SoftBreak => row.push(Expr::string(" ")),
-            TextSpan::HardBreak => row.push(Expr::string("\n")),
+            );
+
+            Expr::list(vec![line_number, Expr::string(line)])
+        })
+        .collect();
+
+    let grid_box = Expr::normal(
+        Symbol::new("System`GridBox"),
+        vec![
+            Expr::list(rows),
+            // GridBoxAlignment -> {"Columns" -> {Right, Left}}
+            Expr::rule(
+                Symbol::new("System`GridBoxAlignment"),
+                Expr::list(vec![Expr::rule(
+                    Expr::from("Columns"),
+                    Expr::list(vec![
+                        Expr::from(Symbol::new("System`Right")),
+                        Expr::from(Symbol::new("System`Left")),
+                    ]),
+                )]),
+            ),
+        ],
+    );
+
+    Expr::normal(Symbol::new("System`BoxData"), vec![grid_box])
+}
+
+/// Returns the `CellAutoOverwrite -> True` option, if [`Options::cell_auto_overwrite`]
+/// is set.
+fn cell_auto_overwrite_option(opts: &Options) -> Option<Expr> {
+    opts.cell_auto_overwrite.then(|| {
+        Expr::rule(
+            Symbol::new("System`CellAutoOverwrite"),
+            Expr::from(Symbol::new("System`True")),
+        )
+    })
+}
+
+/// Returns the style of a `Cell[contents, style, ...]` expression.
+fn cell_style(cell: &Expr) -> Option<&str> {
+    let normal = cell.try_normal()?;
+
+    if !normal.has_head(&Symbol::new("System`Cell")) {
+        return None;
+    }
+
+    match normal.elements().get(1)?.kind() {
+        ExprKind::String(style) => Some(style.as_str()),
+        _ => None,
+    }
+}
+
+/// Returns the cell style used for headings at `level`, unless overridden by
+/// [`Options::heading_styles`].
+fn default_heading_style(level: HeadingLevel) -> &'static str {
+    match level {
+        HeadingLevel::H1 => "Title",
+        HeadingLevel::H2 => "Chapter",
+        HeadingLevel::H3 => "Section",
+        HeadingLevel::H4 => "Subsection",
+        HeadingLevel::H5 => "Subsubsection",
+        HeadingLevel::H6 => "Subsubsubsection",
+    }
+}
+
+/// Returns the names of the [`TextSpan::Anchor`]s in `text`.
+fn text_anchors(Text(spans): &Text) -> Vec<String> {
+    spans
+        .iter()
+        .filter_map(|span| match span {
+            TextSpan::Anchor(name) => Some(name.clone()),
+            _ => None,
+        })
+        .collect()
+}
+
+/// Returns `cell` with a `CellTags -> {...}` option, if `tags` is non-empty.
+fn with_cell_tags(cell: Expr, tags: Vec<String>) -> Expr {
+    if tags.is_empty() {
+        return cell;
+    }
+
+    let tags = tags.into_iter().map(Expr::string).collect();
+
+    cell_with_options(
+        cell,
+        vec![Expr::rule(Symbol::new("System`CellTags"), Expr::list(tags))],
+    )
+}
+
+/// Returns `cell` with `options` appended to its existing elements.
+fn cell_with_options(cell: Expr, options: Vec<Expr>) -> Expr {
+    let normal = cell.try_normal().expect("expected Cell[..] expression");
+
+    let mut elements = normal.elements().to_vec();
+    elements.extend(options);
+
+    Expr::normal(normal.head().clone(), elements)
+}
+
+fn list_to_cells(
+    state: &mut State,
+    opts: &Options,
+    items: Vec<ListItem>,
+    ordered: bool,
+    start: u64,
+    tight: bool,
+) -> Result<Vec<Expr>, Unsupported> {
+    let mut list_cells = Vec::new();
+
+    let parent_ordered = std::mem::replace(&mut state.list_ordered, ordered);
+    let parent_tight = std::mem::replace(&mut state.list_tight, tight);
+    state.list_depth += 1;
+
+    for item in items {
+        list_cells.extend(list_item_to_cells(state, opts, item)?);
+    }
+
+    let style = list_item_style(state.list_depth, ordered);
+
+    state.list_depth -= 1;
+    state.list_ordered = parent_ordered;
+    state.list_tight = parent_tight;
+
+    // Set the counter used to number the items, so that the list starts at `start`
+    // instead of continuing the numbering of a previous list.
+    if ordered {
+        if let Some(first) = list_cells
+            .iter_mut()
+            .find(|cell| cell_style(cell) == Some(style))
+        {
+            // CounterAssignments -> {{"ItemNumbered", start - 1}}
+            *first = cell_with_options(
+                first.clone(),
+                vec![Expr::rule(
+                    Symbol::new("System`CounterAssignments"),
+                    Expr::list(vec![Expr::list(vec![
+                        Expr::string(style),
+                        Expr::from(i64::try_from(start).unwrap_or(i64::MAX) - 1),
+                    ])]),
+                )],
+            );
+        }
+    }
+
+    Ok(list_cells)
+}
+
+/// Returns the cell style used for the items of a list nested `depth` lists deep.
+fn list_item_style(depth: u8, ordered: bool) -> &'static str {
+    match (depth, ordered) {
+        (0, _) => panic!(),
+        (1, false) => "Item",
+        (2, false) => "Subitem",
+        (_, false) => "Subsubitem",
+        (1, true) => "ItemNumbered",
+        (2, true) => "SubitemNumbered",
+        (_, true) => "SubsubitemNumbered",
+    }
+}
+
+fn list_item_to_cells(
+    state: &mut State,
+    opts: &Options,
+    ListItem(blocks): ListItem,
+) -> Result<Vec<Expr>, Unsupported> {
+    let mut cells = vec![];
+
+    for block in blocks {
+        match block {
+            Block::Paragraph(text) => {
+                let style = list_item_style(state.list_depth, state.list_ordered);
+
+                let cell = Expr::normal(
+                    Symbol::new("System`Cell"),
+                    vec![text_to_text_data(text, opts), Expr::from(style)],
+                );
+
+                // There is no style for items nested more deeply than "Subsubitem",
+                // so indent them further instead.
+                let left_margin =
+                    (state.list_depth > 3).then(|| list_left_margin(state.list_depth));
+
+                // The items of a tight list have less space between them.
+                let vertical_margin = state.list_tight.then_some(TIGHT_LIST_ITEM_MARGIN);
+
+                if left_margin.is_some() || vertical_margin.is_some() {
+                    cells.push(cell_with_options(
+                        cell,
+                        vec![list_cell_margins(left_margin, vertical_margin)],
+                    ));
+                } else {
+                    cells.push(cell);
+                }
+            },
+            Block::List {
+                items,
+                ordered,
+                start,
+                tight,
+            } => {
+                cells.extend(list_to_cells(state, opts, items, ordered, start, tight)?);
+            },
+            // Footnote definitions are moved to the end of the document by
+            // `parse_markdown_to_ast()`, but may be nested in a hand-built list.
+            Block::BlockQuote(_)
+            | Block::CodeBlock { .. }
+            | Block::Table { .. }
+            | Block::DisplayMath(_)
+            | Block::Html(_)
+            | Block::FootnoteDefinition { .. } => {
+                let depth = state.list_depth;
+
+                cells.extend(
+                    block_to_cells_(state, opts, block)?
+                        .into_iter()
+                        .map(|cell| indent_to_list_depth(cell, depth)),
+                );
+            },
+            // Headings inside list items are displayed no larger than a
+            // "Subsubsection", so that they don't start a new top-level section.
+            Block::Heading {
+                level,
+                text,
+                id,
+                classes,
+            } => {
+                let depth = state.list_depth;
+                let heading = Block::Heading {
+                    level: level.max(HeadingLevel::H5),
+                    text,
+                    id,
+                    classes,
+                };
+
+                cells.extend(
+                    block_to_cells_(state, opts, heading)?
+                        .into_iter()
+                        .map(|cell| indent_to_list_depth(cell, depth)),
+                );
+            },
+            Block::Rule => return Err(Unsupported("markdown rule inside list item")),
+        }
+    }
+
+    Ok(cells)
+}
+
+/// Indent `cell` to align with the text of list items at `depth`.
+///
+/// The cells of a cell group are each indented. Expressions that aren't cells, like
+/// the `ToExpression[..]` of an `nb-cell` code block, are returned unchanged.
+fn indent_to_list_depth(cell: Expr, depth: u8) -> Expr {
+    let normal = match cell.try_normal() {
+        Some(normal) if normal.has_head(&Symbol::new("System`Cell")) => normal,
+        _ => return cell,
+    };
+
+    if let [group] = normal.elements() {
+        if let Some(group) = group.try_normal() {
+            if group.has_head(&Symbol::new("System`CellGroupData")) {
+                let mut group_args = group.elements().to_vec();
+
+                if let Some(cells) = group_args[0].try_normal() {
+                    let cells = cells
+                        .elements()
+                        .iter()
+                        .map(|cell| indent_to_list_depth(cell.clone(), depth))
+                        .collect();
+                    group_args[0] = Expr::list(cells);
+                }
+
+                return Expr::normal(
+                    Symbol::new("System`Cell"),
+                    vec![Expr::normal(
+                        Symbol::new("System`CellGroupData"),
+                        group_args,
+                    )],
+                );
+            }
         }
     }
 
+    cell_with_options(
+        cell,
+        vec![list_cell_margins(Some(list_left_margin(depth)), None)],
+    )
+}
+
+/// The top and bottom margin of the items of a tight list.
+const TIGHT_LIST_ITEM_MARGIN: i64 = 2;
+
+/// Returns the left margin of a cell in a list nested `depth` lists deep, continuing
+/// the indentation of the "Item", "Subitem", and "Subsubitem" styles in the default
+/// stylesheet.
+fn list_left_margin(depth: u8) -> i64 {
+    57 + 24 * i64::from(depth)
+}
+
+/// Returns a `CellMargins` option that sets the left margin and the top and bottom
+/// margins, if given, and otherwise inherits them from the cell style.
+fn list_cell_margins(left: Option<i64>, vertical: Option<i64>) -> Expr {
+    let margin = |margin: Option<i64>| match margin {
+        Some(margin) => Expr::from(margin),
+        None => Expr::from(Symbol::new("System`Inherited")),
+    };
+
+    // CellMargins -> {{left, Inherited}, {bottom, top}}
+    Expr::rule(
+        Symbol::new("System`CellMargins"),
+        Expr::list(vec![
+            Expr::list(vec![margin(left), margin(None)]),
+            Expr::list(vec![margin(vertical), margin(vertical)]),
+        ]),
+    )
+}
+
+/// Returns a `TextData[{...}]` expression.
+fn text_to_text_data(text: Text, opts: &Options) -> Expr {
     Expr::normal(
-        Symbol::new("System`RowBox"),
-        vec![Expr::normal(Symbol::new("System`List"), row)],
+        Symbol::new("System`TextData"),
+        vec![text_to_boxes(text, opts)],
     )
 }
+
+// Returns a `RowBox[{...}]` expression.
+fn text_to_boxes(text: Text, opts: &Options) -> Expr {
+    let mut wrap = opts.wrap_prose.map(|width| ProseWrap {
+        width,
+        column: 0,
+        after_space: false,
+    });
+
+    text_to_boxes_(text, opts, wrap.as_mut())
+}
+
+fn text_to_boxes_(text: Text, opts: &Options, mut wrap: Option<&mut ProseWrap>) -> Expr {
+    let mut row = Vec::new();
+
+    for span in text {
+        // A footnote reference is a superscript link to the footnote.
+        let span = match span {
+            TextSpan::FootnoteReference(label) => TextSpan::Link {
+                label: Text(vec![TextSpan::Text(
+                    label.clone(),
+                    [TextStyle::Superscript].into_iter().collect(),
+                )]),
+                destination: format!("#{}", footnote_tag(&label)),
+                title: None,
+            },
+            span => span,
+        };
+
+        // Inline code and links can't be broken, but may be moved to a new line.
+        if let Some(wrap) = wrap.as_deref_mut() {
+            let unbreakable_len = match &span {
+                TextSpan::Code(code) | TextSpan::InlineMath(code) => {
+                    Some(code.chars().count())
+                },
+                TextSpan::Link { label, .. } | TextSpan::Abbreviation { label, .. } => {
+                    Some(text_len(label))
+                },
+                TextSpan::Image { .. } => Some(1),
+                _ => None,
+            };
+
+            if let Some(len) = unbreakable_len {
+                if wrap.break_before(len) {
+                    row.push(Expr::string("\n"));
+                }
+            }
+        }
+
+        match span {
+            TextSpan::Text(text, styles) => {
+                let mut style_rules: Vec<Expr> = Vec::new();
+                let mut font_variations: Vec<Expr> = Vec::new();
+
+                for style in styles {
+                    let (lhs, rhs) = match style {
+                        TextStyle::Emphasis => {
+                            (Symbol::new("System`FontSlant"), Expr::string("Italic"))
+                        },
+                        TextStyle::Strong => {
+                            (Symbol::new("System`FontWeight"), Expr::string("Bold"))
+                        },
+                        // FontColor -> RGBColor[r, g, b]
+                        TextStyle::Color(color) => {
+                            (Symbol::new("System`FontColor"), color_expr(color))
+                        },
+                        // Background -> RGBColor[1, 1, 0]
+                        TextStyle::Highlight => (
+                            Symbol::new("System`Background"),
+                            color_expr(Color {
+                                red: 255,
+                                green: 255,
+                                blue: 0,
+                            }),
+                        ),
+                        // FontFamily -> "Source Code Pro"
+                        TextStyle::Keyboard => (
+                            Symbol::new("System`FontFamily"),
+                            Expr::string("Source Code Pro"),
+                        ),
+                        // The remaining styles are combined into a single
+                        // `FontVariations -> {...}` option.
+                        //
+                        //   "StrikeThrough" -> True
+                        TextStyle::Strikethrough => {
+                            font_variations.push(Expr::rule(
+                                Expr::string("StrikeThrough"),
+                                Expr::from(Symbol::new("System`True")),
+                            ));
+                            continue;
+                        },
+                        //   "CompatibilityType" -> "Subscript"
+                        TextStyle::Subscript => {
+                            font_variations.push(Expr::rule(
+                                Expr::string("CompatibilityType"),
+                                Expr::string("Subscript"),
+                            ));
+                            continue;
+                        },
+                        //   "CompatibilityType" -> "Superscript"
+                        TextStyle::Superscript => {
+                            font_variations.push(Expr::rule(
+                                Expr::string("CompatibilityType"),
+                                Expr::string("Superscript"),
+                            ));
+                            continue;
+                        },
+                    };
+
+                    style_rules.push(Expr::normal(
+                        Symbol::new("System`Rule"),
+                        vec![Expr::from(lhs), rhs],
+                    ));
+                }
+
+                if !font_variations.is_empty() {
+                    font_variations.sort_by_key(|rule| rule.to_string());
+
+                    style_rules.push(Expr::rule(
+                        Symbol::new("System`FontVariations"),
+                        Expr::list(font_variations),
+                    ));
+                }
+
+                // `styles` is unordered, so sort the rules to make the output
+                // deterministic.
+                style_rules.sort_by_key(|rule| rule.to_string());
+
+                let styled = |text: &str| -> Expr {
+                    if style_rules.is_empty() {
+                        Expr::string(text)
+                    } else {
+                        let mut args = vec![Expr::string(text)];
+                        args.extend(style_rules.iter().cloned());
+
+                        Expr::normal(Symbol::new("System`StyleBox"), args)
+                    }
+                };
+
+                let text = match wrap.as_deref_mut() {
+                    Some(wrap) => wrap.wrap_text(&text),
+                    None => text,
+                };
+
+                if opts.preserve_tabs && text.contains('\t') {
+                    for (index, segment) in text.split('\t').enumerate() {
+                        if index != 0 {
+                            row.push(Expr::string("\t"));
+                        }
+                        if !segment.is_empty() {
+                            row.push(styled(segment));
+                        }
+                    }
+                } else {
+                    row.push(styled(&text));
+                }
+            },
+            TextSpan::Code(code) => {
+                let mut args = vec![Expr::string(code), Expr::string("Code")];
+
+                // Background -> GrayLevel[0.95], or the theme color.
+                if let Some(color) = opts.theme.inline_code_background {
+                    args.push(Expr::rule(
+                        Symbol::new("System`Background"),
+                        color_expr(color),
+                    ));
+                } else if opts.inline_code_background {
+                    args.push(Expr::rule(
+                        Symbol::new("System`Background"),
+                        Expr::normal(
+                            Symbol::new("System`GrayLevel"),
+                            vec![Expr::real(0.95)],
+                        ),
+                    ));
+                }
+
+                row.push(Expr::normal(Symbol::new("System`StyleBox"), args))
+            },
+            // Links to a fragment, like `#install`, target the cell tagged with that
+            // anchor name.
+            //
+            // ButtonBox[label, BaseStyle -> "Link", ButtonData -> "install"]
+            TextSpan::Link {
+                label, destination, ..
+            } if destination.starts_with('#') => row.push(Expr::normal(
+                Symbol::new("System`ButtonBox"),
+                vec![
+                    text_to_boxes_(label, opts, None),
+                    Expr::rule(Symbol::new("System`BaseStyle"), Expr::string("Link")),
+                    Expr::rule(
+                        Symbol::new("System`ButtonData"),
+                        Expr::string(&destination[1..]),
+                    ),
+                ],
+            )),
+            TextSpan::Link {
+                label,
+                destination,
+                title,
+            } => row.push(Expr::normal(
+                Symbol::new("System`ButtonBox"),
+                vec![
+                    text_to_boxes_(label, opts, None),
+                    Expr::normal(
+                        Symbol::new("System`Rule"),
+                        vec![
+                            Expr::from(Symbol::new("System`BaseStyle")),
+                            Expr::string("Hyperlink"),
+                        ],
+                    ),
+                    Expr::normal(
+                        Symbol::new("System`Rule"),
+                        vec![
+                            Expr::from(Symbol::new("System`ButtonData")),
+                            Expr::normal(
+                                Symbol::new("System`List"),
+                                vec![
+                                    Expr::normal(
+                                        Symbol::new("System`URL"),
+                                        vec![Expr::string(destination.clone())],
+                                    ),
+                                    Expr::from(Symbol::new("System`None")),
+                                ],
+                            ),
+                        ],
+                    ),
+                    Expr::normal(
+                        Symbol::new("System`Rule"),
+                        vec![
+                            Expr::from(Symbol::new("System`ButtonNote")),
+                            Expr::string(
+                                title.unwrap_or_else(|| link_tooltip(&destination)),
+                            ),
+                        ],
+                    ),
+                ],
+            )),
+            // An image within text is an inline `Cell[BoxData[...]]`.
+            TextSpan::Image { alt, destination } => row.push(Expr::normal(
+                Symbol::new("System`Cell"),
+                vec![image_box_data(
+                    &alt,
+                    &destination,
+                    opts.base_directory.as_deref(),
+                )],
+            )),
+            TextSpan::InlineMath(tex) => row.push(Expr::normal(
+                Symbol::new("System`Cell"),
+                vec![Expr::normal(
+                    Symbol::new("System`BoxData"),
+                    vec![math_boxes(&tex)],
+                )],
+            )),
+            // TooltipBox[label, "expansion"]
+            TextSpan::Abbreviation { label, expansion } => row.push(Expr::normal(
+                Symbol::new("System`TooltipBox"),
+                vec![text_to_boxes_(label, opts, None), Expr::string(expansion)],
+            )),
+            // Anchors are converted to the CellTags of the containing cell.
+            TextSpan::FootnoteReference(_) => unreachable!("converted to a link above"),
+            TextSpan::Anchor(_) => (),
+            // CheckboxBox[True], followed by a space before the item text.
+            TextSpan::TaskListMarker(checked) => {
+                let checked = if checked {
+                    "System`True"
+                } else {
+                    "System`False"
+                };
+
+                row.push(Expr::normal(
+                    Symbol::new("System`CheckboxBox"),
+                    vec![Expr::from(Symbol::new(checked))],
+                ));
+                row.push(Expr::string(" "));
+            },
+            TextSpan::SoftBreak => match opts.soft_break {
+                SoftBreakMode::Space => {
+                    if let Some(wrap) = wrap.as_deref_mut() {
+                        wrap.column += 1;
+                        wrap.after_space = true;
+                    }
+                    row.push(Expr::string(" "))
+                },
+                SoftBreakMode::Newline => {
+                    if let Some(wrap) = wrap.as_deref_mut() {
+                        wrap.column = 0;
+                        wrap.after_space = false;
+                    }
+                    row.push(Expr::string("\n"))
+                },
+                SoftBreakMode::None => (),
+            },
+            TextSpan::HardBreak => {
+                if let Some(wrap) = wrap.as_deref_mut() {
+                    wrap.column = 0;
+                    wrap.after_space = false;
+                }
+                row.push(Expr::string("\n"))
+            },
+        }
+    }
+
+    Expr::normal(
+        Symbol::new("System`RowBox"),
+        vec![Expr::normal(Symbol::new("System`List"), row)],
+    )
+}
+
+/// Returns the tooltip shown for a link to `destination` that has no title: the
+/// bare address of `mailto:` links, and the destination of other links.
+fn link_tooltip(destination: &str) -> String {
+    destination
+        .strip_prefix("mailto:")
+        .unwrap_or(destination)
+        .to_owned()
+}
+
+/// Split `text` at word boundaries into pieces that are at most `max_chars`
+/// characters long.
+///
+/// Pieces may be longer than `max_chars` if they contain a single word, inline code
+/// span, or link that is longer than that.
+fn split_text(Text(spans): Text, max_chars: usize) -> Vec<Text> {
+    let mut pieces: Vec<Text> = Vec::new();
+
+    let mut current: Vec<TextSpan> = Vec::new();
+    let mut current_len = 0;
+
+    let mut finish = |current: &mut Vec<TextSpan>, current_len: &mut usize| {
+        if !current.is_empty() {
+            pieces.push(Text(mem::take(current)));
+        }
+        *current_len = 0;
+    };
+
+    for span in spans {
+        let (text, styles) = match span {
+            TextSpan::Text(text, styles) => (text, styles),
+            TextSpan::SoftBreak if current.is_empty() => continue,
+            span => {
+                let len = text_len(&Text(vec![span.clone()]));
+
+                if current_len + len > max_chars {
+                    finish(&mut current, &mut current_len);
+                }
+
+                current.push(span);
+                current_len += len;
+                continue;
+            },
+        };
+
+        // The byte offset and character index of each space in `text`. Finding these
+        // up front keeps splitting a huge line into many pieces linear in its length.
+        let spaces: Vec<(usize, usize)> = text
+            .char_indices()
+            .enumerate()
+            .filter(|(_, (_, c))| *c == ' ')
+            .map(|(char_index, (offset, _))| (offset, char_index))
+            .collect();
+        let text_chars = text.chars().count();
+
+        // The start of the remaining text, and the first space at or after it.
+        let mut start = 0;
+        let mut start_char = 0;
+        let mut next_space = 0;
+
+        while current_len + (text_chars - start_char) > max_chars {
+            let available = max_chars.saturating_sub(current_len);
+
+            // The last space that fits in the available space, or the first space if
+            // no word fits.
+            let fits = spaces[next_space..]
+                .iter()
+                .take_while(|(_, char_index)| char_index - start_char <= available)
+                .count();
+
+            let split = match fits {
+                0 if !current.is_empty() => {
+                    finish(&mut current, &mut current_len);
+                    continue;
+                },
+                0 if next_space < spaces.len() => next_space,
+                0 => break,
+                fits => next_space + fits - 1,
+            };
+
+            let (offset, char_index) = spaces[split];
+
+            if offset > start {
+                current.push(TextSpan::Text(
+                    text[start..offset].to_owned(),
+                    styles.clone(),
+                ));
+            }
+            finish(&mut current, &mut current_len);
+
+            start = offset + 1;
+            start_char = char_index + 1;
+            next_space = split + 1;
+        }
+
+        if start < text.len() {
+            current_len += text_chars - start_char;
+            current.push(TextSpan::Text(text[start..].to_owned(), styles));
+        }
+    }
+
+    finish(&mut current, &mut current_len);
+
+    pieces
+}
+
+/// Returns the characters of `text`, without any formatting.
+fn text_content(Text(spans): &Text) -> String {
+    spans
+        .iter()
+        .map(|span| match span {
+            TextSpan::Text(text, _)
+            | TextSpan::Code(text)
+            | TextSpan::InlineMath(text)
+            | TextSpan::FootnoteReference(text) => text.clone(),
+            TextSpan::Link { label, .. } | TextSpan::Abbreviation { label, .. } => {
+                text_content(label)
+            },
+            TextSpan::Image { alt, .. } => alt.clone(),
+            TextSpan::SoftBreak => " ".to_owned(),
+            TextSpan::HardBreak | TextSpan::Anchor(_) | TextSpan::TaskListMarker(_) => {
+                String::new()
+            },
+        })
+        .collect()
+}
+
+/// Returns the number of characters of visible text in `text`.
+fn text_len(Text(spans): &Text) -> usize {
+    spans
+        .iter()
+        .map(|span| match span {
+            TextSpan::Text(text, _)
+            | TextSpan::Code(text)
+            | TextSpan::InlineMath(text)
+            | TextSpan::FootnoteReference(text) => text.chars().count(),
+            TextSpan::Link { label, .. } | TextSpan::Abbreviation { label, .. } => {
+                text_len(label)
+            },
+            TextSpan::SoftBreak => 1,
+            TextSpan::HardBreak | TextSpan::Anchor(_) => 0,
+            // The checkbox and the following space.
+            TextSpan::TaskListMarker(_) => 2,
+            TextSpan::Image { .. } => 1,
+        })
+        .sum()
+}
+
+/// Returns boxes that display the TeX math `tex` in `TraditionalForm`.
+///
+/// The TeX is interpreted by the Kernel when the cell is written to the notebook.
+/// `HoldForm` prevents the interpreted expression from being evaluated, e.g. so that
+/// `\int_0^1 x \, dx` isn't displayed as `1/2`.
+fn math_boxes(tex: &str) -> Expr {
+    // ToBoxes[ToExpression["tex", TeXForm, HoldForm], TraditionalForm]
+    Expr::normal(
+        Symbol::new("System`ToBoxes"),
+        vec![
+            Expr::normal(
+                Symbol::new("System`ToExpression"),
+                vec![
+                    Expr::string(tex),
+                    Expr::from(Symbol::new("System`TeXForm")),
+                    Expr::from(Symbol::new("System`HoldForm")),
+                ],
+            ),
+            Expr::from(Symbol::new("System`TraditionalForm")),
+        ],
+    )
+}
+
+/// Returns a `BoxData[...]` expression containing the boxes of the Wolfram Language
+/// `code`.
+///
+/// The code is parsed by the Kernel when the cell is written to the notebook.
+/// `MakeBoxes` prevents the parsed expression from being evaluated. If `code`
+/// contains several expressions on separate lines, only the last is kept, so they
+/// should be joined with `;`.
+fn wolfram_code_box_data(code: &str) -> Expr {
+    // BoxData[ToExpression["code", InputForm, MakeBoxes]]
+    Expr::normal(
+        Symbol::new("System`BoxData"),
+        vec![Expr::normal(
+            Symbol::new("System`ToExpression"),
+            vec![
+                Expr::string(code.trim_end()),
+                Expr::from(Symbol::new("System`InputForm")),
+                Expr::from(Symbol::new("System`MakeBoxes")),
+            ],
+        )],
+    )
+}
+
+/// Returns a `BoxData[...]` expression that displays the image at `destination`.
+///
+/// The image is imported by the Kernel when the cell is written to the notebook.
+/// Relative file paths are resolved against `base_directory`, if set.
+fn image_box_data(alt: &str, destination: &str, base_directory: Option<&Path>) -> Expr {
+    let source = if destination.contains("://") {
+        Expr::normal(Symbol::new("System`URL"), vec![Expr::string(destination)])
+    } else {
+        match base_directory {
+            Some(base) if Path::new(destination).is_relative() => {
+                Expr::string(base.join(destination).to_string_lossy())
+            },
+            _ => Expr::string(destination),
+        }
+    };
+
+    // BoxData[ToBoxes[Tooltip[Import[source], "alt"]]]
+    let mut image = Expr::normal(Symbol::new("System`Import"), vec![source]);
+
+    if !alt.is_empty() {
+        image = Expr::normal(
+            Symbol::new("System`Tooltip"),
+            vec![image, Expr::string(alt)],
+        );
+    }
+
+    Expr::normal(
+        Symbol::new("System`BoxData"),
+        vec![Expr::normal(Symbol::new("System`ToBoxes"), vec![image])],
+    )
+}
+
+/// Returns an `RGBColor[r, g, b]` expression.
+fn color_expr(Color { red, green, blue }: Color) -> Expr {
+    let channel = |value: u8| Expr::real(f64::from(value) / 255.0);
+
+    Expr::normal(
+        Symbol::new("System`RGBColor"),
+        vec![channel(red), channel(green), channel(blue)],
+    )
+}
+
+//======================================
+// Tests
+//======================================
+
+#[test]
+fn test_options_builder() {
+    let defaults = Options::default();
+    assert!(defaults.create_external_language_cells);
+    assert!(!defaults.preserve_tabs);
+
+    // Unset fields keep their default values.
+    let opts = Options::builder()
+        .create_external_language_cells(false)
+        .build();
+    assert!(!opts.create_external_language_cells);
+    assert!(!opts.preserve_tabs);
+
+    let opts = Options::builder().preserve_tabs(true).build();
+    assert!(opts.create_external_language_cells);
+    assert!(opts.preserve_tabs);
+}
+
+#[test]
+fn test_page_width() {
+    use pretty_assertions::assert_eq;
+
+    assert_eq!("500".parse::<PageWidth>(), Ok(PageWidth::Points(500)));
+    assert_eq!("infinity".parse::<PageWidth>(), Ok(PageWidth::Infinity));
+    assert_eq!("paper".parse::<PageWidth>(), Ok(PageWidth::Paper));
+    assert!("wide".parse::<PageWidth>().is_err());
+
+    assert_eq!(notebook_options(&Options::default()), Vec::<Expr>::new());
+
+    assert_eq!(
+        notebook_options(
+            &Options::builder()
+                .page_width(Some(PageWidth::Points(500)))
+                .build()
+        ),
+        vec![Expr::rule(Symbol::new("System`PageWidth"), Expr::from(500))]
+    );
+
+    assert_eq!(
+        notebook_options(
+            &Options::builder()
+                .page_width(Some(PageWidth::Infinity))
+                .build()
+        ),
+        vec![Expr::rule(
+            Symbol::new("System`PageWidth"),
+            Expr::from(Symbol::new("System`Infinity"))
+        )]
+    );
+}
+
+#[test]
+fn test_wrap_in_group() {
+    use pretty_assertions::assert_eq;
+
+    use crate::ast::parse_markdown_to_ast;
+
+    let opts = Options::default();
+
+    let to_cells = |input: &str| -> Vec<Expr> {
+        parse_markdown_to_ast(input)
+            .into_iter()
+            .flat_map(|block| block_to_cells(block, &opts))
+            .collect()
+    };
+
+    let group = |cells: Vec<Expr>| {
+        Expr::normal(
+            Symbol::new("System`Cell"),
+            vec![Expr::normal(
+                Symbol::new("System`CellGroupData"),
+                vec![Expr::list(cells), Expr::from(Symbol::new("System`Open"))],
+            )],
+        )
+    };
+
+    // The leading H1 is used as the group title.
+    assert_eq!(
+        wrap_in_group(to_cells("# Guide\n\nfirst\n\nsecond"), "file"),
+        group(to_cells("# Guide\n\nfirst\n\nsecond"))
+    );
+
+    // Otherwise the default title is used.
+    let mut expected = vec![Expr::normal(
+        Symbol::new("System`Cell"),
+        vec![Expr::string("file"), Expr::string("Title")],
+    )];
+    expected.extend(to_cells("first\n\n## Second"));
+
+    assert_eq!(
+        wrap_in_group(to_cells("first\n\n## Second"), "file"),
+        group(expected)
+    );
+}
+
+#[test]
+fn test_hero_cells() {
+    use pretty_assertions::assert_eq;
+
+    use crate::ast::parse_markdown_to_ast;
+
+    let opts = Options::default();
+
+    let mut blocks = parse_markdown_to_ast("# Guide\n\nAn overview.\n\nMore.");
+
+    let margins = |bottom: Expr, top: Expr| {
+        Expr::rule(
+            Symbol::new("System`CellMargins"),
+            Expr::list(vec![
+                Expr::list(vec![
+                    Expr::from(Symbol::new("System`Inherited")),
+                    Expr::from(Symbol::new("System`Inherited")),
+                ]),
+                Expr::list(vec![bottom, top]),
+            ]),
+        )
+    };
+    let text_data = |text: &str| {
+        Expr::normal(
+            Symbol::new("System`TextData"),
+            vec![Expr::normal(
+                Symbol::new("System`RowBox"),
+                vec![Expr::list(vec![Expr::string(text)])],
+            )],
+        )
+    };
+
+    assert_eq!(
+        hero_cells(&mut blocks, &opts),
+        vec![
+            Expr::normal(
+                Symbol::new("System`Cell"),
+                vec![
+                    text_data("Guide"),
+                    Expr::from("Title"),
+                    margins(Expr::from(0), Expr::from(Symbol::new("System`Inherited"))),
+                ]
+            ),
+            Expr::normal(
+                Symbol::new("System`Cell"),
+                vec![
+                    text_data("An overview."),
+                    Expr::from("Subtitle"),
+                    margins(Expr::from(Symbol::new("System`Inherited")), Expr::from(0)),
+                ]
+            ),
+        ]
+    );
+
+    // The remaining blocks are left to be converted normally.
+    assert_eq!(blocks, parse_markdown_to_ast("More."));
+
+    // Not a hero pattern.
+    for input in [
+        "# Guide\n\n## Intro",
+        "## Guide\n\nAn overview.",
+        "An overview.",
+    ] {
+        let mut blocks = parse_markdown_to_ast(input);
+        assert_eq!(
+            hero_cells(&mut blocks, &opts),
+            Vec::<Expr>::new(),
+            "input: {input:?}"
+        );
+        assert_eq!(blocks, parse_markdown_to_ast(input));
+    }
+}
+
+#[test]
+fn test_split_by_heading() {
+    use pretty_assertions::assert_eq;
+
+    use crate::ast::parse_markdown_to_ast;
+
+    let blocks = parse_markdown_to_ast(
+        "# Getting Started\n\none\n\n## Sub\n\n# `API` Reference\n\ntwo",
+    );
+
+    let sections = split_by_heading(blocks, HeadingLevel::H1);
+
+    assert_eq!(
+        sections,
+        vec![
+            (
+                "getting-started".to_owned(),
+                parse_markdown_to_ast("# Getting Started\n\none\n\n## Sub")
+            ),
+            (
+                "api-reference".to_owned(),
+                parse_markdown_to_ast("# `API` Reference\n\ntwo")
+            ),
+        ]
+    );
+
+    let sections =
+        split_by_heading(parse_markdown_to_ast("before\n\n## A"), HeadingLevel::H2);
+    assert_eq!(
+        sections,
+        vec![
+            ("intro".to_owned(), parse_markdown_to_ast("before")),
+            ("a".to_owned(), parse_markdown_to_ast("## A")),
+        ]
+    );
+
+    assert_eq!(parse_heading_level("H2"), Ok(HeadingLevel::H2));
+    assert_eq!(parse_heading_level("3"), Ok(HeadingLevel::H3));
+    assert!(parse_heading_level("H7").is_err());
+}
+
+#[test]
+fn test_tilde_fenced_code_blocks() {
+    use pretty_assertions::assert_eq;
+
+    use crate::ast::parse_markdown_to_ast;
+
+    let opts = Options::default();
+
+    let to_cells = |input: &str| -> Vec<Expr> {
+        parse_markdown_to_ast(input)
+            .into_iter()
+            .flat_map(|block| block_to_cells(block, &opts))
+            .collect()
+    };
+
+    let python_cell = vec![Expr::normal(
+        Symbol::new("System`Cell"),
+        vec![
+            Expr::string("print(1)\n"),
+            Expr::string("ExternalLanguage"),
+            Expr::rule(
+                Symbol::new("System`CellEvaluationLanguage"),
+                Expr::string("Python"),
+            ),
+        ],
+    )];
+
+    assert_eq!(to_cells("~~~python\nprint(1)\n~~~"), python_cell);
+    assert_eq!(to_cells("```python\nprint(1)\n```"), python_cell);
+
+    // Whitespace around the info string is not part of the language name.
+    assert_eq!(to_cells("~~~  python  \nprint(1)\n~~~"), python_cell);
+}
+
+#[test]
+fn test_code_line_numbers() {
+    use pretty_assertions::assert_eq;
+
+    use crate::ast::parse_markdown_to_ast;
+
+    let opts = Options::builder().code_line_numbers(true).build();
+
+    let cells: Vec<Expr> = parse_markdown_to_ast("# Example\n\n```\na\nb\n```\n")
+        .into_iter()
+        .flat_map(|block| block_to_cells(block, &opts))
+        .collect();
+
+    assert_eq!(
+        cells[1],
+        Expr::normal(
+            Symbol::new("System`Cell"),
+            vec![
+                Expr::string("a\nb\n"),
+                Expr::string("Program"),
+                Expr::rule(Symbol::new("System`CellLabel"), Expr::string("lines 3-6")),
+                Expr::rule(
+                    Symbol::new("System`ShowCellLabel"),
+                    Expr::from(Symbol::new("System`True"))
+                ),
+            ]
+        )
+    );
+}
+
+#[test]
+fn test_evaluator() {
+    use pretty_assertions::assert_eq;
+
+    use crate::ast::parse_markdown_to_ast;
+
+    let opts = Options::builder()
+        .evaluator(Some("PythonSession".to_owned()))
+        .build();
+
+    let cells: Vec<Expr> = parse_markdown_to_ast("```python\nprint(1)\n```")
+        .into_iter()
+        .flat_map(|block| block_to_cells(block, &opts))
+        .collect();
+
+    assert_eq!(
+        cells,
+        vec![Expr::normal(
+            Symbol::new("System`Cell"),
+            vec![
+                Expr::string("print(1)\n"),
+                Expr::string("ExternalLanguage"),
+                Expr::rule(
+                    Symbol::new("System`CellEvaluationLanguage"),
+                    Expr::string("Python"),
+                ),
+                Expr::rule(
+                    Symbol::new("System`Evaluator"),
+                    Expr::string("PythonSession"),
+                ),
+            ],
+        )]
+    );
+
+    // Program cells are not evaluated, and don't get an evaluator.
+    let cells: Vec<Expr> = parse_markdown_to_ast("```\ntext\n```")
+        .into_iter()
+        .flat_map(|block| block_to_cells(block, &opts))
+        .collect();
+
+    assert_eq!(
+        cells,
+        vec![Expr::normal(
+            Symbol::new("System`Cell"),
+            vec![Expr::string("text\n"), Expr::string("Program")],
+        )]
+    );
+}
+
+#[test]
+fn test_table_header_inline_styles() {
+    use pretty_assertions::assert_eq;
+
+    use crate::ast::parse_markdown_to_ast;
+
+    let opts = Options::default();
+
+    let cells: Vec<Expr> = parse_markdown_to_ast("| **Name** `id` |\n|---|\n| x |")
+        .into_iter()
+        .flat_map(|block| block_to_cells(block, &opts))
+        .collect();
+
+    // Cell[BoxData[GridBox[{{header}, {row}}, ...]], "Text"]
+    let grid_box = cells[0].normal_part(0).unwrap().normal_part(0).unwrap();
+    let header_row = grid_box.normal_part(0).unwrap().normal_part(0).unwrap();
+
+    assert_eq!(
+        header_row,
+        &Expr::list(vec![Expr::normal(
+            Symbol::new("System`Cell"),
+            vec![
+                Expr::normal(
+                    Symbol::new("System`TextData"),
+                    vec![Expr::normal(
+                        Symbol::new("System`RowBox"),
+                        vec![Expr::list(vec![
+                            Expr::normal(
+                                Symbol::new("System`StyleBox"),
+                                vec![
+                                    Expr::string("Name"),
+                                    Expr::rule(
+                                        Symbol::new("System`FontWeight"),
+                                        Expr::string("Bold")
+                                    ),
+                                ]
+                            ),
+                            Expr::string(" "),
+                            Expr::normal(
+                                Symbol::new("System`StyleBox"),
+                                vec![Expr::string("id"), Expr::string("Code")]
+                            ),
+                        ])]
+                    )]
+                ),
+                Expr::from("Subsubsubsection"),
+            ]
+        )])
+    );
+}
+
+#[test]
+fn test_preserve_tabs() {
+    use pretty_assertions::assert_eq;
+
+    use crate::ast::parse_markdown_to_ast;
+
+    let opts = Options::builder().preserve_tabs(true).build();
+
+    let cells: Vec<Expr> = parse_markdown_to_ast("Name:\tValue")
+        .into_iter()
+        .flat_map(|block| block_to_cells(block, &opts))
+        .collect();
+
+    assert_eq!(
+        cells,
+        vec![Expr::normal(
+            Symbol::new("System`Cell"),
+            vec![
+                Expr::normal(
+                    Symbol::new("System`TextData"),
+                    vec![Expr::normal(
+                        Symbol::new("System`RowBox"),
+                        vec![Expr::list(vec![
+                            Expr::string("Name:"),
+                            Expr::string("\t"),
+                            Expr::string("Value"),
+                        ])],
+                    )],
+                ),
+                Expr::from("Text"),
+            ],
+        )]
+    );
+}
+
+#[test]
+fn test_backslash_hard_break() {
+    use pretty_assertions::assert_eq;
+
+    use crate::ast::parse_markdown_to_ast;
+
+    let opts = Options::default();
+
+    let row = |items: Vec<Expr>| {
+        vec![Expr::normal(
+            Symbol::new("System`Cell"),
+            vec![
+                Expr::normal(
+                    Symbol::new("System`TextData"),
+                    vec![Expr::normal(
+                        Symbol::new("System`RowBox"),
+                        vec![Expr::list(items)],
+                    )],
+                ),
+                Expr::from("Text"),
+            ],
+        )]
+    };
+
+    // A backslash at the end of a line is a hard break, not a literal backslash,
+    // including when it follows a space or ends a CRLF line.
+    for (input, first) in [
+        ("line1\\\nline2", Expr::string("line1")),
+        ("line1 \\\nline2", Expr::string("line1 ")),
+        ("line1\\\r\nline2", Expr::string("line1")),
+    ] {
+        let cells: Vec<Expr> = parse_markdown_to_ast(input)
+            .into_iter()
+            .flat_map(|block| block_to_cells(block, &opts))
+            .collect();
+
+        assert_eq!(
+            cells,
+            row(vec![first, Expr::string("\n"), Expr::string("line2")]),
+            "input: {input:?}"
+        );
+    }
+
+    // A backslash at the end of a paragraph has no line to break, so it is kept.
+    let cells: Vec<Expr> = parse_markdown_to_ast("line1\\")
+        .into_iter()
+        .flat_map(|block| block_to_cells(block, &opts))
+        .collect();
+
+    assert_eq!(cells, row(vec![Expr::string("line1\\")]));
+}
+
+#[test]
+fn test_strikethrough() {
+    use pretty_assertions::assert_eq;
+
+    use crate::ast::parse_markdown_to_ast;
+
+    let cells: Vec<Expr> =
+        parse_markdown_to_ast("~~**bold struck**~~ ~~*italic struck*~~")
+            .into_iter()
+            .flat_map(|block| block_to_cells(block, &Options::default()))
+            .collect();
+
+    let strike_through = Expr::rule(
+        Symbol::new("System`FontVariations"),
+        Expr::list(vec![Expr::rule(
+            Expr::string("StrikeThrough"),
+            Expr::from(Symbol::new("System`True")),
+        )]),
+    );
+
+    assert_eq!(
+        cells,
+        vec![Expr::normal(
+            Symbol::new("System`Cell"),
+            vec![
+                Expr::normal(
+                    Symbol::new("System`TextData"),
+                    vec![Expr::normal(
+                        Symbol::new("System`RowBox"),
+                        vec![Expr::list(vec![
+                            Expr::normal(
+                                Symbol::new("System`StyleBox"),
+                                vec![
+                                    Expr::string("bold struck"),
+                                    strike_through.clone(),
+                                    Expr::rule(
+                                        Symbol::new("System`FontWeight"),
+                                        Expr::string("Bold")
+                                    ),
+                                ]
+                            ),
+                            Expr::string(" "),
+                            Expr::normal(
+                                Symbol::new("System`StyleBox"),
+                                vec![
+                                    Expr::string("italic struck"),
+                                    Expr::rule(
+                                        Symbol::new("System`FontSlant"),
+                                        Expr::string("Italic")
+                                    ),
+                                    strike_through,
+                                ]
+                            ),
+                        ])],
+                    )],
+                ),
+                Expr::from("Text"),
+            ],
+        )]
+    );
+}
+
+#[test]
+fn test_inline_html_style_tags() {
+    use pretty_assertions::assert_eq;
+
+    use crate::ast::parse_markdown_to_ast;
+
+    let cells: Vec<Expr> = parse_markdown_to_ast("x<sub>2</sub>")
+        .into_iter()
+        .flat_map(|block| block_to_cells(block, &Options::default()))
+        .collect();
+
+    assert_eq!(
+        cells,
+        vec![Expr::normal(
+            Symbol::new("System`Cell"),
+            vec![
+                Expr::normal(
+                    Symbol::new("System`TextData"),
+                    vec![Expr::normal(
+                        Symbol::new("System`RowBox"),
+                        vec![Expr::list(vec![
+                            Expr::string("x"),
+                            Expr::normal(
+                                Symbol::new("System`StyleBox"),
+                                vec![
+                                    Expr::string("2"),
+                                    Expr::rule(
+                                        Symbol::new("System`FontVariations"),
+                                        Expr::list(vec![Expr::rule(
+                                            Expr::string("CompatibilityType"),
+                                            Expr::string("Subscript"),
+                                        )]),
+                                    ),
+                                ]
+                            ),
+                        ])],
+                    )],
+                ),
+                Expr::from("Text"),
+            ],
+        )]
+    );
+}
+
+#[test]
+fn test_task_list_checkboxes() {
+    use pretty_assertions::assert_eq;
+
+    use crate::ast::parse_markdown_to_ast;
+
+    let cells: Vec<Expr> = parse_markdown_to_ast("- [x] done\n- [ ] todo")
+        .into_iter()
+        .flat_map(|block| block_to_cells(block, &Options::default()))
+        .collect();
+
+    let item = |checked: &str, text: &str| {
+        Expr::normal(
+            Symbol::new("System`Cell"),
+            vec![
+                Expr::normal(
+                    Symbol::new("System`TextData"),
+                    vec![Expr::normal(
+                        Symbol::new("System`RowBox"),
+                        vec![Expr::list(vec![
+                            Expr::normal(
+                                Symbol::new("System`CheckboxBox"),
+                                vec![Expr::from(Symbol::new(checked))],
+                            ),
+                            Expr::string(" "),
+                            Expr::string(text),
+                        ])],
+                    )],
+                ),
+                Expr::from("Item"),
+                list_cell_margins(None, Some(TIGHT_LIST_ITEM_MARGIN)),
+            ],
+        )
+    };
+
+    assert_eq!(
+        cells,
+        vec![item("System`True", "done"), item("System`False", "todo")]
+    );
+}
+
+#[test]
+fn test_footnotes() {
+    use pretty_assertions::assert_eq;
+
+    use crate::ast::parse_markdown_to_ast;
+
+    let cells: Vec<Expr> = parse_markdown_to_ast("A[^n] B[^n]\n\n[^n]: Note.")
+        .into_iter()
+        .flat_map(|block| block_to_cells(block, &Options::default()))
+        .collect();
+
+    let reference = Expr::normal(
+        Symbol::new("System`ButtonBox"),
+        vec![
+            Expr::normal(
+                Symbol::new("System`RowBox"),
+                vec![Expr::list(vec![Expr::normal(
+                    Symbol::new("System`StyleBox"),
+                    vec![
+                        Expr::string("n"),
+                        Expr::rule(
+                            Symbol::new("System`FontVariations"),
+                            Expr::list(vec![Expr::rule(
+                                Expr::string("CompatibilityType"),
+                                Expr::string("Superscript"),
+                            )]),
+                        ),
+                    ],
+                )])],
+            ),
+            Expr::rule(Symbol::new("System`BaseStyle"), Expr::string("Link")),
+            Expr::rule(Symbol::new("System`ButtonData"), Expr::string("footnote-n")),
+        ],
+    );
+
+    let text_cell = |items: Vec<Expr>| {
+        Expr::normal(
+            Symbol::new("System`Cell"),
+            vec![
+                Expr::normal(
+                    Symbol::new("System`TextData"),
+                    vec![Expr::normal(
+                        Symbol::new("System`RowBox"),
+                        vec![Expr::list(items)],
+                    )],
+                ),
+                Expr::from("Text"),
+            ],
+        )
+    };
+
+    // Both references link to the same footnote cell.
+    assert_eq!(
+        cells,
+        vec![
+            text_cell(vec![
+                Expr::string("A"),
+                reference.clone(),
+                Expr::string(" B"),
+                reference,
+            ]),
+            with_cell_tags(
+                text_cell(vec![Expr::string("n. "), Expr::string("Note.")]),
+                vec!["footnote-n".to_owned()]
+            ),
+        ]
+    );
+
+    // A definition inside a list item is converted at the end of the notebook.
+    let cells: Vec<Expr> = parse_markdown_to_ast("- Item[^n]\n\n  [^n]: Note.")
+        .into_iter()
+        .flat_map(|block| block_to_cells(block, &Options::default()))
+        .collect();
+
+    assert_eq!(cells.len(), 2);
+    assert_eq!(
+        cells[1],
+        with_cell_tags(
+            text_cell(vec![Expr::string("n. "), Expr::string("Note.")]),
+            vec!["footnote-n".to_owned()]
+        )
+    );
+}
+
+#[test]
+fn test_wikilinks() {
+    use pretty_assertions::assert_eq;
+
+    use crate::ast::{self, parse_markdown_to_ast_with_options};
+
+    let cells: Vec<Expr> = parse_markdown_to_ast_with_options(
+        "# Foo Bar\n\nSee [[Foo Bar]].",
+        &ast::Options {
+            wikilinks: true,
+            ..ast::Options::default()
+        },
+    )
+    .into_iter()
+    .flat_map(|block| block_to_cells(block, &Options::default()))
+    .collect();
+
+    let row_box = |items: Vec<Expr>| {
+        Expr::normal(Symbol::new("System`RowBox"), vec![Expr::list(items)])
+    };
+
+    // The wikilink targets the cell tag of the heading.
+    assert_eq!(
+        cells,
+        vec![
+            with_cell_tags(
+                Expr::normal(
+                    Symbol::new("System`Cell"),
+                    vec![
+                        Expr::normal(
+                            Symbol::new("System`TextData"),
+                            vec![row_box(vec![Expr::string("Foo Bar")])],
+                        ),
+                        Expr::from("Title"),
+                    ],
+                ),
+                vec!["foo-bar".to_owned()]
+            ),
+            Expr::normal(
+                Symbol::new("System`Cell"),
+                vec![
+                    Expr::normal(
+                        Symbol::new("System`TextData"),
+                        vec![row_box(vec![
+                            Expr::string("See "),
+                            Expr::normal(
+                                Symbol::new("System`ButtonBox"),
+                                vec![
+                                    row_box(vec![Expr::string("Foo Bar")]),
+                                    Expr::rule(
+                                        Symbol::new("System`BaseStyle"),
+                                        Expr::string("Link"),
+                                    ),
+                                    Expr::rule(
+                                        Symbol::new("System`ButtonData"),
+                                        Expr::string("foo-bar"),
+                                    ),
+                                ],
+                            ),
+                            Expr::string("."),
+                        ])],
+                    ),
+                    Expr::from("Text"),
+                ],
+            ),
+        ]
+    );
+}
+
+#[test]
+fn test_images() {
+    use pretty_assertions::assert_eq;
+
+    use crate::ast::parse_markdown_to_ast;
+
+    let cells: Vec<Expr> = parse_markdown_to_ast(
+        "![Logo](https://example.com/logo.png)\n\nA ![](icon.png) icon.",
+    )
+    .into_iter()
+    .flat_map(|block| block_to_cells(block, &Options::default()))
+    .collect();
+
+    let image_box_data = |image: Expr| {
+        Expr::normal(
+            Symbol::new("System`BoxData"),
+            vec![Expr::normal(Symbol::new("System`ToBoxes"), vec![image])],
+        )
+    };
+
+    assert_eq!(
+        cells,
+        vec![
+            Expr::normal(
+                Symbol::new("System`Cell"),
+                vec![
+                    image_box_data(Expr::normal(
+                        Symbol::new("System`Tooltip"),
+                        vec![
+                            Expr::normal(
+                                Symbol::new("System`Import"),
+                                vec![Expr::normal(
+                                    Symbol::new("System`URL"),
+                                    vec![Expr::string("https://example.com/logo.png")],
+                                )],
+                            ),
+                            Expr::string("Logo"),
+                        ],
+                    )),
+                    Expr::from("Text"),
+                ],
+            ),
+            Expr::normal(
+                Symbol::new("System`Cell"),
+                vec![
+                    Expr::normal(
+                        Symbol::new("System`TextData"),
+                        vec![Expr::normal(
+                            Symbol::new("System`RowBox"),
+                            vec![Expr::list(vec![
+                                Expr::string("A "),
+                                Expr::normal(
+                                    Symbol::new("System`Cell"),
+                                    vec![image_box_data(Expr::normal(
+                                        Symbol::new("System`Import"),
+                                        vec![Expr::string("icon.png")],
+                                    ))],
+                                ),
+                                Expr::string(" icon."),
+                            ])],
+                        )],
+                    ),
+                    Expr::from("Text"),
+                ],
+            ),
+        ]
+    );
+
+    // Relative paths are resolved against the directory of the Markdown file.
+    let opts = Options::builder()
+        .base_directory(Some(PathBuf::from("/docs/guide")))
+        .build();
+
+    let cells: Vec<Expr> = parse_markdown_to_ast(
+        "![](images/icon.png)\n\n![](/abs/icon.png)\n\n![](https://example.com/a.png)",
+    )
+    .into_iter()
+    .flat_map(|block| block_to_cells(block, &opts))
+    .collect();
+
+    let import = |source: Expr| {
+        Expr::normal(
+            Symbol::new("System`Cell"),
+            vec![
+                image_box_data(Expr::normal(Symbol::new("System`Import"), vec![source])),
+                Expr::from("Text"),
+            ],
+        )
+    };
+
+    assert_eq!(
+        cells,
+        vec![
+            import(Expr::string(
+                Path::new("/docs/guide")
+                    .join("images/icon.png")
+                    .to_string_lossy()
+            )),
+            import(Expr::string("/abs/icon.png")),
+            import(Expr::normal(
+                Symbol::new("System`URL"),
+                vec![Expr::string("https://example.com/a.png")],
+            )),
+        ]
+    );
+}
+
+#[test]
+fn test_math() {
+    use pretty_assertions::assert_eq;
+
+    use crate::ast::{self, parse_markdown_to_ast_with_options};
+
+    let cells: Vec<Expr> = parse_markdown_to_ast_with_options(
+        "$$\\sqrt{2}$$\n\nSo $x^2$.",
+        &ast::Options {
+            math: true,
+            ..ast::Options::default()
+        },
+    )
+    .into_iter()
+    .flat_map(|block| block_to_cells(block, &Options::default()))
+    .collect();
+
+    let math_box_data = |tex: &str| {
+        Expr::normal(
+            Symbol::new("System`BoxData"),
+            vec![Expr::normal(
+                Symbol::new("System`ToBoxes"),
+                vec![
+                    Expr::normal(
+                        Symbol::new("System`ToExpression"),
+                        vec![
+                            Expr::string(tex),
+                            Expr::from(Symbol::new("System`TeXForm")),
+                            Expr::from(Symbol::new("System`HoldForm")),
+                        ],
+                    ),
+                    Expr::from(Symbol::new("System`TraditionalForm")),
+                ],
+            )],
+        )
+    };
+
+    assert_eq!(
+        cells,
+        vec![
+            Expr::normal(
+                Symbol::new("System`Cell"),
+                vec![math_box_data("\\sqrt{2}"), Expr::from("Input")],
+            ),
+            Expr::normal(
+                Symbol::new("System`Cell"),
+                vec![
+                    Expr::normal(
+                        Symbol::new("System`TextData"),
+                        vec![Expr::normal(
+                            Symbol::new("System`RowBox"),
+                            vec![Expr::list(vec![
+                                Expr::string("So "),
+                                Expr::normal(
+                                    Symbol::new("System`Cell"),
+                                    vec![math_box_data("x^2")],
+                                ),
+                                Expr::string("."),
+                            ])],
+                        )],
+                    ),
+                    Expr::from("Text"),
+                ],
+            ),
+        ]
+    );
+}
+
+#[test]
+fn test_inline_html_span_color() {
+    use pretty_assertions::assert_eq;
+
+    use crate::ast::{self, parse_markdown_to_ast_with_options};
+
+    let opts = Options::default();
+
+    let cells: Vec<Expr> = parse_markdown_to_ast_with_options(
+        r#"<span style="color:red">red</span>"#,
+        &ast::Options {
+            html_attributes: true,
+            ..ast::Options::default()
+        },
+    )
+    .into_iter()
+    .flat_map(|block| block_to_cells(block, &opts))
+    .collect();
+
+    assert_eq!(
+        cells,
+        vec![Expr::normal(
+            Symbol::new("System`Cell"),
+            vec![
+                Expr::normal(
+                    Symbol::new("System`TextData"),
+                    vec![Expr::normal(
+                        Symbol::new("System`RowBox"),
+                        vec![Expr::list(vec![Expr::normal(
+                            Symbol::new("System`StyleBox"),
+                            vec![
+                                Expr::string("red"),
+                                Expr::rule(
+                                    Symbol::new("System`FontColor"),
+                                    Expr::normal(
+                                        Symbol::new("System`RGBColor"),
+                                        vec![
+                                            Expr::real(1.0),
+                                            Expr::real(0.0),
+                                            Expr::real(0.0)
+                                        ],
+                                    ),
+                                ),
+                            ],
+                        )])],
+                    )],
+                ),
+                Expr::from("Text"),
+            ],
+        )]
+    );
+}
+
+#[test]
+fn test_inline_html_mark() {
+    use pretty_assertions::assert_eq;
+
+    use crate::ast::parse_markdown_to_ast;
+
+    let opts = Options::default();
+
+    let cells: Vec<Expr> = parse_markdown_to_ast("<mark>x</mark>")
+        .into_iter()
+        .flat_map(|block| block_to_cells(block, &opts))
+        .collect();
+
+    assert_eq!(
+        cells,
+        vec![Expr::normal(
+            Symbol::new("System`Cell"),
+            vec![
+                Expr::normal(
+                    Symbol::new("System`TextData"),
+                    vec![Expr::normal(
+                        Symbol::new("System`RowBox"),
+                        vec![Expr::list(vec![Expr::normal(
+                            Symbol::new("System`StyleBox"),
+                            vec![
+                                Expr::string("x"),
+                                Expr::rule(
+                                    Symbol::new("System`Background"),
+                                    Expr::normal(
+                                        Symbol::new("System`RGBColor"),
+                                        vec![
+                                            Expr::real(1.0),
+                                            Expr::real(1.0),
+                                            Expr::real(0.0)
+                                        ],
+                                    ),
+                                ),
+                            ],
+                        )])],
+                    )],
+                ),
+                Expr::from("Text"),
+            ],
+        )]
+    );
+}
+
+#[test]
+fn test_theme_file_block_quote_colors() {
+    use pretty_assertions::assert_eq;
+
+    use crate::ast::parse_markdown_to_ast;
+
+    let path = std::env::temp_dir()
+        .join(format!("md2nb-theme-test-{}.toml", std::process::id()));
+    std::fs::write(&path, "[block_quote]\nbackground = \"#ff0000\"\n").unwrap();
+
+    let theme = Theme::load(&path).unwrap();
+    std::fs::remove_file(&path).unwrap();
+
+    let opts = Options::builder().theme(theme).build();
+
+    let cells: Vec<Expr> = parse_markdown_to_ast("> quoted")
+        .into_iter()
+        .flat_map(|block| block_to_cells(block, &opts))
+        .collect();
+
+    let cell = cells[0].try_normal().unwrap();
+
+    assert_eq!(
+        cell.elements().last(),
+        Some(&Expr::rule(
+            Symbol::new("System`Background"),
+            Expr::normal(
+                Symbol::new("System`RGBColor"),
+                vec![Expr::real(1.0), Expr::real(0.0), Expr::real(0.0)]
+            )
+        ))
+    );
+
+    // The frame color keeps its default.
+    assert!(cell.elements().contains(&Expr::rule(
+        Symbol::new("System`CellFrameColor"),
+        Expr::normal(Symbol::new("System`GrayLevel"), vec![Expr::real(0.8)])
+    )));
+}
+
+#[test]
+fn test_theme_code_block_background() {
+    use pretty_assertions::assert_eq;
+
+    use crate::ast::parse_markdown_to_ast;
+
+    let theme = Theme {
+        code_block_background: Some(Color {
+            red: 0,
+            green: 0,
+            blue: 255,
+        }),
+        ..Theme::default()
+    };
+    let opts = Options::builder().theme(theme).build();
+
+    let cells: Vec<Expr> = parse_markdown_to_ast("```\nx\n```\n\n```python\ny\n```")
+        .into_iter()
+        .flat_map(|block| block_to_cells(block, &opts))
+        .collect();
+
+    let background = Expr::rule(
+        Symbol::new("System`Background"),
+        Expr::normal(
+            Symbol::new("System`RGBColor"),
+            vec![Expr::real(0.0), Expr::real(0.0), Expr::real(1.0)],
+        ),
+    );
+
+    assert_eq!(
+        cells[0],
+        Expr::normal(
+            Symbol::new("System`Cell"),
+            vec![
+                Expr::string("x\n"),
+                Expr::string("Program"),
+                background.clone()
+            ],
+        )
+    );
+    assert_eq!(
+        cells[1].try_normal().unwrap().elements().last(),
+        Some(&background)
+    );
+
+    // Without a theme color, code blocks use the background of their cell style.
+    let cells = block_to_cells(
+        parse_markdown_to_ast("```\nx\n```").remove(0),
+        &Options::default(),
+    );
+    assert_eq!(
+        cells,
+        vec![Expr::normal(
+            Symbol::new("System`Cell"),
+            vec![Expr::string("x\n"), Expr::string("Program")],
+        )]
+    );
+}
+
+#[test]
+fn test_block_quote_with_code_block() {
+    use pretty_assertions::assert_eq;
+
+    use crate::ast::parse_markdown_to_ast;
+
+    let opts = Options::default();
+
+    let cells: Vec<Expr> = parse_markdown_to_ast("> quoted\n>\n> ```\n> code\n> ```")
+        .into_iter()
+        .flat_map(|block| block_to_cells(block, &opts))
+        .collect();
+
+    let mut code_cell = vec![Expr::string("code\n"), Expr::string("Program")];
+    code_cell.extend(block_quote_options(&opts.theme));
+
+    assert_eq!(
+        cells,
+        vec![Expr::normal(
+            Symbol::new("System`Cell"),
+            vec![Expr::normal(
+                Symbol::new("System`CellGroupData"),
+                vec![
+                    Expr::list(vec![
+                        block_quote_cell(&opts, parse_markdown_to_ast("quoted")).unwrap(),
+                        Expr::normal(Symbol::new("System`Cell"), code_cell),
+                    ]),
+                    Expr::from(Symbol::new("System`Open")),
+                ],
+            )],
+        )]
+    );
+}
+
+#[test]
+fn test_deeply_nested_list() {
+    use pretty_assertions::assert_eq;
+
+    use crate::ast::parse_markdown_to_ast;
+
+    let cells: Vec<Expr> =
+        parse_markdown_to_ast("- 1\n  - 2\n    - 3\n      - 4\n        - 5")
+            .into_iter()
+            .flat_map(|block| block_to_cells(block, &Options::default()))
+            .collect();
+
+    assert_eq!(
+        cells.iter().map(cell_style).collect::<Vec<_>>(),
+        vec![
+            Some("Item"),
+            Some("Subitem"),
+            Some("Subsubitem"),
+            Some("Subsubitem"),
+            Some("Subsubitem"),
+        ]
+    );
+
+    let left_margin = |cell: &Expr| {
+        cell.try_normal()
+            .unwrap()
+            .elements()
+            .iter()
+            .find_map(|option| {
+                let (lhs, rhs) = match option.try_normal()?.elements() {
+                    [lhs, rhs] => (lhs, rhs),
+                    _ => return None,
+                };
+                if lhs.try_symbol()? != &Symbol::new("System`CellMargins") {
+                    return None;
+                }
+                let horizontal = &rhs.try_normal()?.elements()[0];
+                Some(horizontal.try_normal()?.elements()[0].clone())
+            })
+    };
+
+    assert_eq!(
+        cells.iter().map(left_margin).collect::<Vec<_>>(),
+        vec![
+            Some(Expr::from(Symbol::new("System`Inherited"))),
+            Some(Expr::from(Symbol::new("System`Inherited"))),
+            Some(Expr::from(Symbol::new("System`Inherited"))),
+            Some(Expr::from(153)),
+            Some(Expr::from(177))
+        ]
+    );
+}
+
+#[test]
+fn test_block_quote_in_list_item() {
+    use pretty_assertions::assert_eq;
+
+    use crate::ast::parse_markdown_to_ast;
+
+    let cells: Vec<Expr> =
+        parse_markdown_to_ast("- Item\n\n  > Quoted\n  - Nested\n\n    > Deeper")
+            .into_iter()
+            .flat_map(|block| block_to_cells(block, &Options::default()))
+            .collect();
+
+    assert_eq!(
+        cells.iter().map(cell_style).collect::<Vec<_>>(),
+        vec![Some("Item"), Some("Text"), Some("Subitem"), Some("Text")]
+    );
+
+    let cell_margins = |left: i64| {
+        let inherited = || Expr::from(Symbol::new("System`Inherited"));
+
+        Expr::rule(
+            Symbol::new("System`CellMargins"),
+            Expr::list(vec![
+                Expr::list(vec![Expr::from(left), inherited()]),
+                Expr::list(vec![inherited(), inherited()]),
+            ]),
+        )
+    };
+
+    let expected_quote = cell_with_options(
+        block_quote_cell(
+            &Options::default(),
+            vec![Block::Paragraph(Text(vec![TextSpan::Text(
+                "Quoted".into(),
+                Default::default(),
+            )]))],
+        )
+        .unwrap(),
+        vec![cell_margins(81)],
+    );
+
+    assert_eq!(cells[1], expected_quote);
+    assert_eq!(
+        cells[3].try_normal().unwrap().elements().last(),
+        Some(&cell_margins(105))
+    );
+}
+
+#[test]
+fn test_heading_in_list_item() {
+    use pretty_assertions::assert_eq;
+
+    use crate::ast::parse_markdown_to_ast;
+
+    let cells: Vec<Expr> = parse_markdown_to_ast("1. # Step one\n\n   ###### Details")
+        .into_iter()
+        .flat_map(|block| block_to_cells(block, &Options::default()))
+        .collect();
+
+    let heading = |text: &str, style: &str| {
+        Expr::normal(
+            Symbol::new("System`Cell"),
+            vec![
+                Expr::normal(
+                    Symbol::new("System`TextData"),
+                    vec![Expr::normal(
+                        Symbol::new("System`RowBox"),
+                        vec![Expr::list(vec![Expr::string(text)])],
+                    )],
+                ),
+                Expr::from(style),
+                Expr::rule(
+                    Symbol::new("System`CellMargins"),
+                    Expr::list(vec![
+                        Expr::list(vec![
+                            Expr::from(81),
+                            Expr::from(Symbol::new("System`Inherited")),
+                        ]),
+                        Expr::list(vec![
+                            Expr::from(Symbol::new("System`Inherited")),
+                            Expr::from(Symbol::new("System`Inherited")),
+                        ]),
+                    ]),
+                ),
+            ],
+        )
+    };
+
+    assert_eq!(
+        cells,
+        vec![
+            heading("Step one", "Subsubsection"),
+            heading("Details", "Subsubsubsection"),
+        ]
+    );
+}
+
+#[test]
+fn test_code_block_in_list_item() {
+    use pretty_assertions::assert_eq;
+
+    use crate::ast::parse_markdown_to_ast;
+
+    let markdown = "- Run:\n\n  ```python\n  print(1)\n  ```\n- Or:\n\n  ```\n  x\n  ```";
+
+    let cell_margins = Expr::rule(
+        Symbol::new("System`CellMargins"),
+        Expr::list(vec![
+            Expr::list(vec![
+                Expr::from(81),
+                Expr::from(Symbol::new("System`Inherited")),
+            ]),
+            Expr::list(vec![
+                Expr::from(Symbol::new("System`Inherited")),
+                Expr::from(Symbol::new("System`Inherited")),
+            ]),
+        ]),
+    );
+
+    let cells: Vec<Expr> = parse_markdown_to_ast(markdown)
+        .into_iter()
+        .flat_map(|block| block_to_cells(block, &Options::default()))
+        .collect();
+
+    assert_eq!(
+        cells.iter().map(cell_style).collect::<Vec<_>>(),
+        vec![
+            Some("Item"),
+            Some("ExternalLanguage"),
+            Some("Item"),
+            Some("Program")
+        ]
+    );
+    assert_eq!(
+        cells[1],
+        Expr::normal(
+            Symbol::new("System`Cell"),
+            vec![
+                Expr::string("print(1)\n"),
+                Expr::string("ExternalLanguage"),
+                Expr::rule(
+                    Symbol::new("System`CellEvaluationLanguage"),
+                    Expr::string("Python"),
+                ),
+                cell_margins.clone(),
+            ],
+        )
+    );
+    assert_eq!(
+        cells[3],
+        Expr::normal(
+            Symbol::new("System`Cell"),
+            vec![Expr::string("x\n"), Expr::string("Program"), cell_margins],
+        )
+    );
+
+    // The option to not create "ExternalLanguage" cells also applies in list items.
+    let opts = Options::builder()
+        .create_external_language_cells(false)
+        .build();
+    let cells: Vec<Expr> = parse_markdown_to_ast(markdown)
+        .into_iter()
+        .flat_map(|block| block_to_cells(block, &opts))
+        .collect();
+
+    assert_eq!(cell_style(&cells[1]), Some("Program"));
+}
+
+#[test]
+fn test_table_in_list_item() {
+    use pretty_assertions::assert_eq;
+
+    use crate::ast::parse_markdown_to_ast;
+
+    let cells: Vec<Expr> =
+        parse_markdown_to_ast("- Compare:\n\n  | A | B |\n  |---|---|\n  | 1 | 2 |")
+            .into_iter()
+            .flat_map(|block| block_to_cells(block, &Options::default()))
+            .collect();
+
+    assert_eq!(
+        cells.iter().map(cell_style).collect::<Vec<_>>(),
+        vec![Some("Item"), Some("Text")]
+    );
+
+    let table = cells[1].try_normal().unwrap().elements();
+
+    let box_data = table[0].try_normal().unwrap();
+    assert!(box_data.has_head(&Symbol::new("System`BoxData")));
+    assert!(box_data.elements()[0]
+        .try_normal()
+        .unwrap()
+        .has_head(&Symbol::new("System`GridBox")));
+
+    assert_eq!(
+        table.last(),
+        Some(&Expr::rule(
+            Symbol::new("System`CellMargins"),
+            Expr::list(vec![
+                Expr::list(vec![
+                    Expr::from(81),
+                    Expr::from(Symbol::new("System`Inherited")),
+                ]),
+                Expr::list(vec![
+                    Expr::from(Symbol::new("System`Inherited")),
+                    Expr::from(Symbol::new("System`Inherited")),
+                ]),
+            ]),
+        ))
+    );
+}
+
+#[test]
+fn test_display_math_in_list_item() {
+    use pretty_assertions::assert_eq;
+
+    use crate::ast::{self, parse_markdown_to_ast_with_options};
+
+    let cells: Vec<Expr> = parse_markdown_to_ast_with_options(
+        "- Square:\n\n  $$x^2$$",
+        &ast::Options {
+            math: true,
+            ..ast::Options::default()
+        },
+    )
+    .into_iter()
+    .flat_map(|block| block_to_cells(block, &Options::default()))
+    .collect();
+
+    assert_eq!(
+        cells.iter().map(cell_style).collect::<Vec<_>>(),
+        vec![Some("Item"), Some("Input")]
+    );
+
+    let math = cells[1].try_normal().unwrap().elements();
+
+    assert!(math[0]
+        .try_normal()
+        .unwrap()
+        .has_head(&Symbol::new("System`BoxData")));
+    assert_eq!(
+        math.last(),
+        Some(&Expr::rule(
+            Symbol::new("System`CellMargins"),
+            Expr::list(vec![
+                Expr::list(vec![
+                    Expr::from(81),
+                    Expr::from(Symbol::new("System`Inherited")),
+                ]),
+                Expr::list(vec![
+                    Expr::from(Symbol::new("System`Inherited")),
+                    Expr::from(Symbol::new("System`Inherited")),
+                ]),
+            ]),
+        ))
+    );
+}
+
+#[test]
+fn test_nb_cell_code_block() {
+    use pretty_assertions::assert_eq;
+
+    use crate::ast::parse_markdown_to_ast;
+
+    let cells: Vec<Expr> =
+        parse_markdown_to_ast("```nb-cell\nCell[\"x\", \"Text\"]\n```")
+            .into_iter()
+            .flat_map(|block| block_to_cells(block, &Options::default()))
+            .collect();
+
+    assert_eq!(
+        cells,
+        vec![Expr::normal(
+            Symbol::new("System`ToExpression"),
+            vec![Expr::string(r#"Cell["x", "Text"]"#)]
+        )]
+    );
+}
+
+#[test]
+fn test_code_gutter() {
+    use pretty_assertions::assert_eq;
+
+    use crate::ast::parse_markdown_to_ast;
+
+    let opts = Options::builder().code_gutter(true).build();
+
+    let to_cells = |input: &str| -> Vec<Expr> {
+        parse_markdown_to_ast(input)
+            .into_iter()
+            .flat_map(|block| block_to_cells(block, &opts))
+            .collect()
+    };
+
+    let row = |number: &str, line: &str| {
+        Expr::list(vec![
+            Expr::normal(
+                Symbol::new("System`StyleBox"),
+                vec![
+                    Expr::string(number),
+                    Expr::rule(
+                        Symbol::new("System`FontColor"),
+                        Expr::normal(
+                            Symbol::new("System`GrayLevel"),
+                            vec![Expr::real(0.5)],
+                        ),
+                    ),
+                ],
+            ),
+            Expr::string(line),
+        ])
+    };
+
+    let cells = to_cells("```\nfirst\nsecond\nthird\n```");
+
+    let grid_box = cells[0].try_normal().unwrap().elements()[0]
+        .try_normal()
+        .unwrap()
+        .elements()[0]
+        .clone();
+
+    assert_eq!(
+        grid_box.try_normal().unwrap().elements()[0],
+        Expr::list(vec![
+            row("1", "first"),
+            row("2", "second"),
+            row("3", "third"),
+        ])
+    );
+
+    // Single-line code blocks are left as plain strings.
+    assert_eq!(
+        to_cells("```\nonly\n```"),
+        vec![Expr::normal(
+            Symbol::new("System`Cell"),
+            vec![Expr::string("only\n"), Expr::string("Program")],
+        )]
+    );
+}
+
+#[test]
+fn test_tab_indented_code_block() {
+    use pretty_assertions::assert_eq;
+
+    use crate::ast::parse_markdown_to_ast;
+
+    let cells: Vec<Expr> =
+        parse_markdown_to_ast("Example:\n\n\tfn main() {\n\t\tprintln!();\n\t}\n")
+            .into_iter()
+            .flat_map(|block| block_to_cells(block, &Options::default()))
+            .collect();
+
+    assert_eq!(
+        cells[1],
+        Expr::normal(
+            Symbol::new("System`Cell"),
+            vec![
+                Expr::string("fn main() {\n\tprintln!();\n}\n"),
+                Expr::string("Program")
+            ]
+        )
+    );
+}
+
+#[test]
+fn test_cell_auto_overwrite() {
+    use pretty_assertions::assert_eq;
+
+    use crate::ast::parse_markdown_to_ast;
+
+    let opts = Options::builder().cell_auto_overwrite(true).build();
+
+    let cells: Vec<Expr> = parse_markdown_to_ast("```python\nprint(1)\n```")
+        .into_iter()
+        .flat_map(|block| block_to_cells(block, &opts))
+        .collect();
+
+    assert_eq!(
+        cells,
+        vec![Expr::normal(
+            Symbol::new("System`Cell"),
+            vec![
+                Expr::string("print(1)\n"),
+                Expr::string("ExternalLanguage"),
+                Expr::rule(
+                    Symbol::new("System`CellEvaluationLanguage"),
+                    Expr::string("Python")
+                ),
+                Expr::rule(
+                    Symbol::new("System`CellAutoOverwrite"),
+                    Expr::from(Symbol::new("System`True"))
+                ),
+            ]
+        )]
+    );
+}
+
+#[test]
+fn test_abbreviations() {
+    use pretty_assertions::assert_eq;
+
+    use crate::ast::parse_markdown_to_ast;
+
+    let blocks = parse_markdown_to_ast(
+        "Write HTML, not HTMLX.\n\n*[HTML]: HyperText Markup Language\n",
+    );
+
+    // The definition itself is not part of the document.
+    assert_eq!(blocks.len(), 1);
+
+    let cells: Vec<Expr> = blocks
+        .into_iter()
+        .flat_map(|block| block_to_cells(block, &Options::default()))
+        .collect();
+
+    assert_eq!(
+        cells,
+        vec![Expr::normal(
+            Symbol::new("System`Cell"),
+            vec![
+                Expr::normal(
+                    Symbol::new("System`TextData"),
+                    vec![Expr::normal(
+                        Symbol::new("System`RowBox"),
+                        vec![Expr::list(vec![
+                            Expr::string("Write "),
+                            Expr::normal(
+                                Symbol::new("System`TooltipBox"),
+                                vec![
+                                    Expr::normal(
+                                        Symbol::new("System`RowBox"),
+                                        vec![Expr::list(vec![Expr::string("HTML")])]
+                                    ),
+                                    Expr::string("HyperText Markup Language"),
+                                ]
+                            ),
+                            Expr::string(", not HTMLX."),
+                        ])]
+                    )]
+                ),
+                Expr::string("Text"),
+            ]
+        )]
+    );
+}
+
+#[test]
+fn test_wrap_prose() {
+    use pretty_assertions::assert_eq;
+
+    use crate::ast::parse_markdown_to_ast;
+
+    let opts = Options::builder().wrap_prose(Some(10)).build();
+
+    let to_row = |input: &str| -> Expr {
+        let cells: Vec<Expr> = parse_markdown_to_ast(input)
+            .into_iter()
+            .flat_map(|block| block_to_cells(block, &opts))
+            .collect();
+
+        let text_data = cells[0].try_normal().unwrap().elements()[0].clone();
+        let row_box = text_data.try_normal().unwrap().elements()[0].clone();
+
+        row_box.try_normal().unwrap().elements()[0].clone()
+    };
+
+    assert_eq!(
+        to_row("one two three four five"),
+        Expr::list(vec![Expr::string("one two\nthree four\nfive")])
+    );
+
+    // Existing hard breaks reset the column.
+    assert_eq!(
+        to_row("one two  \nthree four"),
+        Expr::list(vec![
+            Expr::string("one two"),
+            Expr::string("\n"),
+            Expr::string("three four"),
+        ])
+    );
+
+    // Inline code is moved to the next line instead of being broken.
+    assert_eq!(
+        to_row("see the `long code`"),
+        Expr::list(vec![
+            Expr::string("see the "),
+            Expr::string("\n"),
+            Expr::normal(
+                Symbol::new("System`StyleBox"),
+                vec![Expr::string("long code"), Expr::string("Code")]
+            ),
+        ])
+    );
+}
+
+#[test]
+fn test_pandoc_info_string() {
+    use pretty_assertions::assert_eq;
+
+    use crate::ast::parse_markdown_to_ast;
+
+    let cells: Vec<Expr> =
+        parse_markdown_to_ast("```{.python .numberLines}\nprint(1)\n```")
+            .into_iter()
+            .flat_map(|block| block_to_cells(block, &Options::default()))
+            .collect();
+
+    assert_eq!(
+        cells,
+        vec![Expr::normal(
+            Symbol::new("System`Cell"),
+            vec![
+                Expr::string("print(1)\n"),
+                Expr::string("ExternalLanguage"),
+                Expr::rule(
+                    Symbol::new("System`CellEvaluationLanguage"),
+                    Expr::string("Python")
+                ),
+            ]
+        )]
+    );
+
+    assert_eq!(info_string_language("{.python}"), "python");
+    assert_eq!(info_string_language("{#id .R}"), "R");
+    assert_eq!(info_string_language("{#id}"), "");
+    assert_eq!(info_string_language("python"), "python");
+}
+
+#[test]
+fn test_info_string_language_detection() {
+    use crate::ast::parse_markdown_to_ast;
+
+    let language_of = |input: &str| -> Option<Expr> {
+        let cells: Vec<Expr> = parse_markdown_to_ast(input)
+            .into_iter()
+            .flat_map(|block| block_to_cells(block, &Options::default()))
+            .collect();
+
+        cells[0].try_normal().unwrap().elements().get(2).cloned()
+    };
+
+    let python = Some(Expr::rule(
+        Symbol::new("System`CellEvaluationLanguage"),
+        Expr::string("Python"),
+    ));
+
+    assert_eq!(language_of("```python \nx\n```"), python);
+    assert_eq!(language_of("```Python3\nx\n```"), python);
+    assert_eq!(language_of("```python title=\"a.py\"\nx\n```"), python);
+    assert_eq!(language_of("```*python*\nx\n```"), python);
+    assert_eq!(language_of("```pythonic\nx\n```"), None);
+}
+
+#[test]
+fn test_center_tables() {
+    use pretty_assertions::assert_eq;
+
+    use crate::ast::parse_markdown_to_ast;
+
+    let to_cells = |opts: &Options| -> Vec<Expr> {
+        parse_markdown_to_ast("| a |\n|---|\n| b |")
+            .into_iter()
+            .flat_map(|block| block_to_cells(block, opts))
+            .collect()
+    };
+
+    let centered = Expr::rule(
+        Symbol::new("System`TextAlignment"),
+        Expr::from(Symbol::new("System`Center")),
+    );
+
+    let cells = to_cells(&Options::builder().center_tables(true).build());
+    assert_eq!(cells[0].try_normal().unwrap().elements()[2], centered);
+
+    let cells = to_cells(&Options::default());
+    assert_eq!(cells[0].try_normal().unwrap().elements().len(), 2);
+}
+
+#[test]
+fn test_table_column_alignment() {
+    use pretty_assertions::assert_eq;
+
+    use crate::ast::parse_markdown_to_ast;
+
+    let grid_box_options = |markdown: &str| -> Vec<Expr> {
+        let cells: Vec<Expr> = parse_markdown_to_ast(markdown)
+            .into_iter()
+            .flat_map(|block| block_to_cells(block, &Options::default()))
+            .collect();
+
+        let box_data = cells[0].try_normal().unwrap().elements()[0].clone();
+        let grid_box = box_data.try_normal().unwrap().elements()[0].clone();
+
+        grid_box.try_normal().unwrap().elements()[1..].to_vec()
+    };
+
+    let options =
+        grid_box_options("| a | b | c | d |\n|:--|:-:|--:|---|\n| 1 | 2 | 3 | 4 |");
+    assert_eq!(
+        options.last(),
+        Some(&Expr::rule(
+            Symbol::new("System`GridBoxAlignment"),
+            Expr::list(vec![Expr::rule(
+                Expr::from("Columns"),
+                Expr::list(vec![
+                    Expr::from(Symbol::new("System`Left")),
+                    Expr::from(Symbol::new("System`Center")),
+                    Expr::from(Symbol::new("System`Right")),
+                    Expr::from(Symbol::new("System`Automatic")),
+                ]),
+            )]),
+        ))
+    );
+
+    // Tables without any column alignments don't set GridBoxAlignment.
+    assert_eq!(grid_box_options("| a |\n|---|\n| 1 |").len(), 1);
+}
+
+#[test]
+fn test_table_as_dataset() {
+    use pretty_assertions::assert_eq;
+
+    use crate::ast::parse_markdown_to_ast;
+
+    let opts = Options::builder().table_as_dataset(true).build();
+
+    let to_cells = |input: &str| -> Vec<Expr> {
+        parse_markdown_to_ast(input)
+            .into_iter()
+            .flat_map(|block| block_to_cells(block, &opts))
+            .collect()
+    };
+
+    assert_eq!(
+        to_cells("| Name | Score |\n|---|---|\n| Ada \"A\" | 9.5 |\n| Bob | -10 |"),
+        vec![Expr::normal(
+            Symbol::new("System`Cell"),
+            vec![
+                Expr::string(concat!(
+                    r#"Dataset[{<|"Name" -> "Ada \"A\"", "Score" -> 9.5|>, "#,
+                    r#"<|"Name" -> "Bob", "Score" -> -10|>}]"#
+                )),
+                Expr::string("Input"),
+            ]
+        )]
+    );
+
+    // Tables with styled text are still converted to a grid.
+    let cells = to_cells("| Name |\n|---|\n| **Ada** |");
+    assert_eq!(
+        cells[0].try_normal().unwrap().elements()[1],
+        Expr::from("Text")
+    );
+}
+
+#[test]
+fn test_try_block_to_cells() {
+    use pretty_assertions::assert_eq;
+
+    use crate::ast::parse_markdown_to_ast;
+
+    let opts = Options::default();
+
+    let cells: Vec<Expr> = parse_markdown_to_ast("Start\n\n- ***\n\nEnd")
+        .into_iter()
+        .flat_map(|block| match try_block_to_cells(block, &opts) {
+            Ok(cells) => cells,
+            Err(unsupported) => vec![unsupported_cell(unsupported)],
+        })
+        .collect();
+
+    let text_cell = |text: &str| {
+        Expr::normal(
+            Symbol::new("System`Cell"),
+            vec![
+                Expr::normal(
+                    Symbol::new("System`TextData"),
+                    vec![Expr::normal(
+                        Symbol::new("System`RowBox"),
+                        vec![Expr::list(vec![Expr::string(text)])],
+                    )],
+                ),
+                Expr::from("Text"),
+            ],
+        )
+    };
+
+    assert_eq!(
+        cells,
+        vec![
+            text_cell("Start"),
+            Expr::normal(
+                Symbol::new("System`Cell"),
+                vec![
+                    Expr::string("[unsupported: markdown rule inside list item]"),
+                    Expr::string("Text"),
+                ],
+            ),
+            text_cell("End"),
+        ]
+    );
+
+    // Unsupported constructs nested in block quotes are also reported.
+    let block = parse_markdown_to_ast("> - ***").remove(0);
+    assert_eq!(
+        try_block_to_cells(block, &opts),
+        Err(Unsupported("markdown rule inside list item"))
+    );
+}
+
+#[test]
+fn test_inline_code_background() {
+    use pretty_assertions::assert_eq;
+
+    use crate::ast::parse_markdown_to_ast;
+
+    let opts = Options::builder().inline_code_background(true).build();
+
+    let cells: Vec<Expr> = parse_markdown_to_ast("`x`")
+        .into_iter()
+        .flat_map(|block| block_to_cells(block, &opts))
+        .collect();
+
+    assert_eq!(
+        cells,
+        vec![Expr::normal(
+            Symbol::new("System`Cell"),
+            vec![
+                Expr::normal(
+                    Symbol::new("System`TextData"),
+                    vec![Expr::normal(
+                        Symbol::new("System`RowBox"),
+                        vec![Expr::list(vec![Expr::normal(
+                            Symbol::new("System`StyleBox"),
+                            vec![
+                                Expr::string("x"),
+                                Expr::string("Code"),
+                                Expr::rule(
+                                    Symbol::new("System`Background"),
+                                    Expr::normal(
+                                        Symbol::new("System`GrayLevel"),
+                                        vec![Expr::real(0.95)]
+                                    )
+                                ),
+                            ]
+                        )])]
+                    )]
+                ),
+                Expr::string("Text"),
+            ]
+        )]
+    );
+}
+
+#[test]
+fn test_docked_cells() {
+    use pretty_assertions::assert_eq;
+
+    let options = notebook_options(
+        &Options::builder()
+            .header_text(Some("Example Corp".into()))
+            .footer_text(Some("v1.0".into()))
+            .build(),
+    );
+
+    assert_eq!(
+        options.iter().map(ToString::to_string).collect::<Vec<_>>(),
+        vec![
+            "System`Rule[System`DockedCells, System`List[\
+             System`Cell[\"Example Corp\", \"DockedCell\"], \
+             System`Cell[\"v1.0\", \"DockedCell\"]]]"
+        ]
+    );
+}
+
+#[test]
+fn test_max_cell_chars() {
+    use pretty_assertions::assert_eq;
+
+    use crate::ast::parse_markdown_to_ast;
+
+    let opts = Options::builder().max_cell_chars(Some(12)).build();
+
+    let cells: Vec<Expr> =
+        parse_markdown_to_ast("one two three four five six seven eight nine ten")
+            .into_iter()
+            .flat_map(|block| block_to_cells(block, &opts))
+            .collect();
+
+    let text_cell = |text: &str| {
+        Expr::normal(
+            Symbol::new("System`Cell"),
+            vec![
+                Expr::normal(
+                    Symbol::new("System`TextData"),
+                    vec![Expr::normal(
+                        Symbol::new("System`RowBox"),
+                        vec![Expr::list(vec![Expr::string(text)])],
+                    )],
+                ),
+                Expr::string("Text"),
+            ],
+        )
+    };
+
+    assert_eq!(
+        cells,
+        vec![
+            text_cell("one two"),
+            text_cell("three four"),
+            text_cell("five six"),
+            text_cell("seven eight"),
+            text_cell("nine ten"),
+        ]
+    );
+
+    let pieces = |input: &str, max_chars: usize| -> Vec<String> {
+        let text = Text(vec![TextSpan::Text(input.into(), Default::default())]);
+
+        split_text(text, max_chars)
+            .iter()
+            .map(text_content)
+            .collect()
+    };
+
+    // Words longer than the limit are not broken, and characters are counted, not
+    // bytes.
+    assert_eq!(
+        pieces("ab extraordinary cd", 5),
+        vec!["ab", "extraordinary", "cd"]
+    );
+    assert_eq!(pieces("äöü äöü äöü", 7), vec!["äöü äöü", "äöü"]);
+
+    // A huge pasted line is split into many pieces.
+    let pieces = pieces(&"word ".repeat(200_000), 1000);
+    assert_eq!(pieces.len(), 1000);
+    assert!(pieces.iter().all(|piece| piece.chars().count() <= 1000));
+}
+
+#[test]
+fn test_html_anchors() {
+    use pretty_assertions::assert_eq;
+
+    use crate::ast::{self, parse_markdown_to_ast_with_options};
+
+    let cells: Vec<Expr> = parse_markdown_to_ast_with_options(
+        "See [installing](#install).\n\n## <a name=\"install\"></a>Installation",
+        &ast::Options {
+            html_attributes: true,
+            ..ast::Options::default()
+        },
+    )
+    .into_iter()
+    .flat_map(|block| block_to_cells(block, &Options::default()))
+    .collect();
+
+    let row_box = |elements: Vec<Expr>| {
+        Expr::normal(
+            Symbol::new("System`TextData"),
+            vec![Expr::normal(
+                Symbol::new("System`RowBox"),
+                vec![Expr::list(elements)],
+            )],
+        )
+    };
+
+    assert_eq!(
+        cells,
+        vec![
+            Expr::normal(
+                Symbol::new("System`Cell"),
+                vec![
+                    row_box(vec![
+                        Expr::string("See "),
+                        Expr::normal(
+                            Symbol::new("System`ButtonBox"),
+                            vec![
+                                Expr::normal(
+                                    Symbol::new("System`RowBox"),
+                                    vec![Expr::list(vec![Expr::string("installing")])]
+                                ),
+                                Expr::rule(
+                                    Symbol::new("System`BaseStyle"),
+                                    Expr::string("Link")
+                                ),
+                                Expr::rule(
+                                    Symbol::new("System`ButtonData"),
+                                    Expr::string("install")
+                                ),
+                            ]
+                        ),
+                        Expr::string("."),
+                    ]),
+                    Expr::string("Text"),
+                ]
+            ),
+            Expr::normal(
+                Symbol::new("System`Cell"),
+                vec![
+                    row_box(vec![Expr::string("Installation")]),
+                    Expr::string("Chapter"),
+                    Expr::rule(
+                        Symbol::new("System`CellTags"),
+                        Expr::list(vec![Expr::string("install")])
+                    ),
+                ]
+            ),
+        ]
+    );
+}
+
+#[test]
+fn test_heading_styles() {
+    use pretty_assertions::assert_eq;
+
+    use crate::ast::parse_markdown_to_ast;
+
+    assert_eq!(
+        "H1=Section".parse::<HeadingStyle>(),
+        Ok(HeadingStyle {
+            level: HeadingLevel::H1,
+            style: "Section".into()
+        })
+    );
+    assert_eq!(
+        "h2 = Subsection".parse::<HeadingStyle>(),
+        Ok(HeadingStyle {
+            level: HeadingLevel::H2,
+            style: "Subsection".into()
+        })
+    );
+
+    assert!("H1".parse::<HeadingStyle>().is_err());
+    assert!("H7=Section".parse::<HeadingStyle>().is_err());
+    assert!("Title=Section".parse::<HeadingStyle>().is_err());
+    assert!("H1=".parse::<HeadingStyle>().is_err());
+    assert!("H1=  ".parse::<HeadingStyle>().is_err());
+
+    let opts = Options::builder()
+        .heading_styles(BTreeMap::from([(HeadingLevel::H1, "Section".to_owned())]))
+        .build();
+
+    let cells: Vec<Expr> = parse_markdown_to_ast("# One\n\n## Two")
+        .into_iter()
+        .flat_map(|block| block_to_cells(block, &opts))
+        .collect();
+
+    let styles: Vec<Option<&str>> = cells.iter().map(cell_style).collect();
+
+    assert_eq!(styles, vec![Some("Section"), Some("Chapter")]);
+}
+
+#[test]
+fn test_heading_id_cell_tags() {
+    use pretty_assertions::assert_eq;
+
+    use crate::ast::parse_markdown_to_ast;
+
+    let cells: Vec<Expr> = parse_markdown_to_ast("## Setup {#setup}")
+        .into_iter()
+        .flat_map(|block| block_to_cells(block, &Options::default()))
+        .collect();
+
+    assert_eq!(
+        cells,
+        vec![Expr::normal(
+            Symbol::new("System`Cell"),
+            vec![
+                Expr::normal(
+                    Symbol::new("System`TextData"),
+                    vec![Expr::normal(
+                        Symbol::new("System`RowBox"),
+                        vec![Expr::list(vec![Expr::string("Setup")])],
+                    )],
+                ),
+                Expr::from("Chapter"),
+                Expr::rule(
+                    Symbol::new("System`CellTags"),
+                    Expr::list(vec![Expr::string("setup")]),
+                ),
+            ],
+        )]
+    );
+}
+
+#[test]
+fn test_html_block() {
+    use pretty_assertions::assert_eq;
+
+    assert_eq!(
+        block_to_cells(
+            Block::Html("<div>\n<b>hi</b>\n</div>\n".into()),
+            &Options::default()
+        ),
+        vec![Expr::normal(
+            Symbol::new("System`Cell"),
+            vec![
+                Expr::string("<div>\n<b>hi</b>\n</div>\n"),
+                Expr::string("Program")
+            ],
+        )]
+    );
+}
+
+#[test]
+fn test_link_title() {
+    use crate::ast::parse_markdown_to_ast;
+
+    let cells: Vec<Expr> = parse_markdown_to_ast(
+        "[a](https://example.com \"Example site\") and [b](https://example.org)",
+    )
+    .into_iter()
+    .flat_map(|block| block_to_cells(block, &Options::default()))
+    .collect();
+
+    // The tooltip is the link title, if there is one, and otherwise the URL.
+    let cell = write_notebook_string(&cells[0]);
+    assert!(cell.contains("ButtonNote -> \"Example site\""), "{cell}");
+    assert!(
+        cell.contains("ButtonNote -> \"https://example.org\""),
+        "{cell}"
+    );
+}
+
+#[test]
+fn test_email_link() {
+    use pretty_assertions::assert_eq;
+
+    use crate::ast::parse_markdown_to_ast;
+
+    let cells: Vec<Expr> = parse_markdown_to_ast("<me@example.com>")
+        .into_iter()
+        .flat_map(|block| block_to_cells(block, &Options::default()))
+        .collect();
+
+    assert_eq!(
+        cells,
+        vec![Expr::normal(
+            Symbol::new("System`Cell"),
+            vec![
+                Expr::normal(
+                    Symbol::new("System`TextData"),
+                    vec![Expr::normal(
+                        Symbol::new("System`RowBox"),
+                        vec![Expr::list(vec![Expr::normal(
+                            Symbol::new("System`ButtonBox"),
+                            vec![
+                                Expr::normal(
+                                    Symbol::new("System`RowBox"),
+                                    vec![Expr::list(vec![Expr::string(
+                                        "me@example.com"
+                                    )])],
+                                ),
+                                Expr::rule(
+                                    Symbol::new("System`BaseStyle"),
+                                    Expr::string("Hyperlink")
+                                ),
+                                Expr::rule(
+                                    Symbol::new("System`ButtonData"),
+                                    Expr::list(vec![
+                                        Expr::normal(
+                                            Symbol::new("System`URL"),
+                                            vec![Expr::string("mailto:me@example.com")]
+                                        ),
+                                        Expr::from(Symbol::new("System`None")),
+                                    ])
+                                ),
+                                Expr::rule(
+                                    Symbol::new("System`ButtonNote"),
+                                    Expr::string("me@example.com")
+                                ),
+                            ],
+                        )])],
+                    )],
+                ),
+                Expr::string("Text"),
+            ],
+        )]
+    );
+}
+
+#[test]
+fn test_link_to_heading_id() {
+    use pretty_assertions::assert_eq;
+
+    use crate::ast::parse_markdown_to_ast;
+
+    let cells: Vec<Expr> = parse_markdown_to_ast("[jump](#setup)\n\n## Setup {#setup}")
+        .into_iter()
+        .flat_map(|block| block_to_cells(block, &Options::default()))
+        .collect();
+
+    // The link targets the heading's cell tag, instead of being a URL hyperlink.
+    let text_data = cells[0].try_normal().unwrap().elements()[0].clone();
+    assert_eq!(
+        text_data,
+        Expr::normal(
+            Symbol::new("System`TextData"),
+            vec![Expr::normal(
+                Symbol::new("System`RowBox"),
+                vec![Expr::list(vec![Expr::normal(
+                    Symbol::new("System`ButtonBox"),
+                    vec![
+                        Expr::normal(
+                            Symbol::new("System`RowBox"),
+                            vec![Expr::list(vec![Expr::string("jump")])],
+                        ),
+                        Expr::rule(Symbol::new("System`BaseStyle"), Expr::string("Link")),
+                        Expr::rule(
+                            Symbol::new("System`ButtonData"),
+                            Expr::string("setup")
+                        ),
+                    ],
+                )])],
+            )],
+        )
+    );
+
+    assert_eq!(
+        cells[1].try_normal().unwrap().elements().last(),
+        Some(&Expr::rule(
+            Symbol::new("System`CellTags"),
+            Expr::list(vec![Expr::string("setup")]),
+        ))
+    );
+}
+
+#[test]
+fn test_rule_style() {
+    use pretty_assertions::assert_eq;
+
+    use crate::ast::parse_markdown_to_ast;
+
+    assert_eq!("dots".parse::<RuleStyle>(), Ok(RuleStyle::Dots));
+    assert!("stars".parse::<RuleStyle>().is_err());
+
+    let to_cells = |style: RuleStyle| -> Vec<Expr> {
+        let opts = Options::builder().rule_style(style).build();
+
+        parse_markdown_to_ast("***")
+            .into_iter()
+            .flat_map(|block| block_to_cells(block, &opts))
+            .collect()
+    };
+
+    let line = to_cells(RuleStyle::Line);
+    let dots = to_cells(RuleStyle::Dots);
+    let space = to_cells(RuleStyle::Space);
+
+    assert_eq!(line, to_cells(Options::default().rule_style));
+    assert_eq!(line, vec![rule_cell(RuleStyle::Line)]);
+    assert_eq!(dots, vec![rule_cell(RuleStyle::Dots)]);
+    assert_eq!(space, vec![rule_cell(RuleStyle::Space)]);
+
+    assert_ne!(line, dots);
+    assert_ne!(line, space);
+    assert_ne!(dots, space);
+
+    assert_eq!(
+        dots[0].try_normal().unwrap().elements()[0],
+        Expr::string("*   *   *")
+    );
+
+    // A rule between paragraphs is a cell of its own.
+    let cells: Vec<Expr> = parse_markdown_to_ast("a\n\n---\n\nb")
+        .into_iter()
+        .flat_map(|block| block_to_cells(block, &Options::default()))
+        .collect();
+
+    assert_eq!(cells.len(), 3);
+    assert_eq!(cells[1], rule_cell(RuleStyle::Line));
+}
+
+#[test]
+fn test_raw_markdown_cell() {
+    use pretty_assertions::assert_eq;
+
+    let input = "# Title\n\nSome \"quoted\" text with `code`, \\ and unicode: \u{3bb}\n";
+
+    assert_eq!(
+        raw_markdown_cell(input),
+        Expr::normal(
+            Symbol::new("System`Cell"),
+            vec![
+                Expr::string(input),
+                Expr::string("RawMarkdown"),
+                Expr::rule(
+                    Symbol::new("System`CellOpen"),
+                    Expr::from(Symbol::new("System`False"))
+                ),
+            ]
+        )
+    );
+}
+
+#[test]
+fn test_write_notebook_string() {
+    use pretty_assertions::assert_eq;
+
+    let opts = Options {
+        page_width: Some(PageWidth::Points(500)),
+        ..Options::default()
+    };
+
+    let notebook = cells_to_notebook_expr(
+        block_to_cells(
+            Block::Paragraph(Text(vec![TextSpan::Text(
+                "Say \"héllo\" \\ 😀\n".to_owned(),
+                Default::default(),
+            )])),
+            &opts,
+        ),
+        &opts,
+    );
+
+    assert_eq!(
+        write_notebook_string(&notebook),
+        "Notebook[{Cell[TextData[RowBox[{\"Say \\\"h\\:00e9llo\\\" \\\\ \\|01f600\\n\"}]], \
+         \"Text\"]}, PageWidth -> 500]\n"
+    );
+
+    assert_eq!(
+        write_notebook_string(&Expr::list(vec![
+            Expr::real(0.5),
+            Expr::real(1e-10),
+            Expr::from(Symbol::new("Global`x")),
+        ])),
+        "{0.5, 1*^-10, Global`x}\n"
+    );
+
+    assert!(!requires_kernel(&notebook));
+    assert!(requires_kernel(&Expr::normal(
+        Symbol::new("System`Cell"),
+        vec![math_boxes("x^2"), Expr::string("Input")]
+    )));
+}
+
+#[test]
+fn test_ordered_list() {
+    use pretty_assertions::assert_eq;
+
+    use crate::ast::parse_markdown_to_ast;
+
+    let to_cells = |markdown: &str| -> Vec<Expr> {
+        parse_markdown_to_ast(markdown)
+            .into_iter()
+            .flat_map(|block| block_to_cells(block, &Options::default()))
+            .collect()
+    };
+
+    assert_eq!(
+        to_cells("- a\n- b")
+            .iter()
+            .map(cell_style)
+            .collect::<Vec<_>>(),
+        vec![Some("Item"), Some("Item")]
+    );
+
+    let cells = to_cells("1. a\n   - b\n2. c\n\n   3. d");
+
+    assert_eq!(
+        cells.iter().map(cell_style).collect::<Vec<_>>(),
+        vec![
+            Some("ItemNumbered"),
+            Some("Subitem"),
+            Some("ItemNumbered"),
+            Some("SubitemNumbered"),
+        ]
+    );
+
+    let counter_assignments = |style: &str, start: i64| {
+        Expr::rule(
+            Symbol::new("System`CounterAssignments"),
+            Expr::list(vec![Expr::list(vec![
+                Expr::string(style),
+                Expr::from(start - 1),
+            ])]),
+        )
+    };
+
+    assert_eq!(
+        cells[0],
+        Expr::normal(
+            Symbol::new("System`Cell"),
+            vec![
+                text_to_text_data(
+                    Text(vec![TextSpan::Text("a".to_owned(), Default::default())]),
+                    &Options::default()
+                ),
+                Expr::string("ItemNumbered"),
+                counter_assignments("ItemNumbered", 1),
+            ]
+        )
+    );
+    assert_eq!(
+        cells[3].try_normal().unwrap().elements()[3],
+        counter_assignments("SubitemNumbered", 3)
+    );
+    assert_eq!(cells[2].try_normal().unwrap().elements().len(), 2);
+}
+
+#[test]
+fn test_tight_and_loose_lists() {
+    use pretty_assertions::assert_eq;
+
+    use crate::ast::parse_markdown_to_ast;
+
+    let tight = parse_markdown_to_ast("- a\n- b");
+    let loose = parse_markdown_to_ast("- a\n\n- b");
+
+    assert!(matches!(
+        tight.as_slice(),
+        [Block::List { tight: true, .. }]
+    ));
+    assert!(matches!(
+        loose.as_slice(),
+        [Block::List { tight: false, .. }]
+    ));
+
+    let to_cells = |blocks: Vec<Block>| -> Vec<Expr> {
+        blocks
+            .into_iter()
+            .flat_map(|block| block_to_cells(block, &Options::default()))
+            .collect()
+    };
+
+    let item = |text: &str, options: Vec<Expr>| {
+        let mut elements = vec![
+            text_to_text_data(
+                Text(vec![TextSpan::Text(text.to_owned(), Default::default())]),
+                &Options::default(),
+            ),
+            Expr::string("Item"),
+        ];
+        elements.extend(options);
+        Expr::normal(Symbol::new("System`Cell"), elements)
+    };
+
+    // CellMargins -> {{Inherited, Inherited}, {2, 2}}
+    let tight_margins = || {
+        Expr::rule(
+            Symbol::new("System`CellMargins"),
+            Expr::list(vec![
+                Expr::list(vec![
+                    Expr::from(Symbol::new("System`Inherited")),
+                    Expr::from(Symbol::new("System`Inherited")),
+                ]),
+                Expr::list(vec![Expr::from(2), Expr::from(2)]),
+            ]),
+        )
+    };
+
+    assert_eq!(
+        to_cells(tight),
+        vec![
+            item("a", vec![tight_margins()]),
+            item("b", vec![tight_margins()])
+        ]
+    );
+    assert_eq!(to_cells(loose), vec![item("a", vec![]), item("b", vec![])]);
+}
+
+#[test]
+fn test_ordered_list_start() {
+    use pretty_assertions::assert_eq;
+
+    use crate::ast::parse_markdown_to_ast;
+
+    let cells: Vec<Expr> = parse_markdown_to_ast("5. five\n6. six")
+        .into_iter()
+        .flat_map(|block| block_to_cells(block, &Options::default()))
+        .collect();
+
+    assert_eq!(cells.len(), 2);
+
+    // CounterAssignments -> {{"ItemNumbered", 4}}
+    assert_eq!(
+        cells[0].try_normal().unwrap().elements()[3],
+        Expr::rule(
+            Symbol::new("System`CounterAssignments"),
+            Expr::list(vec![Expr::list(vec![
+                Expr::string("ItemNumbered"),
+                Expr::from(4)
+            ])]),
+        )
+    );
+    assert_eq!(cells[1].try_normal().unwrap().elements().len(), 3);
+}
+
+#[test]
+fn test_group_cells_by_heading() {
+    use pretty_assertions::assert_eq;
+
+    use crate::ast::parse_markdown_to_ast;
+
+    let to_cells = |input: &str| -> Vec<Expr> {
+        parse_markdown_to_ast(input)
+            .into_iter()
+            .flat_map(|block| block_to_cells(block, &Options::default()))
+            .collect()
+    };
+
+    let cells = to_cells(
+        "intro\n\n### One\n\nfirst\n\n#### One A\n\nnested\n\n### Two\n\nsecond\n\n### Three",
+    );
+    let [intro, one, first, one_a, nested, two, second, three]: [Expr; 8] =
+        cells.clone().try_into().unwrap();
+
+    assert_eq!(
+        group_cells_by_heading(cells),
+        vec![
+            intro,
+            cell_group(vec![one, first, cell_group(vec![one_a, nested])]),
+            cell_group(vec![two, second]),
+            three,
+        ]
+    );
+
+    // The contents of a `--wrap-in-group` group are grouped after its title.
+    let cells = to_cells("### One\n\nfirst\n\n### Two\n\nsecond");
+    let [one, first, two, second]: [Expr; 4] = cells.clone().try_into().unwrap();
+
+    let title = Expr::normal(
+        Symbol::new("System`Cell"),
+        vec![Expr::string("file"), Expr::string("Title")],
+    );
+
+    assert_eq!(
+        group_cells_by_heading(vec![wrap_in_group(cells, "file")]),
+        vec![cell_group(vec![
+            title,
+            cell_group(vec![one, first]),
+            cell_group(vec![two, second]),
+        ])]
+    );
+}
+
+#[test]
+fn test_table_of_contents_cell() {
+    use pretty_assertions::assert_eq;
+
+    use crate::ast::parse_markdown_to_ast;
+
+    let opts = Options::default();
+
+    let mut blocks =
+        parse_markdown_to_ast("# Guide\n\nintro\n\n## Install {#setup}\n\n## Guide");
+
+    let toc = table_of_contents_cell(&mut blocks, &opts).unwrap();
+
+    let label = |text: &str| {
+        text_to_boxes(
+            Text(vec![TextSpan::Text(text.to_owned(), Default::default())]),
+            &opts,
+        )
+    };
+    let link = |text: &str, id: &str| {
+        Expr::normal(
+            Symbol::new("System`ButtonBox"),
+            vec![
+                label(text),
+                Expr::rule(Symbol::new("System`BaseStyle"), Expr::string("Link")),
+                Expr::rule(Symbol::new("System`ButtonData"), Expr::string(id)),
+            ],
+        )
+    };
+
+    assert_eq!(
+        toc,
+        Expr::normal(
+            Symbol::new("System`Cell"),
+            vec![
+                Expr::normal(
+                    Symbol::new("System`TextData"),
+                    vec![Expr::list(vec![
+                        Expr::string("\u{2022} "),
+                        link("Guide", "guide"),
+                        Expr::string("\n"),
+                        Expr::string("\t\u{2022} "),
+                        link("Install", "setup"),
+                        Expr::string("\n"),
+                        Expr::string("\t\u{2022} "),
+                        link("Guide", "guide-1"),
+                    ])]
+                ),
+                Expr::string("Text"),
+            ]
+        )
+    );
+
+    // The links target the CellTags of the heading cells.
+    let tags: Vec<Option<Expr>> = blocks
+        .into_iter()
+        .flat_map(|block| block_to_cells(block, &opts))
+        .map(|cell| {
+            cell.try_normal()
+                .unwrap()
+                .elements()
+                .iter()
+                .find(|option| {
+                    option.try_normal().is_some_and(|rule| {
+                        rule.elements()[0].try_symbol()
+                            == Some(&Symbol::new("System`CellTags"))
+                    })
+                })
+                .cloned()
+        })
+        .collect();
+
+    let cell_tags = |id: &str| {
+        Some(Expr::rule(
+            Symbol::new("System`CellTags"),
+            Expr::list(vec![Expr::string(id)]),
+        ))
+    };
+
+    assert_eq!(
+        tags,
+        vec![
+            cell_tags("guide"),
+            None,
+            cell_tags("setup"),
+            cell_tags("guide-1")
+        ]
+    );
+
+    assert_eq!(
+        table_of_contents_cell(&mut parse_markdown_to_ast("no headings"), &opts),
+        None
+    );
+}
+
+#[test]
+fn test_soft_break_mode() {
+    use pretty_assertions::assert_eq;
+
+    use crate::ast::parse_markdown_to_ast;
+
+    let to_row = |soft_break: SoftBreakMode| -> Vec<Expr> {
+        let opts = Options::builder().soft_break(soft_break).build();
+
+        let cells: Vec<Expr> = parse_markdown_to_ast("line1\nline2")
+            .into_iter()
+            .flat_map(|block| block_to_cells(block, &opts))
+            .collect();
+
+        // Cell[TextData[RowBox[{...}]], "Text"]
+        let text_data = &cells[0].try_normal().unwrap().elements()[0];
+        let row_box = &text_data.try_normal().unwrap().elements()[0];
+        let row = &row_box.try_normal().unwrap().elements()[0];
+
+        row.try_normal().unwrap().elements().to_vec()
+    };
+
+    assert_eq!(
+        to_row(SoftBreakMode::Space),
+        vec![
+            Expr::string("line1"),
+            Expr::string(" "),
+            Expr::string("line2")
+        ]
+    );
+    assert_eq!(
+        to_row(SoftBreakMode::Newline),
+        vec![
+            Expr::string("line1"),
+            Expr::string("\n"),
+            Expr::string("line2")
+        ]
+    );
+    assert_eq!(
+        to_row(SoftBreakMode::None),
+        vec![Expr::string("line1"), Expr::string("line2")]
+    );
+
+    assert_eq!("none".parse(), Ok(SoftBreakMode::None));
+    assert!("tab".parse::<SoftBreakMode>().is_err());
+}
+
+#[test]
+fn test_code_block_special_characters() {
+    use pretty_assertions::assert_eq;
+
+    use crate::ast::parse_markdown_to_ast;
+
+    let c_code = r#"printf("%s\n", x);"#;
+    let regex = r#"^\d+\s*"[^"\\]*"$"#;
+
+    let cells: Vec<Expr> =
+        parse_markdown_to_ast(&format!("```c\n{c_code}\n```\n\n```\n{regex}\n```"))
+            .into_iter()
+            .flat_map(|block| block_to_cells(block, &Options::default()))
+            .collect();
+
+    // The code is passed to the cell unchanged; the WSTP string is not escaped.
+    let code = |cell: &Expr| cell.try_normal().unwrap().elements()[0].clone();
+    assert_eq!(code(&cells[0]), Expr::string(format!("{c_code}\n")));
+    assert_eq!(code(&cells[1]), Expr::string(format!("{regex}\n")));
+
+    // When writing the notebook file directly, quotes, backslashes, and newlines are
+    // escaped in the string literal.
+    assert_eq!(
+        write_notebook_string(&code(&cells[0])).trim_end(),
+        r#""printf(\"%s\\n\", x);\n""#
+    );
+    assert_eq!(
+        write_notebook_string(&code(&cells[1])).trim_end(),
+        r#""^\\d+\\s*\"[^\"\\\\]*\"$\n""#
+    );
+}
+
+#[test]
+fn test_code_block_languages() {
+    use pretty_assertions::assert_eq;
+
+    use crate::ast::parse_markdown_to_ast;
+
+    let to_cells = |input: &str, opts: &Options| -> Vec<Expr> {
+        parse_markdown_to_ast(input)
+            .into_iter()
+            .flat_map(|block| block_to_cells(block, opts))
+            .collect()
+    };
+
+    assert_eq!(
+        to_cells("```ruby\nputs 1\n```", &Options::default()),
+        vec![Expr::normal(
+            Symbol::new("System`Cell"),
+            vec![
+                Expr::string("puts 1\n"),
+                Expr::string("ExternalLanguage"),
+                Expr::rule(
+                    Symbol::new("System`CellEvaluationLanguage"),
+                    Expr::string("Ruby"),
+                ),
+            ],
+        )]
+    );
+
+    // Wolfram Language code is an evaluatable "Input" cell, not an external language.
+    for language in ["wolfram", "wl", "mathematica"] {
+        assert_eq!(
+            to_cells(
+                &format!("```{language}\nPlot[x, {{x, 0, 1}}]\n```"),
+                &Options::default()
+            ),
+            vec![Expr::normal(
+                Symbol::new("System`Cell"),
+                vec![Expr::string("Plot[x, {x, 0, 1}]\n"), Expr::string("Input")],
+            )],
+            "language: {language}"
+        );
+    }
+
+    let opts = Options::builder()
+        .create_external_language_cells(false)
+        .cell_auto_overwrite(true)
+        .evaluator(Some("PythonSession".to_owned()))
+        .build();
+
+    assert_eq!(
+        to_cells("```wl\n1 + 1\n```", &opts),
+        vec![Expr::normal(
+            Symbol::new("System`Cell"),
+            vec![
+                Expr::string("1 + 1\n"),
+                Expr::string("Input"),
+                Expr::rule(
+                    Symbol::new("System`CellAutoOverwrite"),
+                    Expr::from(Symbol::new("System`True")),
+                ),
+            ],
+        )]
+    );
+
+    // Unknown languages are static "Program" cells.
+    assert_eq!(
+        to_cells("```cobol\nSTOP RUN.\n```", &Options::default()),
+        vec![Expr::normal(
+            Symbol::new("System`Cell"),
+            vec![Expr::string("STOP RUN.\n"), Expr::string("Program")],
+        )]
+    );
+}
+
+#[test]
+fn test_language_map() {
+    use pretty_assertions::assert_eq;
+
+    use crate::ast::parse_markdown_to_ast;
+
+    let opts = Options::builder()
+        .language_map(BTreeMap::from([
+            ("python".to_owned(), "Program".to_owned()),
+            ("rust".to_owned(), "Rust".to_owned()),
+        ]))
+        .build();
+
+    let to_cells = |input: &str| -> Vec<Expr> {
+        parse_markdown_to_ast(input)
+            .into_iter()
+            .flat_map(|block| block_to_cells(block, &opts))
+            .collect()
+    };
+
+    assert_eq!(
+        to_cells("```Python\nprint(1)\n```"),
+        vec![Expr::normal(
+            Symbol::new("System`Cell"),
+            vec![Expr::string("print(1)\n"), Expr::string("Program")],
+        )]
+    );
+    assert_eq!(
+        to_cells("```rust\nfn main() {}\n```"),
+        vec![Expr::normal(
+            Symbol::new("System`Cell"),
+            vec![
+                Expr::string("fn main() {}\n"),
+                Expr::string("ExternalLanguage"),
+                Expr::rule(
+                    Symbol::new("System`CellEvaluationLanguage"),
+                    Expr::string("Rust"),
+                ),
+            ],
+        )]
+    );
+
+    assert_eq!(
+        "Rust = Program".parse(),
+        Ok(LanguageMapping {
+            language: "rust".to_owned(),
+            target: "Program".to_owned(),
+        })
+    );
+    assert!("rust".parse::<LanguageMapping>().is_err());
+    assert!("rust=".parse::<LanguageMapping>().is_err());
+}
+
+#[test]
+fn test_parse_wolfram_code() {
+    use pretty_assertions::assert_eq;
+
+    use crate::ast::parse_markdown_to_ast;
+
+    let opts = Options::builder().parse_wolfram_code(true).build();
+
+    let cells: Vec<Expr> = parse_markdown_to_ast("```wl\nPlot[x, {x, 0, 1}]\n```")
+        .into_iter()
+        .flat_map(|block| block_to_cells(block, &opts))
+        .collect();
+
+    assert_eq!(
+        cells,
+        vec![Expr::normal(
+            Symbol::new("System`Cell"),
+            vec![
+                Expr::normal(
+                    Symbol::new("System`BoxData"),
+                    vec![Expr::normal(
+                        Symbol::new("System`ToExpression"),
+                        vec![
+                            Expr::string("Plot[x, {x, 0, 1}]"),
+                            Expr::from(Symbol::new("System`InputForm")),
+                            Expr::from(Symbol::new("System`MakeBoxes")),
+                        ],
+                    )],
+                ),
+                Expr::string("Input"),
+            ],
+        )]
+    );
+    assert_eq!(cell_style(&cells[0]), Some("Input"));
+    assert!(requires_kernel(&cells[0]));
+}
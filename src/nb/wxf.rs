@@ -0,0 +1,184 @@
+//! Serialization of expressions to the [Wolfram Exchange Format][wxf] (WXF), a
+//! binary format that other programs can read without a Wolfram Kernel.
+//!
+//! Only the token types needed to represent [`Expr`]s are written: machine
+//! integers, reals, strings, symbols, and functions.
+//!
+//! [wxf]: https://reference.wolfram.com/language/tutorial/WXFFormatDescription.html
+
+use wolfram_expr::{Expr, ExprKind};
+
+/// The header of an uncompressed WXF file, version 8.
+const HEADER: &[u8] = b"8:";
+
+/// Returns the WXF serialization of `expr`, including the header.
+pub(crate) fn expr_to_wxf(expr: &Expr) -> Vec<u8> {
+    let mut out = HEADER.to_vec();
+    write_expr(&mut out, expr);
+    out
+}
+
+fn write_expr(out: &mut Vec<u8>, expr: &Expr) {
+    match expr.kind() {
+        ExprKind::Integer(int) => write_integer(out, *int),
+        ExprKind::Real(real) => {
+            out.push(b'r');
+            out.extend_from_slice(&(**real).to_le_bytes());
+        },
+        ExprKind::String(string) => {
+            out.push(b'S');
+            write_bytes(out, string.as_bytes());
+        },
+        ExprKind::Symbol(symbol) => {
+            out.push(b's');
+            write_bytes(out, symbol.as_str().as_bytes());
+        },
+        ExprKind::Normal(normal) => {
+            out.push(b'f');
+            write_varint(out, normal.elements().len());
+            write_expr(out, normal.head());
+            for element in normal.elements() {
+                write_expr(out, element);
+            }
+        },
+    }
+}
+
+/// Write `int` using the smallest machine integer token that can represent it.
+fn write_integer(out: &mut Vec<u8>, int: i64) {
+    if let Ok(int) = i8::try_from(int) {
+        out.push(b'C');
+        out.extend_from_slice(&int.to_le_bytes());
+    } else if let Ok(int) = i16::try_from(int) {
+        out.push(b'j');
+        out.extend_from_slice(&int.to_le_bytes());
+    } else if let Ok(int) = i32::try_from(int) {
+        out.push(b'i');
+        out.extend_from_slice(&int.to_le_bytes());
+    } else {
+        out.push(b'L');
+        out.extend_from_slice(&int.to_le_bytes());
+    }
+}
+
+/// Write the length of `bytes`, followed by `bytes`.
+fn write_bytes(out: &mut Vec<u8>, bytes: &[u8]) {
+    write_varint(out, bytes.len());
+    out.extend_from_slice(bytes);
+}
+
+/// Write `value` as a variable-length integer: 7 bits per byte, least significant
+/// first, with the high bit set on every byte except the last.
+fn write_varint(out: &mut Vec<u8>, mut value: usize) {
+    while value >= 0x80 {
+        out.push((value & 0x7F) as u8 | 0x80);
+        value >>= 7;
+    }
+    out.push(value as u8);
+}
+
+//======================================
+// Tests
+//======================================
+
+/// Parse the WXF serialization `wxf` written by [`expr_to_wxf`].
+#[cfg(test)]
+fn wxf_to_expr(wxf: &[u8]) -> Result<Expr, String> {
+    use wolfram_expr::Symbol;
+
+    fn take<'a>(rest: &mut &'a [u8], len: usize) -> Result<&'a [u8], String> {
+        if rest.len() < len {
+            return Err("unexpected end of WXF data".into());
+        }
+        let (bytes, remaining) = rest.split_at(len);
+        *rest = remaining;
+        Ok(bytes)
+    }
+
+    fn varint(rest: &mut &[u8]) -> Result<usize, String> {
+        let mut value = 0;
+        for shift in (0..).step_by(7) {
+            let byte = take(rest, 1)?[0];
+            value |= usize::from(byte & 0x7F) << shift;
+            if byte & 0x80 == 0 {
+                break;
+            }
+        }
+        Ok(value)
+    }
+
+    fn string(rest: &mut &[u8]) -> Result<String, String> {
+        let len = varint(rest)?;
+        String::from_utf8(take(rest, len)?.to_vec()).map_err(|err| err.to_string())
+    }
+
+    fn expr(rest: &mut &[u8]) -> Result<Expr, String> {
+        let token = take(rest, 1)?[0];
+        let expr = match token {
+            b'C' => Expr::from(i64::from(i8::from_le_bytes(
+                take(rest, 1)?.try_into().unwrap(),
+            ))),
+            b'j' => Expr::from(i64::from(i16::from_le_bytes(
+                take(rest, 2)?.try_into().unwrap(),
+            ))),
+            b'i' => Expr::from(i64::from(i32::from_le_bytes(
+                take(rest, 4)?.try_into().unwrap(),
+            ))),
+            b'L' => Expr::from(i64::from_le_bytes(take(rest, 8)?.try_into().unwrap())),
+            b'r' => Expr::real(f64::from_le_bytes(take(rest, 8)?.try_into().unwrap())),
+            b'S' => Expr::string(string(rest)?),
+            b's' => Expr::from(Symbol::try_new(&string(rest)?).ok_or("invalid symbol")?),
+            b'f' => {
+                let len = varint(rest)?;
+                let head = expr(rest)?;
+                let elements = (0..len).map(|_| expr(rest)).collect::<Result<_, _>>()?;
+                Expr::normal(head, elements)
+            },
+            _ => return Err(format!("unsupported WXF token: {:?}", token as char)),
+        };
+        Ok(expr)
+    }
+
+    let mut rest = wxf.strip_prefix(HEADER).ok_or("missing WXF header")?;
+    let expr = expr(&mut rest)?;
+
+    if !rest.is_empty() {
+        return Err("unexpected data after WXF expression".into());
+    }
+
+    Ok(expr)
+}
+
+#[test]
+fn test_expr_to_wxf() {
+    use pretty_assertions::assert_eq;
+    use wolfram_expr::Symbol;
+
+    // Rule[x, 1]
+    assert_eq!(
+        expr_to_wxf(&Expr::rule(Symbol::new("Global`x"), Expr::from(1))),
+        b"8:f\x02s\x0bSystem`Rules\x08Global`xC\x01"
+    );
+
+    let long_string = "a".repeat(300);
+
+    let expr = Expr::normal(
+        Symbol::new("System`Notebook"),
+        vec![
+            Expr::list(vec![Expr::normal(
+                Symbol::new("System`Cell"),
+                vec![Expr::string("Héllo, \"world\"\n"), Expr::string("Text")],
+            )]),
+            Expr::rule(Symbol::new("System`PageWidth"), Expr::from(500)),
+            Expr::list(vec![
+                Expr::from(-1),
+                Expr::from(100_000),
+                Expr::from(i64::MIN),
+                Expr::real(0.25),
+                Expr::string(long_string),
+            ]),
+        ],
+    );
+
+    assert_eq!(wxf_to_expr(&expr_to_wxf(&expr)), Ok(expr));
+}
@@ -0,0 +1,260 @@
+//! User-defined colors for generated notebook cells, loaded from a TOML or JSON
+//! theme file.
+//!
+//! A theme file sets the colors of block quotes, code blocks, and inline code, using
+//! CSS color syntax:
+//!
+//! ```toml
+//! [block_quote]
+//! background = "#f4f4f8"
+//! frame_color = "slateblue"
+//!
+//! [code_block]
+//! background = "#fafafa"
+//!
+//! [inline_code]
+//! background = "#eeeeee"
+//! ```
+//!
+//! The equivalent JSON theme is:
+//!
+//! ```json
+//! {
+//!     "block_quote": { "background": "#f4f4f8", "frame_color": "slateblue" },
+//!     "code_block": { "background": "#fafafa" },
+//!     "inline_code": { "background": "#eeeeee" }
+//! }
+//! ```
+//!
+//! Colors that are not set keep their built-in default. Fonts and the colors of other
+//! content are not themeable.
+
+use std::path::Path;
+
+use crate::ast::Color;
+
+/// Colors used when generating notebook cells. Colors that are `None` use the
+/// built-in default.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Theme {
+    pub block_quote_background: Option<Color>,
+    pub block_quote_frame_color: Option<Color>,
+    /// If `None`, code blocks use the background of their cell style.
+    pub code_block_background: Option<Color>,
+    pub inline_code_background: Option<Color>,
+}
+
+impl Theme {
+    /// Load a theme from a `.json` or `.toml` file.
+    pub fn load(path: &Path) -> Result<Theme, String> {
+        let contents = std::fs::read_to_string(path).map_err(|err| {
+            format!("unable to read theme file {}: {err}", path.display())
+        })?;
+
+        let entries = match path.extension().and_then(|ext| ext.to_str()) {
+            Some("json") => parse_json(&contents),
+            _ => parse_toml(&contents),
+        };
+
+        entries
+            .and_then(Theme::from_entries)
+            .map_err(|err| format!("invalid theme file {}: {err}", path.display()))
+    }
+
+    /// Construct a theme from `(section, key, value)` entries.
+    fn from_entries(entries: Vec<(String, String, String)>) -> Result<Theme, String> {
+        let mut theme = Theme::default();
+
+        for (section, key, value) in entries {
+            let field = match (section.as_str(), key.as_str()) {
+                ("block_quote", "background") => &mut theme.block_quote_background,
+                ("block_quote", "frame_color") => &mut theme.block_quote_frame_color,
+                ("code_block", "background") => &mut theme.code_block_background,
+                ("inline_code", "background") => &mut theme.inline_code_background,
+                _ => return Err(format!("unknown theme setting: {section}.{key}")),
+            };
+
+            let color = Color::from_css(&value)
+                .ok_or_else(|| format!("invalid color for {section}.{key}: {value:?}"))?;
+
+            *field = Some(color);
+        }
+
+        Ok(theme)
+    }
+}
+
+/// Parse the `[section]` headers and `key = "value"` pairs of a TOML file.
+fn parse_toml(contents: &str) -> Result<Vec<(String, String, String)>, String> {
+    let mut entries = Vec::new();
+    let mut section = String::new();
+
+    for (index, line) in contents.lines().enumerate() {
+        let line = line.trim();
+
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        if let Some(name) = line.strip_prefix('[').and_then(|l| l.strip_suffix(']')) {
+            section = name.trim().to_owned();
+            continue;
+        }
+
+        let (key, value) = line
+            .split_once('=')
+            .ok_or_else(|| format!("line {}: expected `key = \"value\"`", index + 1))?;
+
+        let value = value
+            .trim()
+            .strip_prefix('"')
+            .and_then(|v| v.strip_suffix('"'))
+            .ok_or_else(|| {
+                format!("line {}: expected a quoted string value", index + 1)
+            })?;
+
+        entries.push((section.clone(), key.trim().to_owned(), value.to_owned()));
+    }
+
+    Ok(entries)
+}
+
+/// Parse a JSON object of objects with string values.
+fn parse_json(contents: &str) -> Result<Vec<(String, String, String)>, String> {
+    let mut parser = JsonParser {
+        rest: contents.trim_start(),
+    };
+
+    let mut entries = Vec::new();
+
+    parser.expect('{')?;
+    while !parser.eat('}') {
+        let section = parser.string()?;
+        parser.expect(':')?;
+
+        parser.expect('{')?;
+        while !parser.eat('}') {
+            let key = parser.string()?;
+            parser.expect(':')?;
+            let value = parser.string()?;
+
+            entries.push((section.clone(), key, value));
+
+            if !parser.eat(',') {
+                parser.expect('}')?;
+                break;
+            }
+        }
+
+        if !parser.eat(',') {
+            parser.expect('}')?;
+            break;
+        }
+    }
+
+    if !parser.rest.is_empty() {
+        return Err("unexpected content after JSON object".into());
+    }
+
+    Ok(entries)
+}
+
+struct JsonParser<'a> {
+    rest: &'a str,
+}
+
+impl JsonParser<'_> {
+    /// Consume `c`, if it is the next non-whitespace character.
+    fn eat(&mut self, c: char) -> bool {
+        match self.rest.strip_prefix(c) {
+            Some(rest) => {
+                self.rest = rest.trim_start();
+                true
+            },
+            None => false,
+        }
+    }
+
+    fn expect(&mut self, c: char) -> Result<(), String> {
+        if self.eat(c) {
+            Ok(())
+        } else {
+            Err(format!("expected `{c}` in JSON"))
+        }
+    }
+
+    fn string(&mut self) -> Result<String, String> {
+        let rest = self
+            .rest
+            .strip_prefix('"')
+            .ok_or_else(|| "expected a JSON string".to_owned())?;
+
+        let mut string = String::new();
+        let mut chars = rest.char_indices();
+
+        while let Some((offset, c)) = chars.next() {
+            match c {
+                '"' => {
+                    self.rest = rest[offset + 1..].trim_start();
+                    return Ok(string);
+                },
+                '\\' => match chars.next() {
+                    Some((_, c @ ('"' | '\\' | '/'))) => string.push(c),
+                    Some((_, 'n')) => string.push('\n'),
+                    Some((_, 't')) => string.push('\t'),
+                    _ => return Err("unsupported escape sequence in JSON string".into()),
+                },
+                c => string.push(c),
+            }
+        }
+
+        Err("unterminated JSON string".into())
+    }
+}
+
+//======================================
+// Tests
+//======================================
+
+#[test]
+fn test_parse_theme() {
+    use pretty_assertions::assert_eq;
+
+    let expected = Theme {
+        block_quote_background: Some(Color {
+            red: 255,
+            green: 0,
+            blue: 0,
+        }),
+        block_quote_frame_color: None,
+        code_block_background: Some(Color {
+            red: 0xfa,
+            green: 0xfa,
+            blue: 0xfa,
+        }),
+        inline_code_background: Some(Color {
+            red: 0x11,
+            green: 0x22,
+            blue: 0x33,
+        }),
+    };
+
+    let toml = "# Comment\n[block_quote]\nbackground = \"red\"\n\n[code_block]\nbackground = \"#fafafa\"\n\n[inline_code]\nbackground = \"#123\"\n";
+    assert_eq!(
+        parse_toml(toml).and_then(Theme::from_entries),
+        Ok(expected.clone())
+    );
+
+    let json = r##"{"block_quote": {"background": "red"}, "code_block": {"background": "#fafafa"}, "inline_code": {"background": "#123"}}"##;
+    assert_eq!(parse_json(json).and_then(Theme::from_entries), Ok(expected));
+
+    assert_eq!(
+        parse_toml("[block_quote]\nbackground = \"#ggg\"").and_then(Theme::from_entries),
+        Err("invalid color for block_quote.background: \"#ggg\"".into())
+    );
+    assert!(parse_toml("[table]\nstripe = \"red\"")
+        .and_then(Theme::from_entries)
+        .is_err());
+    assert!(parse_toml("[block_quote]\nbackground = red").is_err());
+    assert!(parse_json(r#"{"block_quote": {"background": "red"}"#).is_err());
+}
@@ -0,0 +1,93 @@
+//! Abort the conversion if the Wolfram Kernel stops responding, instead of waiting
+//! for it forever.
+
+use std::{
+    sync::mpsc::{self, RecvTimeoutError},
+    thread,
+    time::Duration,
+};
+
+/// Calls a function on a background thread if [`Watchdog::reset()`] isn't called
+/// at least once every `timeout`.
+///
+/// The background thread exits when the `Watchdog` is dropped.
+pub(crate) struct Watchdog {
+    sender: Option<mpsc::Sender<()>>,
+}
+
+impl Watchdog {
+    /// Start a watchdog that calls `on_timeout` if it isn't reset within `timeout`.
+    ///
+    /// If `timeout` is `None`, the watchdog never times out.
+    pub(crate) fn start(
+        timeout: Option<Duration>,
+        on_timeout: impl FnOnce() + Send + 'static,
+    ) -> Watchdog {
+        let timeout = match timeout {
+            Some(timeout) => timeout,
+            None => return Watchdog { sender: None },
+        };
+
+        let (sender, receiver) = mpsc::channel();
+
+        thread::spawn(move || loop {
+            match receiver.recv_timeout(timeout) {
+                Ok(()) => (),
+                Err(RecvTimeoutError::Timeout) => {
+                    on_timeout();
+                    return;
+                },
+                Err(RecvTimeoutError::Disconnected) => return,
+            }
+        });
+
+        Watchdog {
+            sender: Some(sender),
+        }
+    }
+
+    /// Restart the timeout, after the Kernel made progress.
+    pub(crate) fn reset(&self) {
+        if let Some(sender) = &self.sender {
+            // The background thread has exited if the watchdog already timed out.
+            let _ = sender.send(());
+        }
+    }
+}
+
+//======================================
+// Tests
+//======================================
+
+#[test]
+fn test_watchdog() {
+    use std::time::Instant;
+
+    let (timed_out, receiver) = mpsc::channel();
+
+    // A stalled Kernel, which never makes progress.
+    let _watchdog = Watchdog::start(Some(Duration::from_millis(50)), move || {
+        timed_out.send(()).unwrap()
+    });
+
+    assert_eq!(receiver.recv_timeout(Duration::from_secs(5)), Ok(()));
+
+    // A Kernel that keeps making progress.
+    let (timed_out, receiver) = mpsc::channel();
+
+    let watchdog = Watchdog::start(Some(Duration::from_millis(500)), move || {
+        timed_out.send(()).unwrap()
+    });
+
+    let start = Instant::now();
+    while start.elapsed() < Duration::from_millis(1000) {
+        watchdog.reset();
+        thread::sleep(Duration::from_millis(10));
+    }
+    drop(watchdog);
+
+    assert_eq!(
+        receiver.recv_timeout(Duration::from_millis(100)),
+        Err(RecvTimeoutError::Disconnected)
+    );
+}
@@ -0,0 +1,48 @@
+use std::{
+    path::Path,
+    process::{Command, Output},
+};
+
+fn md2nb(args: &[&Path]) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_md2nb"))
+        .arg("--no-kernel")
+        .args(args)
+        .output()
+        .unwrap()
+}
+
+#[test]
+fn test_force_overwrite() {
+    let dir =
+        std::env::temp_dir().join(format!("md2nb-cli-force-test-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+
+    let input = dir.join("input.md");
+    let output = dir.join("output.nb");
+
+    std::fs::write(&input, "# First").unwrap();
+    let first = md2nb(&[&input, &output]);
+    assert!(first.status.success(), "{first:?}");
+
+    let first_notebook = std::fs::read_to_string(&output).unwrap();
+    assert!(first_notebook.contains("First"));
+
+    // Without `-f`, the existing output file is left unchanged.
+    std::fs::write(&input, "# Second").unwrap();
+    let second = md2nb(&[&input, &output]);
+    assert!(!second.status.success());
+    assert!(
+        String::from_utf8_lossy(&second.stderr).contains("output file already exists")
+    );
+    assert_eq!(std::fs::read_to_string(&output).unwrap(), first_notebook);
+
+    // With `-f`, the output file is replaced.
+    let forced = md2nb(&[Path::new("-f"), &input, &output]);
+    assert!(forced.status.success(), "{forced:?}");
+
+    let forced_notebook = std::fs::read_to_string(&output).unwrap();
+    assert!(forced_notebook.contains("Second"));
+    assert!(!forced_notebook.contains("First"));
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}